@@ -0,0 +1,15 @@
+-- User-defined labels on entries
+
+-- Labels are per user: each user has their own set, and applies them to
+-- entries independently of other users. A label exists as long as some entry
+-- has it.
+CREATE TABLE entry_labels (
+	user_id TEXT NOT NULL,
+	entry_id TEXT NOT NULL,
+	label TEXT NOT NULL,
+	PRIMARY KEY (user_id, entry_id, label),
+	FOREIGN KEY (user_id) REFERENCES users(id),
+	FOREIGN KEY (entry_id) REFERENCES entries(id)
+) STRICT;
+
+CREATE INDEX entry_labels_user_label ON entry_labels (user_id, label);
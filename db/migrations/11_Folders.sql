@@ -0,0 +1,16 @@
+-- Folders for subscriptions
+
+-- A user can only be subscribed to a feed once, so subscriptions can carry
+-- per-user settings like folders. Drop any duplicates first.
+DELETE FROM subscriptions
+	WHERE rowid NOT IN (
+		SELECT MIN(rowid)
+		FROM subscriptions
+		GROUP BY user_id, feed_id
+	);
+
+CREATE UNIQUE INDEX subscriptions_user_feed ON subscriptions (user_id, feed_id);
+
+-- The folder the user has filed this subscription in, if any. Like labels,
+-- folders are just names; a folder exists as long as some feed is in it.
+ALTER TABLE subscriptions ADD COLUMN folder TEXT NULL;
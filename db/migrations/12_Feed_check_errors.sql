@@ -0,0 +1,9 @@
+-- Errors from feed checks
+
+-- A check that failed to fetch or parse the feed records why, so subscribers
+-- can see which of their feeds are broken. Successful checks have no error.
+ALTER TABLE feed_checks ADD COLUMN error TEXT NULL;
+
+-- The subscriptions page aggregates checks and entries per feed
+CREATE INDEX feed_checks_feed ON feed_checks (feed_id, id);
+CREATE INDEX entries_feed_date ON entries (feed_id, article_date);
@@ -0,0 +1,23 @@
+-- Rules for filtering incoming entries
+
+-- Rules can match on authors and categories, so entries need to keep them.
+-- Categories are newline-separated.
+ALTER TABLE entries ADD COLUMN author TEXT NULL;
+ALTER TABLE entries ADD COLUMN categories TEXT NULL;
+
+-- A rule applies an action to a user's entries whose `field` matches
+-- `pattern`, either in one feed or (with a NULL `feed_id`) in all of them.
+-- `label` is the label to apply, for the "label" action.
+CREATE TABLE rules (
+	id TEXT NOT NULL PRIMARY KEY,
+	user_id TEXT NOT NULL,
+	feed_id TEXT NULL,
+	field TEXT NOT NULL,
+	match_type TEXT NOT NULL,
+	pattern TEXT NOT NULL,
+	action TEXT NOT NULL,
+	label TEXT NULL,
+	FOREIGN KEY (user_id) REFERENCES users(id),
+	FOREIGN KEY (feed_id) REFERENCES feeds(id)
+);
+CREATE INDEX rules_user ON rules (user_id);
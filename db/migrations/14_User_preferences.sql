@@ -0,0 +1,11 @@
+-- Per-user defaults for entry listings. Users without a row get the defaults
+-- in crate::persistence::model::UserPreferences.
+
+CREATE TABLE user_preferences (
+	user_id TEXT NOT NULL PRIMARY KEY,
+	unread_only INTEGER NOT NULL,
+	oldest_first INTEGER NOT NULL,
+	entry_sort TEXT NOT NULL,
+	page_size INTEGER NOT NULL,
+	FOREIGN KEY (user_id) REFERENCES users(id)
+);
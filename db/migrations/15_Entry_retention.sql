@@ -0,0 +1,17 @@
+-- Entry retention
+
+-- How many days to keep read entries from this feed, overriding the configured
+-- default. 0 keeps them forever.
+ALTER TABLE feeds ADD COLUMN retention_days INTEGER NULL;
+
+-- The internal IDs of pruned entries, so they aren't added again the next time
+-- their feed is checked
+CREATE TABLE pruned_entries (
+	feed_id TEXT NOT NULL,
+	internal_id TEXT NOT NULL,
+	PRIMARY KEY (feed_id, internal_id),
+	FOREIGN KEY (feed_id) REFERENCES feeds(id)
+);
+
+-- Pruning checks every user's settings for each old entry
+CREATE INDEX user_entry_settings_entry ON user_entry_settings (entry_id);
@@ -0,0 +1,10 @@
+-- Cross-feed duplicate entries
+
+-- An entry which duplicates one from another feed refers to the first entry of
+-- its group. Entries are grouped by COALESCE(duplicate_of, id). This isn't a
+-- foreign key, since the first entry may be pruned before its duplicates.
+ALTER TABLE entries ADD COLUMN duplicate_of TEXT NULL;
+CREATE INDEX entries_duplicate_group ON entries (COALESCE(duplicate_of, id));
+
+-- New entries are compared against other feeds' recent entries
+CREATE INDEX entries_article_date ON entries (article_date);
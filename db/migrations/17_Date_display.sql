@@ -0,0 +1,8 @@
+-- Per-user date display preferences
+
+-- An IANA timezone name
+ALTER TABLE user_preferences ADD COLUMN timezone TEXT NOT NULL DEFAULT 'UTC';
+ALTER TABLE user_preferences ADD COLUMN date_format TEXT NOT NULL DEFAULT 'iso';
+ALTER TABLE user_preferences ADD COLUMN date_locale TEXT NOT NULL DEFAULT 'en-US';
+-- Entries newer than this many days are shown with times
+ALTER TABLE user_preferences ADD COLUMN recent_days INTEGER NOT NULL DEFAULT 2;
@@ -0,0 +1,3 @@
+-- Per-user theme preference
+
+ALTER TABLE user_preferences ADD COLUMN theme TEXT NOT NULL DEFAULT 'dark';
@@ -0,0 +1,5 @@
+-- User disabling
+
+-- Disabled users can't log in, but keep their subscriptions and entry state in
+-- case they're enabled again
+ALTER TABLE users ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE;
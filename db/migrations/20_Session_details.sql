@@ -0,0 +1,11 @@
+-- Session details
+
+-- When each session was logged in and last used, and the address and browser
+-- it was last used from, so users can recognize their sessions. Sessions from
+-- before this have none of these until they're next used.
+ALTER TABLE sessions ADD COLUMN created INTEGER NULL;
+ALTER TABLE sessions ADD COLUMN last_used INTEGER NULL;
+ALTER TABLE sessions ADD COLUMN ip TEXT NULL;
+ALTER TABLE sessions ADD COLUMN user_agent TEXT NULL;
+
+CREATE INDEX sessions_user ON sessions (user_id);
@@ -0,0 +1,19 @@
+-- Two-factor authentication
+
+-- Users with a row here must give a TOTP code (or a recovery code) after their
+-- password to log in. `last_step` is the time step of the last code accepted,
+-- so a code can't be used twice.
+CREATE TABLE user_totp (
+	user_id TEXT NOT NULL PRIMARY KEY,
+	secret TEXT NOT NULL,
+	last_step INTEGER NOT NULL,
+	FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+-- One-time codes for logging in without the TOTP device, hashed like passwords
+CREATE TABLE recovery_codes (
+	user_id TEXT NOT NULL,
+	code_hash TEXT NOT NULL,
+	FOREIGN KEY (user_id) REFERENCES users(id)
+);
+CREATE INDEX recovery_codes_user ON recovery_codes (user_id);
@@ -0,0 +1,14 @@
+-- Invitations
+
+-- Single-use invitations for new users to register themselves. Only a hash of
+-- each invitation's token is kept, so its link can't be recovered from the
+-- database.
+CREATE TABLE invitations (
+	id TEXT NOT NULL PRIMARY KEY,
+	token_hash TEXT NOT NULL UNIQUE,
+	user_type TEXT NOT NULL,
+	created_by TEXT NOT NULL,
+	created INTEGER NOT NULL,
+	expiration INTEGER NULL,
+	FOREIGN KEY (created_by) REFERENCES users(id)
+);
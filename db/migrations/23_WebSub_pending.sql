@@ -0,0 +1,4 @@
+-- When we last asked the hub to (re)subscribe, if it hasn't verified or denied
+-- that request yet. Verifications and denials are only accepted while a
+-- request is pending.
+ALTER TABLE websub_subscriptions ADD COLUMN pending_since INT NULL;
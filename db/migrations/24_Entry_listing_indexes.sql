@@ -0,0 +1,6 @@
+-- Entry listings page through entries in (check_id, article_date, id) or
+-- (article_date, check_id, id) order, seeking from the cursor of the page
+-- before. These make each page an index range scan rather than a sort of every
+-- entry.
+CREATE INDEX entries_check_listing ON entries (check_id, article_date, id);
+CREATE INDEX entries_date_listing ON entries (article_date, check_id, id);
@@ -0,0 +1,2 @@
+-- Labeled entries are kept when pruning, which looks labels up by entry
+CREATE INDEX entry_labels_entry ON entry_labels (entry_id);
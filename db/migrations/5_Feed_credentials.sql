@@ -0,0 +1,6 @@
+-- Credentials for private feeds
+
+-- Encrypted credentials (HTTP headers) to send when fetching the feed. Feeds
+-- with credentials are private to the subscription that created them, so they
+-- are never looked up by URL and never shared between users.
+ALTER TABLE feeds ADD COLUMN credentials TEXT NULL;
@@ -0,0 +1,14 @@
+-- Feeds scraped from HTML pages
+
+-- CSS selectors for extracting entries from a page. A feed with selectors is
+-- scraped rather than parsed as RSS/Atom. Like feeds with credentials, scraped
+-- feeds are never looked up by URL, since two sets of selectors for the same
+-- page are different feeds.
+CREATE TABLE scrape_selectors (
+	feed_id TEXT NOT NULL PRIMARY KEY,
+	item_selector TEXT NOT NULL,
+	title_selector TEXT NULL,
+	link_selector TEXT NULL,
+	date_selector TEXT NULL,
+	FOREIGN KEY (feed_id) REFERENCES feeds(id)
+);
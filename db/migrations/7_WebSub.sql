@@ -0,0 +1,12 @@
+-- WebSub (PubSubHubbub) push subscriptions
+
+-- One subscription per feed, to the hub the feed advertises. `lease_expiration`
+-- is NULL until the hub has verified our intent to subscribe.
+CREATE TABLE websub_subscriptions (
+	feed_id TEXT NOT NULL PRIMARY KEY,
+	hub_url TEXT NOT NULL,
+	topic_url TEXT NOT NULL,
+	secret TEXT NOT NULL,
+	lease_expiration INT NULL,
+	FOREIGN KEY (feed_id) REFERENCES feeds(id)
+) STRICT;
@@ -0,0 +1,10 @@
+-- Full-text search over entries. Only titles are indexed for now; entry
+-- content belongs here too once it's stored.
+CREATE VIRTUAL TABLE entry_search USING fts5(
+	entry_id UNINDEXED,
+	title,
+	tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO entry_search (entry_id, title)
+	SELECT id, title FROM entries;
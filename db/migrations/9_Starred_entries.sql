@@ -0,0 +1,5 @@
+-- Starred (saved for later) entries
+
+-- When the user starred the entry, or NULL if it isn't starred. Starred
+-- entries are kept regardless of any retention policy.
+ALTER TABLE user_entry_settings ADD COLUMN starred INT NULL;
@@ -2,6 +2,7 @@ use clap::{ Args, Parser, Subcommand};
 use crate::model::UserType;
 use merge::Merge;
 use serde::Deserialize;
+use std::net::IpAddr;
 use std::num::ParseIntError;
 use std::time::Duration;
 
@@ -34,6 +35,13 @@ pub struct Config {
 	#[arg(hide = true)]
 	pub pepper: Option<String>,
 
+	/// Key material for encrypting stored feed credentials
+	///
+	/// (Not exposed on the CLI, for the same reasons as `pepper`.) If unset,
+	/// feeds requiring credentials can't be added.
+	#[arg(hide = true)]
+	pub credential_key: Option<String>,
+
 	/// Duration between feed checks, in seconds
 	#[arg(
 		short,
@@ -47,15 +55,56 @@ pub struct Config {
 
 	/// Disable logins.
 	///
-	/// This option will go away once more robust rate-limiting of the login
-	/// endpoint is available. For now, it's a quick toggle to prevent
-	/// credential stuffing or resource exhaustion attacks.
+	/// Failed logins are rate limited per client and per user (see the rate
+	/// limiting options), so this shouldn't be needed; it's left as an
+	/// emergency switch.
 	#[arg(long)]
 	pub disable_logins: Option<bool>,
 
+	/// Public URL of this Russet instance
+	///
+	/// This is the URL at which Russet can be reached from the outside world
+	/// (e.g. `https://russet.example.com/`). If set, Russet subscribes to
+	/// WebSub hubs advertised by feeds (over HTTPS only) and accepts pushed
+	/// updates.
+	#[arg(long, value_name = "URL")]
+	pub public_url: Option<String>,
+
+	/// Allow feeds from local files and commands.
+	///
+	/// Sysops can subscribe to `file:`, `exec:`, and `filter:` feeds, which
+	/// read files and run commands on the server with Russet's privileges.
+	#[arg(long)]
+	pub local_feed_sources: Option<bool>,
+
+	/// Days to keep read entries for, unless their feed says otherwise
+	///
+	/// Entries older than this which every subscriber has read (and none has
+	/// starred or labeled) are pruned. 0 keeps entries forever.
+	#[arg(long, value_name = "DAYS")]
+	pub entry_retention_days: Option<u32>,
+
+	/// Only send cookies over HTTPS.
+	///
+	/// Set this when Russet is served over HTTPS (e.g. behind a TLS-terminating
+	/// reverse proxy), so session cookies are never sent in the clear.
+	#[arg(long)]
+	pub secure_cookies: Option<bool>,
+
+	/// Addresses of reverse proxies to trust the `X-Forwarded-For` header from
+	///
+	/// Requests from these addresses are taken to be from the client the
+	/// header says they're forwarded for, for rate limiting and session
+	/// details. Comma-separated on the command line.
+	#[arg(long, value_name = "ADDRESSES", value_delimiter = ',')]
+	pub trusted_proxies: Option<Vec<IpAddr>>,
+
 	#[command(flatten)]
 	pub rate_limiting: RateLimitingConfig,
 
+	#[command(flatten)]
+	pub proxy_auth: ProxyAuthConfig,
+
 }
 impl Default for Config {
 	fn default() -> Self {
@@ -65,9 +114,16 @@ impl Default for Config {
 			db_file: Some("/tmp/russet-db.sqlite".to_string()),
 			listen_address: Some("127.0.0.1:9892".to_string()),
 			pepper: Some("IzvoEPMQIi82NSXTz7cZ".to_string()),
+			credential_key: None,
 			feed_check_interval: Some(Duration::from_secs(3_600)),
 			disable_logins: Some(false),
+			local_feed_sources: Some(false),
+			public_url: None,
+			entry_retention_days: Some(0),
+			secure_cookies: Some(false),
+			trusted_proxies: Some(Vec::new()),
 			rate_limiting: RateLimitingConfig::default(),
+			proxy_auth: ProxyAuthConfig::default(),
 		}
 	}
 }
@@ -78,8 +134,15 @@ impl std::fmt::Debug for Config {
 			.field("config_file", &self.config_file)
 			.field("listen_address", &self.listen_address)
 			.field("pepper", &"<redacted>")
+			.field("credential_key", &self.credential_key.as_ref().map(|_| "<redacted>"))
 			.field("feed_check_interval", &self.feed_check_interval.map(|duration| duration.as_secs()))
+			.field("local_feed_sources", &self.local_feed_sources)
+			.field("public_url", &self.public_url)
+			.field("entry_retention_days", &self.entry_retention_days)
+			.field("secure_cookies", &self.secure_cookies)
+			.field("trusted_proxies", &self.trusted_proxies)
 			.field("rate_limiting", &self.rate_limiting)
+			.field("proxy_auth", &self.proxy_auth)
 			.finish()
 	}
 }
@@ -112,6 +175,12 @@ pub enum Command {
 		user_name: String,
 	},
 
+	/// Turn off two-factor authentication for a user who's lost their
+	/// authenticator and recovery codes
+	ResetTwoFactor {
+		user_name: String,
+	},
+
 	/// Add a feed by URL
 	AddFeed {
 		url: String,
@@ -121,6 +190,13 @@ pub enum Command {
 	RemoveFeed {
 		url: String,
 	},
+
+	/// Prune old, read entries now, as the server does daily
+	Prune {
+		/// Report what would be pruned without pruning it
+		#[arg(long)]
+		dry_run: bool,
+	},
 }
 
 #[derive(Args, Debug, Deserialize, Merge)]
@@ -136,12 +212,79 @@ pub struct RateLimitingConfig {
 	/// available to Russet.
 	#[arg(short = 'o', long, value_name = "CONNECTIONS")]
 	pub login_concurrent_limit: Option<u32>,
+
+	/// Failed logins allowed from one IP address in a burst.
+	#[arg(long, value_name = "ATTEMPTS")]
+	pub login_attempts_per_ip: Option<u32>,
+
+	/// Failed logins allowed for one user name in a burst.
+	#[arg(long, value_name = "ATTEMPTS")]
+	pub login_attempts_per_user: Option<u32>,
+
+	/// Seconds it takes to earn back one failed login attempt.
+	#[arg(
+		long,
+		value_name = "SECONDS",
+		value_parser = |arg: &str| Ok::<Duration, ParseIntError>(
+			Duration::from_secs(arg.parse()?)
+		)
+	)]
+	pub login_attempt_refill: Option<Duration>,
+
+	/// Consecutive failed logins which lock a user name. 0 never locks.
+	#[arg(long, value_name = "ATTEMPTS")]
+	pub login_lockout_threshold: Option<u32>,
+
+	/// Seconds a locked user name stays locked.
+	#[arg(
+		long,
+		value_name = "SECONDS",
+		value_parser = |arg: &str| Ok::<Duration, ParseIntError>(
+			Duration::from_secs(arg.parse()?)
+		)
+	)]
+	pub login_lockout_duration: Option<Duration>,
 }
 impl Default for RateLimitingConfig {
 	fn default() -> Self {
 		RateLimitingConfig {
 			global_concurrent_limit: Some(1024),
 			login_concurrent_limit: Some(4),
+			login_attempts_per_ip: Some(20),
+			login_attempts_per_user: Some(10),
+			login_attempt_refill: Some(Duration::from_secs(60)),
+			login_lockout_threshold: Some(0),
+			login_lockout_duration: Some(Duration::from_secs(900)),
+		}
+	}
+}
+
+#[derive(Args, Debug, Deserialize, Merge)]
+pub struct ProxyAuthConfig {
+	/// Header a reverse proxy names the authenticated user in, e.g. `Remote-User`.
+	///
+	/// If set, users are authenticated by this header on requests from
+	/// `proxy_auth_addresses` instead of by logging in with a password, and the
+	/// login page is disabled.
+	#[arg(long, value_name = "HEADER")]
+	pub proxy_auth_header: Option<String>,
+
+	/// Addresses of the reverse proxies to trust `proxy_auth_header` from.
+	///
+	/// Comma-separated on the command line.
+	#[arg(long, value_name = "ADDRESSES", value_delimiter = ',')]
+	pub proxy_auth_addresses: Option<Vec<IpAddr>>,
+
+	/// Add users named in `proxy_auth_header` who don't exist yet, as members.
+	#[arg(long)]
+	pub proxy_auth_provision_users: Option<bool>,
+}
+impl Default for ProxyAuthConfig {
+	fn default() -> Self {
+		ProxyAuthConfig {
+			proxy_auth_header: None,
+			proxy_auth_addresses: Some(Vec::new()),
+			proxy_auth_provision_users: Some(false),
 		}
 	}
 }
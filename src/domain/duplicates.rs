@@ -0,0 +1,148 @@
+//! Cross-feed duplicate entries. When several feeds carry the same article
+//! (say, an aggregator and the original blog), their entries are grouped, and
+//! listings show the group as one entry, "also in" the other feeds.
+
+use crate::domain::model::{ DuplicateFeed, EntryPage };
+use crate::domain::RussetDomainService;
+use crate::model::{ EntryId, FeedId, Timestamp, UserId };
+use crate::persistence::model::Entry;
+use crate::persistence::RussetEntryPersistenceLayer;
+use crate::Result;
+use reqwest::Url;
+use std::collections::{ BTreeSet, HashMap };
+use std::time::{ Duration, UNIX_EPOCH };
+use ulid::Ulid;
+
+/// How far before a new entry's date to look for entries it duplicates
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(7 * 86_400);
+
+/// How far back from now to look for duplicates at all. New entries dated
+/// before this (say, a newly added feed's archive) aren't matched, so they
+/// can't widen the search to every entry in the database.
+const DUPLICATE_LOOKBACK: Duration = Duration::from_secs(30 * 86_400);
+
+/// Titles with fewer distinct words than this are too generic to match on
+const MIN_TITLE_WORDS: usize = 4;
+
+/// The share of two titles' distinct words they must have in common to match
+const TITLE_SIMILARITY: f64 = 0.8;
+
+/// Query parameters which only track where a link was followed from
+const TRACKING_PARAMETERS: &[&str] = &[
+	"fbclid", "gclid", "dclid", "msclkid", "yclid", "igshid",
+	"mc_cid", "mc_eid", "_hsenc", "_hsmi", "ref", "ref_src",
+];
+
+impl <Persistence> RussetDomainService<Persistence>
+where Persistence: RussetEntryPersistenceLayer {
+	/// Mark which of the given new entries for the given feed duplicate recent
+	/// entries from other feeds, by canonical URL or, failing that, by title
+	pub(super) async fn find_duplicates(&self, feed_id: &FeedId, entries: &mut [Entry]) -> Result<()> {
+		let cutoff = Timestamp::now().0.checked_sub(DUPLICATE_LOOKBACK).unwrap_or(UNIX_EPOCH);
+		let Some(earliest) = entries.iter()
+			.map(|entry| entry.article_date.0)
+			.filter(|date| *date >= cutoff)
+			.min()
+		else {
+			return Ok(())
+		};
+		let since = Timestamp::new(earliest.checked_sub(DUPLICATE_WINDOW).unwrap_or(UNIX_EPOCH).max(cutoff));
+		let candidates = self.persistence
+			.get_recent_entries_from_other_feeds(feed_id, &since)
+			.await
+			.into_iter()
+			.map(|entry| entry.map(Candidate::new))
+			.collect::<Result<Vec<Candidate>>>()?;
+		if candidates.is_empty() {
+			return Ok(())
+		}
+		for entry in entries.iter_mut().filter(|entry| entry.article_date.0 >= cutoff) {
+			let url = entry.url.as_ref().map(canonical_url);
+			let words = title_words(&entry.title);
+			// Candidates are in ID order, so the earliest match wins
+			let duplicate_of = candidates.iter()
+				.find(|candidate| url.is_some() && candidate.url == url)
+				.or_else(|| candidates.iter().find(|candidate| similar_titles(&candidate.words, &words)))
+				.map(|candidate| candidate.group);
+			entry.duplicate_of = duplicate_of;
+		}
+		Ok(())
+	}
+
+	/// Fill in which of the given user's other feeds have duplicates of each
+	/// entry on the given page
+	pub(super) async fn add_duplicate_feeds(&self, user_id: &UserId, mut page: EntryPage) -> Result<EntryPage> {
+		let entry_ids = page.entries
+			.iter()
+			.filter_map(|entry| entry.as_ref().ok().map(|entry| entry.id))
+			.collect::<Vec<EntryId>>();
+		if entry_ids.is_empty() {
+			return Ok(page)
+		}
+		let mut duplicate_feeds = HashMap::<Ulid, Vec<DuplicateFeed>>::new();
+		for result in self.persistence.get_duplicate_feeds(user_id, &entry_ids).await {
+			let (entry_id, id, title) = result?;
+			duplicate_feeds.entry(entry_id.0).or_default().push(DuplicateFeed { id, title });
+		}
+		for entry in page.entries.iter_mut().filter_map(|entry| entry.as_mut().ok()) {
+			entry.also_in = duplicate_feeds.remove(&entry.id.0).unwrap_or_default();
+		}
+		Ok(page)
+	}
+}
+
+/// An existing entry a new one might duplicate
+struct Candidate {
+	/// The first entry of the candidate's group
+	group: EntryId,
+	url: Option<String>,
+	words: BTreeSet<String>,
+}
+impl Candidate {
+	fn new(entry: Entry) -> Candidate {
+		Candidate {
+			group: entry.duplicate_of.unwrap_or(entry.id),
+			url: entry.url.as_ref().map(canonical_url),
+			words: title_words(&entry.title),
+		}
+	}
+}
+
+/// Reduce a URL to the parts which identify the article it links to: no
+/// scheme, "www.", fragment, trailing slash, or tracking parameters, and the
+/// remaining query parameters sorted
+fn canonical_url(url: &Url) -> String {
+	let host = url.host_str().unwrap_or_default();
+	let host = host.strip_prefix("www.").unwrap_or(host);
+	let port = url.port().map(|port| format!(":{port}")).unwrap_or_default();
+	let path = url.path().trim_end_matches('/');
+	let mut query = url.query_pairs()
+		.filter(|(key, _)| !key.starts_with("utm_") && !TRACKING_PARAMETERS.contains(&key.as_ref()))
+		.map(|(key, value)| format!("{key}={value}"))
+		.collect::<Vec<String>>();
+	query.sort();
+	if query.is_empty() {
+		format!("{host}{port}{path}")
+	} else {
+		format!("{host}{port}{path}?{}", query.join("&"))
+	}
+}
+
+/// The distinct words of a title, lowercased, ignoring punctuation
+fn title_words(title: &str) -> BTreeSet<String> {
+	title.split(|c: char| !c.is_alphanumeric())
+		.filter(|word| !word.is_empty())
+		.map(str::to_lowercase)
+		.collect()
+}
+
+/// Whether two titles (as [title_words]) are long enough and share enough
+/// words to be taken for the same article's
+fn similar_titles(a: &BTreeSet<String>, b: &BTreeSet<String>) -> bool {
+	if a.len() < MIN_TITLE_WORDS || b.len() < MIN_TITLE_WORDS {
+		return false
+	}
+	let common = a.intersection(b).count();
+	let all = a.union(b).count();
+	common as f64 >= TITLE_SIMILARITY * all as f64
+}
@@ -1,9 +1,8 @@
 use chrono::{ DateTime, TimeDelta, Utc };
-use chrono_tz::Tz;
-use crate::domain::model::Entry;
+use crate::domain::model::{ Entry, EntryPage, PageInfo };
 use crate::domain::RussetDomainService;
-use crate::model::{ EntryId, FeedId, Pagination, Timestamp, UserId };
-use crate::persistence::model::{ Entry as PersistenceEntry, UserEntry };
+use crate::model::{ DateDisplay, DateFormat, DateLocale, EntryCursor, EntryId, EntryPagination, FeedId, ListingOptions, MarkReadScope, PagePosition, Timestamp, UserId };
+use crate::persistence::model::{ Entry as PersistenceEntry, UserEntry, UserEntryPage };
 use crate::persistence::RussetEntryPersistenceLayer;
 use crate::Result;
 use std::time::SystemTime;
@@ -13,26 +12,32 @@ where Persistence: RussetEntryPersistenceLayer {
 	pub async fn get_subscribed_entries(
 		&self,
 		user_id: &UserId,
-		pagination: &Pagination
-	) -> impl IntoIterator<Item = Result<Entry>> {
-		self.persistence
-			.get_entries_for_user(user_id, pagination)
+		options: &ListingOptions,
+		pagination: &EntryPagination,
+		display: &DateDisplay,
+	) -> Result<EntryPage> {
+		let page = self.persistence
+			.get_entries_for_user(user_id, options, pagination)
 			.await
-			.into_iter()
-			.map(|result| result.map(|(entry, user_entry)| convert_entry(entry, user_entry, /*FIXME*/Tz::UTC)))
-			.filter(|entry| entry.as_ref().map_or_else(|_| true, |entry| !entry.tombstone))
-			.collect::<Vec<Result<Entry>>>()
+			.map(|page| convert_page(page, &pagination.position, display))?;
+		self.add_duplicate_feeds(user_id, page).await
 	}
 
-	pub async fn get_entry(&self, entry_id: &EntryId, user_id: &UserId) -> Result<Entry> {
+	pub async fn get_entry(
+		&self,
+		entry_id: &EntryId,
+		user_id: &UserId,
+		display: &DateDisplay,
+	) -> Result<Entry> {
 		let user_entry = UserEntry {
 			read: Some(Timestamp::new(SystemTime::now())),
 			tombstone: None,
+			starred: None,
 		};
 		Ok(self.persistence
 			.get_entry_and_set_userentry(entry_id, user_id, &user_entry)
 			.await
-			.map(|entry| convert_entry(entry, Some(user_entry), Tz::UTC))?
+			.map(|entry| convert_entry(entry, Some(user_entry), display))?
 		)
 	}
 
@@ -40,49 +45,150 @@ where Persistence: RussetEntryPersistenceLayer {
 		&self,
 		user_id: &UserId,
 		feed_id: &FeedId,
-		pagination: &Pagination,
-	) -> impl IntoIterator<Item = Result<Entry>> {
+		options: &ListingOptions,
+		pagination: &EntryPagination,
+		display: &DateDisplay,
+	) -> Result<EntryPage> {
+		let page = self.persistence
+			.get_entries_for_user_feed(user_id, feed_id, options, pagination)
+			.await
+			.map(|page| convert_page(page, &pagination.position, display))?;
+		self.add_duplicate_feeds(user_id, page).await
+	}
+
+	pub async fn get_starred_entries(
+		&self,
+		user_id: &UserId,
+		options: &ListingOptions,
+		pagination: &EntryPagination,
+		display: &DateDisplay,
+	) -> Result<EntryPage> {
+		let page = self.persistence
+			.get_starred_entries_for_user(user_id, options, pagination)
+			.await
+			.map(|page| convert_page(page, &pagination.position, display))?;
+		self.add_duplicate_feeds(user_id, page).await
+	}
+
+	/// Delete the given entries for the given user at once, as rules do
+	pub async fn delete_entries(&self, entry_ids: &[EntryId], user_id: &UserId) -> Result<()> {
 		self.persistence
-			.get_entries_for_user_feed(user_id, feed_id, pagination)
+			.delete_entries(user_id, entry_ids, &Timestamp::now())
 			.await
-			.into_iter()
-			.map(|result| result.map(|(entry, user_entry)| convert_entry(entry, user_entry, /*FIXME*/Tz::UTC)))
-			.filter(|entry| entry.as_ref().map_or_else(|_| true, |entry| !entry.tombstone))
-			.collect::<Vec<Result<Entry>>>()
 	}
 
-	pub async fn set_userentries(
+	/// Mark every entry in `scope` read for the given user at once. Returns
+	/// how many entries weren't already read.
+	pub async fn mark_read(&self, user_id: &UserId, scope: &MarkReadScope) -> Result<u64> {
+		self.persistence
+			.mark_entries_read(user_id, scope, &Timestamp::now())
+			.await
+	}
+
+	/// Star or unstar the given entries for the given user. Returns `false` if
+	/// any of them isn't in one of the user's subscriptions; the rest are still
+	/// starred or unstarred.
+	pub async fn set_starred(
 		&self,
 		entry_ids: &Vec<EntryId>,
 		user_id: &UserId,
-		user_entry: &UserEntry,
-	) -> Result<()> {
+		starred: bool,
+	) -> Result<bool> {
+		let starred = starred.then(Timestamp::now);
+		let mut all_found = true;
 		for entry_id in entry_ids {
-			let _ = self.persistence
-				.get_entry_and_set_userentry(entry_id, user_id, user_entry)
+			all_found &= self.persistence
+				.set_userentry_starred(entry_id, user_id, starred.as_ref())
 				.await?;
 		}
-		Ok(())
+		Ok(all_found)
 	}
 
 }
 
-fn convert_entry(entry: PersistenceEntry, user_entry: Option<UserEntry>, tz: Tz) -> Entry {
-	// Article date: full ISO8601 out to -2 days, then ISO8601 date
-	let article_date_utc: DateTime<Utc> = entry.article_date.0.into();
-	let article_date = article_date_utc.with_timezone(&tz);
-	let article_date_str = if (Utc::now() - article_date_utc) < TimeDelta::days(2) {
-		article_date.to_rfc3339()
+/// Convert a page of a listing read from the given position, working out which
+/// neighboring pages there are
+pub(super) fn convert_page(page: UserEntryPage, position: &PagePosition, display: &DateDisplay) -> EntryPage {
+	// Reading from a cursor means there were entries on the other side of it
+	let (before, after) = if position.is_backward() {
+		(page.more, position.cursor().is_some())
 	} else {
-		article_date.date_naive().format("%Y-%m-%d").to_string()
+		(position.cursor().is_some(), page.more)
 	};
+	let mut cursors = page.entries
+		.iter()
+		.filter_map(|result| result.as_ref().ok())
+		.map(|(entry, _)| entry_cursor(entry));
+	let first = cursors.next();
+	let last = cursors.next_back().or(first);
+	EntryPage {
+		entries: page.entries
+			.into_iter()
+			.map(|result| result.map(|(entry, user_entry)| convert_entry(entry, user_entry, display)))
+			.collect(),
+		info: PageInfo {
+			total: page.total,
+			unread: page.unread,
+			prev: first.filter(|_| before),
+			next: last.filter(|_| after),
+		},
+	}
+}
+
+pub(super) fn entry_cursor(entry: &PersistenceEntry) -> EntryCursor {
+	EntryCursor { check_id: entry.check_id, article_date: entry.article_date, id: entry.id }
+}
+
+pub(super) fn convert_entry(entry: PersistenceEntry, user_entry: Option<UserEntry>, display: &DateDisplay) -> Entry {
+	let article_date = format_date(entry.article_date, display, Utc::now());
 	Entry {
 		id: entry.id,
 		feed_id: entry.feed_id,
 		url: entry.url.map(|url| url.to_string()),
 		title: entry.title,
-		article_date: article_date_str,
+		article_date,
 		read: user_entry.as_ref().and_then(|user_entry| user_entry.read.as_ref()).is_some(),
-		tombstone: user_entry.as_ref().and_then(|user_entry| user_entry.tombstone.as_ref()).is_some(),
+		starred: user_entry.as_ref().and_then(|user_entry| user_entry.starred.as_ref()).is_some(),
+		also_in: Vec::new(),
+	}
+}
+
+/// Format a date as the user has chosen. Recent dates are shown with times, or
+/// relative to `now`; older ones are shown as dates alone.
+pub(super) fn format_date(date: Timestamp, display: &DateDisplay, now: DateTime<Utc>) -> String {
+	let date_utc: DateTime<Utc> = date.0.into();
+	let date = date_utc.with_timezone(&display.timezone);
+	let age = now - date_utc;
+	let recent = age < TimeDelta::days(display.recent_days.into());
+	let (date_pattern, time_pattern) = locale_patterns(display.locale);
+	match display.format {
+		DateFormat::Iso if recent => date.to_rfc3339(),
+		DateFormat::Iso => date.format("%Y-%m-%d").to_string(),
+		DateFormat::Absolute if recent => date.format(&format!("{date_pattern} {time_pattern}")).to_string(),
+		// Future dates aren't any time ago
+		DateFormat::Relative if recent && age >= TimeDelta::zero() => {
+			if age < TimeDelta::minutes(1) {
+				"just now".to_string()
+			} else if age < TimeDelta::hours(1) {
+				format!("{}m ago", age.num_minutes())
+			} else if age < TimeDelta::days(1) {
+				format!("{}h ago", age.num_hours())
+			} else {
+				format!("{}d ago", age.num_days())
+			}
+		},
+		DateFormat::Absolute | DateFormat::Relative => date.format(date_pattern).to_string(),
+	}
+}
+
+/// The date and time [strftime](chrono::format::strftime) patterns for the
+/// given locale
+fn locale_patterns(locale: DateLocale) -> (&'static str, &'static str) {
+	match locale {
+		DateLocale::EnUs => ("%b %-d, %Y", "%-I:%M %p"),
+		DateLocale::EnGb => ("%-d %b %Y", "%H:%M"),
+		DateLocale::De => ("%d.%m.%Y", "%H:%M"),
+		DateLocale::Fr => ("%d/%m/%Y", "%H:%M"),
+		DateLocale::Ja => ("%Y/%m/%d", "%H:%M"),
 	}
 }
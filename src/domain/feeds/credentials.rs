@@ -0,0 +1,155 @@
+//! Credentials for fetching private feeds.
+//!
+//! Credentials are stored as a list of HTTP headers to send with each fetch of
+//! the feed. Basic auth, bearer tokens, and cookies are all just headers, so
+//! this covers all of them (plus anything site-specific, like API keys) without
+//! the persistence layer needing to know the difference.
+//!
+//! Credentials are encrypted at rest with a key derived from the configured
+//! `credential_key`, and the feed ID is bound to the ciphertext as associated
+//! data, so an encrypted blob can't be copied onto another feed.
+
+use argon2::Argon2;
+use base64ct::{ Base64, Encoding };
+use chacha20poly1305::{ ChaCha20Poly1305, KeyInit, Nonce };
+use chacha20poly1305::aead::{ Aead, AeadCore, OsRng, Payload };
+use crate::Err;
+use crate::model::FeedId;
+use crate::persistence::model::EncryptedCredentials;
+use crate::Result;
+use reqwest::header::{ AUTHORIZATION, COOKIE, HeaderMap, HeaderName, HeaderValue };
+
+/// Fixed salt for deriving the credential encryption key. The key material is
+/// expected to be high-entropy already; this just stretches it to a key.
+const KEY_SALT: &[u8] = b"russet-feed-credentials";
+const NONCE_LEN: usize = 12;
+
+#[derive(Clone, Default)]
+pub struct FeedCredentials {
+	headers: Vec<(HeaderName, HeaderValue)>,
+}
+impl FeedCredentials {
+	/// Add an arbitrary header. The name and value must be valid HTTP header
+	/// components.
+	pub fn add_header(&mut self, name: &str, value: &str) -> Result<()> {
+		let name = HeaderName::from_bytes(name.trim().as_bytes())
+			.map_err(|_| -> Err { format!("Invalid header name {name:?}").into() })?;
+		let mut value = HeaderValue::from_str(value.trim())
+			.map_err(|_| -> Err { format!("Invalid value for header {name}").into() })?;
+		value.set_sensitive(true);
+		self.headers.push((name, value));
+		Ok(())
+	}
+
+	/// Add an HTTP Basic `Authorization` header.
+	pub fn add_basic_auth(&mut self, user_name: &str, password: &str) -> Result<()> {
+		let encoded = Base64::encode_string(format!("{user_name}:{password}").as_bytes());
+		self.add_header(AUTHORIZATION.as_str(), &format!("Basic {encoded}"))
+	}
+
+	/// Add a bearer token `Authorization` header.
+	pub fn add_bearer_token(&mut self, token: &str) -> Result<()> {
+		self.add_header(AUTHORIZATION.as_str(), &format!("Bearer {token}"))
+	}
+
+	/// Add a `Cookie` header.
+	pub fn add_cookie(&mut self, cookie: &str) -> Result<()> {
+		self.add_header(COOKIE.as_str(), cookie)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.headers.is_empty()
+	}
+
+	pub fn header_map(&self) -> HeaderMap {
+		self.headers.iter().cloned().collect()
+	}
+
+	/// Serialize as `name: value` lines. Header names can't contain `:` and
+	/// neither names nor values can contain newlines, so this is unambiguous.
+	fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		for (name, value) in &self.headers {
+			bytes.extend_from_slice(name.as_str().as_bytes());
+			bytes.extend_from_slice(b": ");
+			bytes.extend_from_slice(value.as_bytes());
+			bytes.push(b'\n');
+		}
+		bytes
+	}
+
+	fn from_bytes(bytes: &[u8]) -> Result<FeedCredentials> {
+		let mut credentials = FeedCredentials::default();
+		for line in std::str::from_utf8(bytes)?.lines() {
+			let (name, value) = line
+				.split_once(':')
+				.ok_or::<Err>("Malformed stored credentials".into())?;
+			credentials.add_header(name, value)?;
+		}
+		Ok(credentials)
+	}
+}
+impl std::fmt::Debug for FeedCredentials {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_list()
+			.entries(self.headers.iter().map(|(name, _)| format!("{name}: <redacted>")))
+			.finish()
+	}
+}
+
+/// Cipher for encrypting and decrypting [FeedCredentials].
+#[derive(Clone)]
+pub struct CredentialCipher {
+	cipher: ChaCha20Poly1305,
+}
+impl CredentialCipher {
+	/// Derive a cipher from the given key material.
+	pub fn new(key_material: &[u8]) -> Result<CredentialCipher> {
+		let mut key = [0u8; 32];
+		Argon2::default()
+			.hash_password_into(key_material, KEY_SALT, &mut key)
+			.map_err(|e| -> Err { format!("Unable to derive credential key: {e}").into() })?;
+		Ok(CredentialCipher { cipher: ChaCha20Poly1305::new(&key.into()) })
+	}
+
+	pub fn encrypt(
+		&self,
+		feed_id: &FeedId,
+		credentials: &FeedCredentials,
+	) -> Result<EncryptedCredentials> {
+		let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+		let aad = feed_id.to_string();
+		let plaintext = credentials.to_bytes();
+		let ciphertext = self.cipher
+			.encrypt(&nonce, Payload { msg: &plaintext, aad: aad.as_bytes() })
+			.map_err(|_| -> Err { "Unable to encrypt feed credentials".into() })?;
+		let mut bytes = nonce.to_vec();
+		bytes.extend_from_slice(&ciphertext);
+		Ok(EncryptedCredentials(Base64::encode_string(&bytes)))
+	}
+
+	pub fn decrypt(
+		&self,
+		feed_id: &FeedId,
+		credentials: &EncryptedCredentials,
+	) -> Result<FeedCredentials> {
+		let bytes = Base64::decode_vec(&credentials.0)
+			.map_err(|_| -> Err { "Stored credentials are not valid base64".into() })?;
+		if bytes.len() < NONCE_LEN {
+			return Err("Stored credentials are truncated".into());
+		}
+		let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+		let aad = feed_id.to_string();
+		let plaintext = self.cipher
+			.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: aad.as_bytes() })
+			.map_err(|_| -> Err {
+				format!("Unable to decrypt credentials for feed {feed_id:?} (has credential_key changed?)").into()
+			})?;
+		FeedCredentials::from_bytes(&plaintext)
+	}
+}
+impl std::fmt::Debug for CredentialCipher {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("\"<redacted>\"")
+	}
+}
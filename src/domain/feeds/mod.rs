@@ -1,14 +1,23 @@
+pub mod credentials;
+pub mod sources;
 mod update;
+pub mod websub;
 
+use crate::domain::feeds::credentials::FeedCredentials;
+use crate::domain::feeds::sources::{ FeedSource, run_command };
 use crate::domain::model::Feed;
 use crate::domain::RussetDomainService;
-use crate::Result;
+use crate::{ Err, Result };
 use crate::model::{ EntryId, FeedId, UserId, Timestamp };
-use crate::persistence::model::{ Entry, Feed as PersistenceFeed, FeedCheck, WriteFeedCheck };
+use crate::persistence::model::{ Entry, Feed as PersistenceFeed, FeedCheck, ScrapeSelectors, WriteFeedCheck };
 use crate::persistence::{ RussetEntryPersistenceLayer, RussetFeedPersistenceLayer };
+use crate::feed::encoding::to_utf8;
 use crate::feed::model::Feed as ReaderFeed;
+use crate::feed::RussetFeedReader;
+use crate::feed::scrape::ScrapeFeedReader;
 use reqwest::Url;
 use std::collections::HashSet;
+use tracing::warn;
 use ulid::Ulid;
 
 impl <Persistence> RussetDomainService<Persistence>
@@ -16,7 +25,9 @@ where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer {
 
 	/// Update the stored entries for the given feed.
 	///
-	/// Returns the [FeedCheck] generated from this update.
+	/// Returns the [FeedCheck] generated from this update. If the feed can't be
+	/// fetched, a check flagged with the error is recorded, scheduling the next
+	/// check after the default interval, and the error is returned.
 	pub async fn update_feed(&self, feed_id: &FeedId, check_time: &Timestamp)
 		-> Result<FeedCheck>
 	{
@@ -24,8 +35,20 @@ where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer {
 
 		// Fetch the feed data. We do this now (before recording the check)
 		// because some of its details will need to feed back into the check.
-		// TODO: include the etag and handle errors (flag on the check) here
-		let reader_feed = self.fetch(&feed.url).await?;
+		// TODO: include the etag here
+		let reader_feed = match self.fetch_for_update(&feed).await {
+			Ok(reader_feed) => reader_feed,
+			Err(err) => {
+				self.persistence.add_feed_check(WriteFeedCheck {
+					feed_id: *feed_id,
+					check_time: *check_time,
+					next_check_time: *check_time + self.default_feed_check_interval,
+					etag: None,
+					error: Some(err.to_string()),
+				} ).await?;
+				return Err(err)
+			},
+		};
 
 		// Now, generate the check. We need this to store the entries, because
 		// they must be tagged with the check that generated them.
@@ -33,6 +56,8 @@ where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer {
 			.build_check_and_update(&check_time, &feed_id, &reader_feed)
 			.await?;
 
+		self.maintain_websub_subscription(&feed, &reader_feed).await;
+
 		Ok(check)
 	}
 
@@ -52,18 +77,40 @@ where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer {
 	/// If a feed with that URL is already stored, no action is taken.
 	/// Otherwise, the feed will be downloaded and added to the persistence
 	/// layer.
-	pub async fn add_feed(&self, url: &Url) -> Result<FeedId> {
-		match self.persistence.get_feed_by_url(url).await? {
+	///
+	/// Feeds with `credentials` are never shared, so a new feed is always
+	/// added for them.
+	pub async fn add_feed(
+		&self,
+		url: &Url,
+		credentials: Option<&FeedCredentials>,
+	) -> Result<FeedId> {
+		let credentials = credentials.filter(|credentials| !credentials.is_empty());
+		let existing_feed = match credentials {
+			Some(_) => None,
+			None => self.persistence.get_feed_by_url(url).await?,
+		};
+		match existing_feed {
 			Some(feed) => {
 				// TODO: Contemplate rescheduling the next check in this case.
 				Ok(feed.id)
 			}
 			None => {
-				let reader_feed = self.fetch(url).await?;
+				let id = FeedId(Ulid::new());
+				let encrypted_credentials = credentials
+					.map(|credentials| {
+						self.credential_cipher
+							.as_ref()
+							.ok_or::<Err>("Feed credentials require a credential_key to be configured".into())?
+							.encrypt(&id, credentials)
+					} )
+					.transpose()?;
+				let reader_feed = self.fetch(url, credentials).await?;
 				let feed = PersistenceFeed {
-					id: FeedId(Ulid::new()),
+					id,
 					title: reader_feed.title.clone(),
 					url: url.clone(),
+					credentials: encrypted_credentials,
 				};
 				self.persistence.add_feed(&feed).await?;
 				// TODO: Add the feed to scheduling.
@@ -72,12 +119,47 @@ where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer {
 						&feed.id,
 						&reader_feed
 					).await?;
+				self.maintain_websub_subscription(&feed, &reader_feed).await;
 
 				Ok(feed.id)
 			}
 		}
 	}
 
+	/// Add a new feed scraped from the HTML page at `url` using `selectors`.
+	///
+	/// Scraped feeds are never shared, so a new feed is always added.
+	pub async fn add_scraped_feed(
+		&self,
+		url: &Url,
+		selectors: &ScrapeSelectors,
+	) -> Result<FeedId> {
+		let reader_feed = self.scrape(url, selectors, None).await?;
+		let feed = PersistenceFeed {
+			id: FeedId(Ulid::new()),
+			title: reader_feed.title.clone(),
+			url: url.clone(),
+			credentials: None,
+		};
+		self.persistence.add_scraped_feed(&feed, selectors).await?;
+		self.build_check_and_update(
+				&Timestamp::now(),
+				&feed.id,
+				&reader_feed
+			).await?;
+		Ok(feed.id)
+	}
+
+	/// Scrape the HTML page at `url` using `selectors`, without storing
+	/// anything, to show what a scraped feed would contain.
+	pub async fn preview_scraped_feed(
+		&self,
+		url: &Url,
+		selectors: &ScrapeSelectors,
+	) -> Result<ReaderFeed> {
+		self.scrape(url, selectors, None).await
+	}
+
 	pub async fn feeds_for_user(&self, user_id: &UserId) -> Vec<Result<Feed>> {
 		self.persistence
 			.get_subscribed_feeds(user_id)
@@ -105,17 +187,108 @@ where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer {
 	}
 
 
-	/// Fetch feed data from the remote system
-	async fn fetch(&self, url: &Url) -> Result<ReaderFeed> {
-		let bytes = reqwest::get(url.clone())
-				.await?
-				.bytes()
-				.await?;
+	/// Fetch a stored feed, scraping it if it's a scraped feed
+	async fn fetch_for_update(&self, feed: &PersistenceFeed) -> Result<ReaderFeed> {
+		let credentials = self.decrypt_credentials(feed)?;
+		match self.persistence.get_scrape_selectors(&feed.id).await? {
+			Some(selectors) => self.scrape(&feed.url, &selectors, credentials.as_ref()).await,
+			None => self.fetch(&feed.url, credentials.as_ref()).await,
+		}
+	}
+
+	/// Decrypt the stored credentials for the given feed, if it has any.
+	fn decrypt_credentials(&self, feed: &PersistenceFeed) -> Result<Option<FeedCredentials>> {
+		feed.credentials
+			.as_ref()
+			.map(|credentials| {
+				self.credential_cipher
+					.as_ref()
+					.ok_or::<Err>(format!("Feed {:?} has credentials, but no credential_key is configured", feed.id).into())?
+					.decrypt(&feed.id, credentials)
+			} )
+			.transpose()
+	}
+
+	/// Fetch feed data from the remote system (or local source; see
+	/// [sources](crate::domain::feeds::sources))
+	async fn fetch(&self, url: &Url, credentials: Option<&FeedCredentials>) -> Result<ReaderFeed> {
+		let bytes = self.fetch_raw(url, credentials, false).await?;
 		// TODO: Store a reader hint with the feed to save redundant parsing effort
 		let reader_feed = self.feed_from_bytes(&bytes).await?;
 		Ok(reader_feed)
 	}
 
+	/// Fetch an HTML page and scrape a feed out of it
+	async fn scrape(
+		&self,
+		url: &Url,
+		selectors: &ScrapeSelectors,
+		credentials: Option<&FeedCredentials>,
+	) -> Result<ReaderFeed> {
+		let reader = ScrapeFeedReader::new(
+			&selectors.item,
+			selectors.title.as_deref(),
+			selectors.link.as_deref(),
+			selectors.date.as_deref(),
+			url.clone(),
+		)?;
+		let bytes = self.fetch_raw(url, credentials, true).await?;
+		reader.read_feed(&bytes)
+	}
+
+	/// Fetch the raw bytes of a feed from whatever source its URL specifies,
+	/// transcoded to UTF-8 (see [encoding](crate::feed::encoding)). `html` is
+	/// whether it's an HTML page to scrape.
+	async fn fetch_raw(&self, url: &Url, credentials: Option<&FeedCredentials>, html: bool) -> Result<Vec<u8>> {
+		let source = FeedSource::from_url(url)?;
+		if source.is_local() && !self.local_feed_sources {
+			return Err(format!("Local feed sources are disabled; not fetching {url}").into());
+		}
+		let (bytes, content_type) = match source {
+			FeedSource::Exec(command) => (run_command(&command, None).await?, None),
+			FeedSource::Filter { command, url } => {
+				let (bytes, _) = match FeedSource::from_url(&url)? {
+					source @ (FeedSource::Http(_) | FeedSource::File(_)) =>
+						Self::fetch_bytes(&source, credentials).await?,
+					_ => return Err(format!("Can't filter {url}").into()),
+				};
+				// The filter's output is its own document, so the original
+				// content type doesn't apply to it.
+				(run_command(&command, Some(bytes)).await?, None)
+			},
+			source => Self::fetch_bytes(&source, credentials).await?,
+		};
+		Ok(to_utf8(&bytes, content_type.as_deref(), html).into_owned())
+	}
+
+	/// Read the raw bytes of an HTTP or file source, along with their content
+	/// type, if known
+	async fn fetch_bytes(
+		source: &FeedSource,
+		credentials: Option<&FeedCredentials>,
+	) -> Result<(Vec<u8>, Option<String>)> {
+		match source {
+			FeedSource::Http(url) => {
+				let mut request = reqwest::Client::new().get(url.clone());
+				if let Some(credentials) = credentials {
+					request = request.headers(credentials.header_map());
+				}
+				let response = request
+					.send()
+					.await?
+					.error_for_status()?;
+				let content_type = response
+					.headers()
+					.get(reqwest::header::CONTENT_TYPE)
+					.and_then(|content_type| content_type.to_str().ok())
+					.map(|content_type| content_type.to_string());
+				Ok((response.bytes().await?.to_vec(), content_type))
+			},
+			FeedSource::File(path) => Ok((tokio::fs::read(path).await?, None)),
+			_ => Err(format!("Can't read {source:?} directly").into()),
+		}
+	}
+
 	/// Given a parsed `reader_feed`, generate and persist a [FeedCheck] for it
 	/// and update the persistence layer with its entries
 	async fn build_check_and_update(
@@ -127,12 +300,19 @@ where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer {
 		// Generate the check. We need this to store the entries, because
 		// they must be tagged with the check that generated them.
 		// TODO: Whole lotta logic goes here:
-		let next_check_time = *check_time + self.default_feed_check_interval;
+		// Feeds a hub pushes to only need polling as a fallback.
+		let interval = if self.websub_active(feed_id).await? {
+			self.max_feed_check_interval
+		} else {
+			self.default_feed_check_interval
+		};
+		let next_check_time = *check_time + interval;
 		let check = self.persistence.add_feed_check(WriteFeedCheck {
 			feed_id: feed_id.clone(),
 			check_time: check_time.clone(),
 			next_check_time,
 			etag: None,
+			error: None,
 		} ).await?;
 
 		// Finally, store the entries, tagged with the check.
@@ -144,13 +324,19 @@ where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer {
 	/// Given a parsed `reader_feed`, update the persistence layer for the given
 	/// feed with the entries from it
 	async fn update_with_entries(&self, feed_id: &FeedId, reader_feed: &ReaderFeed, check_id: u64) -> Result<()> {
-		let known_internal_ids = self.persistence
+		let mut known_internal_ids = self.persistence
 			.get_entries_for_feed(&feed_id)
 			.await
 			.into_iter()
 			.filter_map(|entry| entry.ok().map(|entry| entry.internal_id) )
 			.collect::<HashSet<String>>();
-		let new_entries = reader_feed.entries.as_slice().into_iter()
+		// Pruned entries are still known; they shouldn't come back
+		known_internal_ids.extend(self.persistence
+			.get_pruned_internal_ids(feed_id)
+			.await
+			.into_iter()
+			.collect::<Result<Vec<String>>>()?);
+		let mut new_entries = reader_feed.entries.as_slice().into_iter()
 			.filter(|entry| !known_internal_ids.contains(&entry.internal_id) )
 			.map (|entry| {
 				Entry {
@@ -161,17 +347,29 @@ where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer {
 					article_date: entry.article_date.clone(),
 					title: entry.title.clone(),
 					url: entry.url.clone(),
+					author: entry.author.clone(),
+					categories: entry.categories.clone(),
+					duplicate_of: None,
 				}
 			} )
 			.collect::<Vec<Entry>>();
+		// Entries which can't be checked for duplicates are still new entries
+		if let Err(e) = self.find_duplicates(feed_id, &mut new_entries).await {
+			warn!(error = e.as_ref(), "Error finding duplicates of new entries from {feed_id:?}");
+		}
 		for e in new_entries.as_slice() {
 			self.persistence.add_entry(e, &feed_id).await?;
 		}
+		// The entries are stored either way, so a failure here shouldn't fail
+		// the whole update.
+		if let Err(e) = self.apply_rules_to_new_entries(feed_id, &new_entries).await {
+			warn!(error = e.as_ref(), "Error applying rules to new entries from {feed_id:?}");
+		}
 		Ok(())
 	}
 
 	/// Given a serialized feed, attempt to deserialize it using all the known
-	/// `readers`.
+	/// `readers`. `bytes` must already be UTF-8.
 	///
 	/// TODO: This always attempts all `readers`, but a given URL will virtually
 	/// never change format. We should store a format hint with the feed and
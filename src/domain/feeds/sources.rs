@@ -0,0 +1,111 @@
+//! Feed sources other than plain HTTP.
+//!
+//! Besides `http` and `https` URLs, a feed may be read from:
+//!
+//! * `file:///path/to/feed.xml` — a file on the server's filesystem
+//! * `exec:<command>` — the standard output of a shell command
+//! * `filter:<command>:<url>` — another feed, piped through a command first
+//!
+//! `filter:` works like newsboat's: the feed at `url` (which must be `http`,
+//! `https`, or `file`) is fed to the command's standard input, and the
+//! command's standard output is parsed as the feed.
+//!
+//! Commands are run exactly as written in the URL: percent-encoding isn't
+//! undone, so `%` is just `%`. Parsing the URL percent-encodes characters
+//! outside ASCII, though, so commands should stick to ASCII.
+//!
+//! These run with the server's privileges, so they're only fetched if
+//! `local_feed_sources` is enabled in the config, and only users with
+//! [Permission::LocalFeedSources](crate::model::Permission) may add them.
+
+use crate::{ Err, Result };
+use reqwest::Url;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::warn;
+
+/// How long a command may run before we give up on it.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum FeedSource {
+	Http(Url),
+	File(PathBuf),
+	Exec(String),
+	Filter { command: String, url: Url },
+}
+impl FeedSource {
+	pub fn from_url(url: &Url) -> Result<FeedSource> {
+		match url.scheme() {
+			"http" | "https" => Ok(FeedSource::Http(url.clone())),
+			"file" => Ok(FeedSource::File(
+				url.to_file_path()
+					.map_err(|_| -> Err { format!("Not a valid file path: {url}").into() })?
+			) ),
+			"exec" => Ok(FeedSource::Exec(Self::opaque_part(url).to_string())),
+			"filter" => {
+				// Commands can contain colons, so split on the first one which
+				// introduces a URL scheme we can read from.
+				let rest = url.as_str()
+					.strip_prefix("filter:")
+					.expect("URL with scheme \"filter\" starts with \"filter:\"");
+				let (command, inner_url) = ["http:", "https:", "file:"]
+					.iter()
+					.filter_map(|scheme| rest.find(&format!(":{scheme}")))
+					.min()
+					.map(|index| (&rest[..index], &rest[index + 1..]))
+					.ok_or_else(|| -> Err { format!("No feed URL in filter {url}").into() })?;
+				let url = Url::parse(inner_url)?;
+				Ok(FeedSource::Filter { command: command.to_string(), url })
+			},
+			scheme => Err(format!("Unsupported feed URL scheme {scheme:?}").into()),
+		}
+	}
+
+	/// Whether this source runs commands or reads files on the server
+	pub fn is_local(&self) -> bool {
+		!matches!(self, FeedSource::Http(_))
+	}
+
+	/// The part of a URL after the scheme, as written
+	fn opaque_part(url: &Url) -> &str {
+		&url.as_str()[url.scheme().len() + 1..]
+	}
+}
+
+/// Run `command` with `sh -c`, optionally feeding it `stdin`, and return its
+/// standard output.
+pub async fn run_command(command: &str, stdin: Option<Vec<u8>>) -> Result<Vec<u8>> {
+	let mut child = Command::new("sh")
+		.arg("-c")
+		.arg(command)
+		.stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.kill_on_drop(true)
+		.spawn()?;
+	// Write stdin concurrently, or a command which produces a lot of output
+	// before it has read all its input will deadlock.
+	let writer = match (stdin, child.stdin.take()) {
+		(Some(bytes), Some(mut pipe)) => Some(tokio::spawn(async move {
+			pipe.write_all(&bytes).await
+		} )),
+		_ => None,
+	};
+	let output = tokio::time::timeout(COMMAND_TIMEOUT, child.wait_with_output())
+		.await
+		.map_err(|_| -> Err { format!("Command {command:?} timed out").into() })??;
+	if let Some(writer) = writer {
+		if let Err(e) = writer.await? {
+			warn!(error = e.to_string(), "Command {command:?} did not read all its input");
+		}
+	}
+	if !output.status.success() {
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		return Err(format!("Command {command:?} failed ({}): {}", output.status, stderr.trim()).into());
+	}
+	Ok(output.stdout)
+}
@@ -0,0 +1,335 @@
+//! WebSub (formerly PubSubHubbub) push subscriptions.
+//!
+//! When a feed advertises a hub (`<link rel="hub">`) and Russet knows its own
+//! public URL, we ask the hub to push new content to
+//! `<public_url>/websub/<feed_id>`. The hub verifies our intent with a GET to
+//! that URL, then POSTs the feed to it whenever it changes, signed with a
+//! secret we gave it. Pushed content is ingested just like a check. Only
+//! hubs reached over HTTPS are used, since the secret can't be sent in the
+//! clear.
+//!
+//! Pushes are best-effort, so feeds with an active subscription are still
+//! polled, but only at the maximum check interval. Leases are renewed by a
+//! background task (see [crate::server]) before they expire.
+
+use base32ct::{ Base32Unpadded, Encoding };
+use crate::domain::RussetDomainService;
+use crate::model::{ FeedId, Timestamp };
+use crate::persistence::model::{ Feed, HubSecret, WebSubSubscription, WriteFeedCheck };
+use crate::persistence::{ RussetEntryPersistenceLayer, RussetFeedPersistenceLayer };
+use crate::feed::encoding::to_utf8;
+use crate::feed::model::Feed as ReaderFeed;
+use crate::Result;
+use getrandom::getrandom;
+use hmac::{ Hmac, Mac };
+use reqwest::Url;
+use std::time::Duration;
+use tracing::{ error, info, warn };
+
+/// Lease we ask hubs for. Hubs are free to pick something else.
+const REQUESTED_LEASE: Duration = Duration::from_secs(10 * 24 * 60 * 60);
+
+/// Renew leases this long before they expire.
+const LEASE_RENEWAL_MARGIN: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long after asking a hub to subscribe we accept its verification.
+const PENDING_VERIFICATION_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long we wait on a hub to answer a subscription request.
+const HUB_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The result of a hub's intent verification request
+pub enum Verification {
+	/// Echo the challenge back to the hub
+	Confirm(String),
+	/// We don't recognize this request
+	Reject,
+}
+
+impl <Persistence> RussetDomainService<Persistence>
+where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer {
+
+	/// Whether the given feed currently has pushes coming from a hub
+	pub(super) async fn websub_active(&self, feed_id: &FeedId) -> Result<bool> {
+		Ok(self.persistence
+			.get_websub_subscription(feed_id)
+			.await?
+			.and_then(|subscription| subscription.lease_expiration)
+			.is_some_and(|expiration| expiration > Timestamp::now()))
+	}
+
+	/// After a check, make sure our hub subscription for the feed matches what
+	/// the feed advertises. Errors are logged rather than returned: failing to
+	/// set up pushes shouldn't fail the check.
+	pub(super) async fn maintain_websub_subscription(&self, feed: &Feed, reader_feed: &ReaderFeed) {
+		if let Err(e) = self.try_maintain_websub_subscription(feed, reader_feed).await {
+			error!(error = e.as_ref(), "Error maintaining WebSub subscription for {:?}", feed.id);
+		}
+	}
+
+	async fn try_maintain_websub_subscription(&self, feed: &Feed, reader_feed: &ReaderFeed) -> Result<()> {
+		if self.public_url.is_none() || feed.credentials.is_some() {
+			return Ok(())
+		}
+		let existing = self.persistence.get_websub_subscription(&feed.id).await?;
+		let hub = match &reader_feed.hub {
+			Some(hub) if hub.scheme() == "https" => hub,
+			Some(hub) => {
+				info!("Not subscribing to {:?} with non-HTTPS hub {}", feed.id, hub);
+				if existing.is_some() {
+					self.persistence.delete_websub_subscription(&feed.id).await?;
+				}
+				return Ok(())
+			},
+			None => {
+				if existing.is_some() {
+					info!("Feed {:?} no longer advertises a hub; dropping subscription", feed.id);
+					self.persistence.delete_websub_subscription(&feed.id).await?;
+				}
+				return Ok(())
+			}
+		};
+		let topic = reader_feed.self_url.as_ref().unwrap_or(&feed.url);
+		let renewal_time = Timestamp::now() + LEASE_RENEWAL_MARGIN;
+		match existing {
+			Some(subscription) if &subscription.hub == hub
+					&& &subscription.topic == topic
+					&& subscription.lease_expiration.is_some_and(|expiration| expiration > renewal_time) => {
+				Ok(())
+			},
+			// Keep the secret if we're renewing with the same hub, so content
+			// pushed in the meantime still verifies.
+			Some(subscription) if &subscription.hub == hub && &subscription.topic == topic => {
+				self.request_websub_subscription(subscription).await
+			},
+			_ => {
+				let subscription = WebSubSubscription {
+					feed_id: feed.id,
+					hub: hub.clone(),
+					topic: topic.clone(),
+					secret: generate_secret()?,
+					lease_expiration: None,
+					pending_since: None,
+				};
+				self.request_websub_subscription(subscription).await
+			}
+		}
+	}
+
+	/// Record the subscription and ask the hub to (re)subscribe us
+	async fn request_websub_subscription(&self, subscription: WebSubSubscription) -> Result<()> {
+		let callback = match self.websub_callback(&subscription.feed_id) {
+			Some(callback) => callback,
+			None => return Ok(()),
+		};
+		// Subscriptions from before we insisted on HTTPS shouldn't be renewed
+		if subscription.hub.scheme() != "https" {
+			info!("Dropping WebSub subscription for {:?} with non-HTTPS hub {}", subscription.feed_id, subscription.hub);
+			return self.persistence.delete_websub_subscription(&subscription.feed_id).await
+		}
+		// Hubs verify asynchronously, so note that we're expecting them to
+		let subscription = WebSubSubscription { pending_since: Some(Timestamp::now()), ..subscription };
+		self.persistence.set_websub_subscription(&subscription).await?;
+		info!("Requesting WebSub subscription to {} from {}", subscription.topic, subscription.hub);
+		let lease_seconds = REQUESTED_LEASE.as_secs().to_string();
+		reqwest::Client::builder()
+			.timeout(HUB_REQUEST_TIMEOUT)
+			.build()?
+			.post(subscription.hub.clone())
+			.form(&[
+				("hub.callback", callback.as_str()),
+				("hub.mode", "subscribe"),
+				("hub.topic", subscription.topic.as_str()),
+				("hub.lease_seconds", lease_seconds.as_str()),
+				("hub.secret", subscription.secret.0.as_str()),
+			])
+			.send()
+			.await?
+			.error_for_status()?;
+		Ok(())
+	}
+
+	fn websub_callback(&self, feed_id: &FeedId) -> Option<Url> {
+		let public_url = self.public_url.as_ref()?;
+		let base = public_url.as_str().trim_end_matches('/');
+		Url::parse(&format!("{base}/websub/{}", feed_id.to_string())).ok()
+	}
+
+	/// Handle a hub's verification of intent (or denial) for a feed. These
+	/// come unauthenticated, so they're only accepted for the topic we asked
+	/// for, while we're waiting to hear back about asking.
+	pub async fn verify_websub_intent(
+		&self,
+		feed_id: &FeedId,
+		mode: &str,
+		topic: Option<&str>,
+		challenge: Option<&str>,
+		lease_seconds: Option<u64>,
+	) -> Result<Verification> {
+		let subscription = match self.persistence.get_websub_subscription(feed_id).await? {
+			Some(subscription) => subscription,
+			None => return Ok(Verification::Reject),
+		};
+		if topic != Some(subscription.topic.as_str()) {
+			warn!("WebSub verification for {feed_id:?} had the wrong topic {topic:?}");
+			return Ok(Verification::Reject)
+		}
+		let pending = subscription.pending_since
+			.and_then(|pending_since| pending_since.checked_add(PENDING_VERIFICATION_WINDOW))
+			.is_some_and(|window_end| window_end > Timestamp::now());
+		if !pending {
+			warn!("Unexpected WebSub verification for {feed_id:?}; no subscription request is pending");
+			return Ok(Verification::Reject)
+		}
+		match (mode, challenge) {
+			("subscribe", Some(challenge)) => {
+				// Hubs may pick their own lease, but not an absurd one
+				let lease = lease_seconds
+					.map(|lease_seconds| Duration::from_secs(lease_seconds).min(REQUESTED_LEASE * 2))
+					.unwrap_or(REQUESTED_LEASE);
+				let Some(lease_expiration) = Timestamp::now().checked_add(lease) else {
+					return Ok(Verification::Reject)
+				};
+				let subscription = WebSubSubscription {
+					lease_expiration: Some(lease_expiration),
+					pending_since: None,
+					..subscription
+				};
+				info!("WebSub subscription for {feed_id:?} verified, lease expires {:?}", subscription.lease_expiration);
+				self.persistence.set_websub_subscription(&subscription).await?;
+				Ok(Verification::Confirm(challenge.to_string()))
+			},
+			("denied", _) => {
+				warn!("Hub {} denied WebSub subscription for {feed_id:?}", subscription.hub);
+				self.persistence.delete_websub_subscription(feed_id).await?;
+				Ok(Verification::Confirm(String::new()))
+			},
+			// We never unsubscribe, so an unsubscribe verification isn't ours
+			_ => Ok(Verification::Reject),
+		}
+	}
+
+	/// Ingest content pushed by a hub. Returns `false` if there's no
+	/// subscription for the feed.
+	///
+	/// Per the spec, content with a missing or bad signature is acknowledged
+	/// but ignored.
+	pub async fn receive_websub_content(
+		&self,
+		feed_id: &FeedId,
+		signature: Option<&str>,
+		content_type: Option<&str>,
+		body: &[u8],
+	) -> Result<bool> {
+		let subscription = match self.persistence.get_websub_subscription(feed_id).await? {
+			Some(subscription) => subscription,
+			None => return Ok(false),
+		};
+		if !signature.is_some_and(|signature| verify_signature(&subscription.secret, signature, body)) {
+			warn!("Ignoring WebSub content for {feed_id:?} with missing or invalid signature");
+			return Ok(true)
+		}
+		let reader_feed = self.feed_from_bytes(&to_utf8(body, content_type, false)).await?;
+		// Pushed content gets a check of its own, so its entries are tagged
+		// like any other. It doesn't change when the next poll is scheduled.
+		let now = Timestamp::now();
+		let next_check_time = self.persistence
+			.get_last_feed_check(feed_id)
+			.await?
+			.map(|check| check.next_check_time)
+			.unwrap_or(now + self.max_feed_check_interval);
+		let check = self.persistence.add_feed_check(WriteFeedCheck {
+			feed_id: *feed_id,
+			check_time: now,
+			next_check_time,
+			etag: None,
+			error: None,
+		} ).await?;
+		self.update_with_entries(feed_id, &reader_feed, check.id).await?;
+		info!("Ingested WebSub content for {feed_id:?}");
+		Ok(true)
+	}
+
+	/// Renew subscriptions whose leases are about to expire
+	pub async fn renew_websub_leases(&self) -> Result<()> {
+		let renewal_time = Timestamp::now() + LEASE_RENEWAL_MARGIN;
+		let subscriptions = self.persistence
+			.get_expiring_websub_subscriptions(&renewal_time)
+			.await
+			.into_iter()
+			.collect::<Vec<Result<WebSubSubscription>>>();
+		for subscription in subscriptions {
+			match subscription {
+				Ok(subscription) => {
+					let feed_id = subscription.feed_id;
+					if let Err(e) = self.request_websub_subscription(subscription).await {
+						error!(error = e.as_ref(), "Error renewing WebSub subscription for {feed_id:?}");
+					}
+				},
+				Err(e) => error!(error = e.as_ref(), "Error loading WebSub subscription"),
+			}
+		}
+		Ok(())
+	}
+}
+
+fn generate_secret() -> Result<HubSecret> {
+	let mut bytes = [0u8; 32];
+	getrandom(&mut bytes)?;
+	Ok(HubSecret(Base32Unpadded::encode_string(&bytes)))
+}
+
+/// Check an `X-Hub-Signature` header (`method=hexdigest`) against the body
+fn verify_signature(secret: &HubSecret, signature: &str, body: &[u8]) -> bool {
+	fn verify<M: Mac + hmac::digest::KeyInit>(secret: &[u8], digest: &[u8], body: &[u8]) -> bool {
+		match <M as hmac::digest::KeyInit>::new_from_slice(secret) {
+			Ok(mut mac) => {
+				mac.update(body);
+				mac.verify_slice(digest).is_ok()
+			},
+			Err(_) => false,
+		}
+	}
+	let Some((method, hex)) = signature.split_once('=') else { return false };
+	let Ok(digest) = base16ct::mixed::decode_vec(hex) else { return false };
+	let secret = secret.0.as_bytes();
+	match method {
+		"sha1" => verify::<Hmac<sha1::Sha1>>(secret, &digest, body),
+		"sha256" => verify::<Hmac<sha2::Sha256>>(secret, &digest, body),
+		"sha384" => verify::<Hmac<sha2::Sha384>>(secret, &digest, body),
+		"sha512" => verify::<Hmac<sha2::Sha512>>(secret, &digest, body),
+		_ => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sign(secret: &HubSecret, body: &[u8]) -> String {
+		let mut mac = <Hmac<sha2::Sha256> as Mac>::new_from_slice(secret.0.as_bytes())
+			.expect("HMAC should take any key");
+		mac.update(body);
+		format!("sha256={}", base16ct::lower::encode_string(&mac.finalize().into_bytes()))
+	}
+
+	#[test]
+	fn signature_over_the_body_verifies() {
+		let secret = generate_secret().unwrap();
+		let body = b"<feed></feed>";
+		assert!(verify_signature(&secret, &sign(&secret, body), body));
+	}
+
+	#[test]
+	fn bad_signatures_are_rejected() {
+		let secret = generate_secret().unwrap();
+		let other_secret = generate_secret().unwrap();
+		let body = b"<feed></feed>";
+		let signature = sign(&secret, body);
+		assert!(!verify_signature(&secret, &signature, b"<feed>forged</feed>"));
+		assert!(!verify_signature(&other_secret, &signature, body));
+		assert!(!verify_signature(&secret, &signature.replacen("sha256", "sha512", 1), body));
+		assert!(!verify_signature(&secret, "sha256=not hex", body));
+		assert!(!verify_signature(&secret, "", body));
+	}
+}
@@ -0,0 +1,58 @@
+use crate::domain::entries::convert_page;
+use crate::domain::model::{ EntryPage, Subscription };
+use crate::domain::{ normalize_name, RussetDomainService };
+use crate::model::{ DateDisplay, FeedId, EntryPagination, ListingOptions, UserId };
+use crate::persistence::{ RussetEntryPersistenceLayer, RussetFeedPersistenceLayer, RussetUserPersistenceLayer };
+use crate::Result;
+use std::collections::BTreeSet;
+
+impl <Persistence> RussetDomainService<Persistence>
+where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer + RussetUserPersistenceLayer {
+	/// Get the given user's subscriptions, sorted by folder then title
+	pub async fn get_subscriptions(&self, user_id: &UserId) -> Result<Vec<Subscription>> {
+		self.persistence
+			.get_subscriptions(user_id)
+			.await
+			.into_iter()
+			.map(|result| result.map(|(feed, folder)| Subscription { feed: feed.into(), folder }))
+			.collect()
+	}
+
+	/// Get the names of the given user's folders, sorted
+	pub async fn get_folders(&self, user_id: &UserId) -> Result<Vec<String>> {
+		Ok(self.get_subscriptions(user_id)
+			.await?
+			.into_iter()
+			.filter_map(|subscription| subscription.folder)
+			.collect::<BTreeSet<String>>()
+			.into_iter()
+			.collect())
+	}
+
+	/// File the given user's subscription in the named folder. An empty name
+	/// takes the subscription out of its folder.
+	pub async fn set_folder(&self, user_id: &UserId, feed_id: &FeedId, folder: &str) -> Result<()> {
+		let folder = match folder.trim() {
+			"" => None,
+			folder => Some(normalize_name("Folder", folder)?),
+		};
+		self.persistence
+			.set_subscription_folder(user_id, feed_id, folder.as_deref())
+			.await
+	}
+
+	pub async fn get_folder_entries(
+		&self,
+		user_id: &UserId,
+		folder: &str,
+		options: &ListingOptions,
+		pagination: &EntryPagination,
+		display: &DateDisplay,
+	) -> Result<EntryPage> {
+		let page = self.persistence
+			.get_entries_for_user_folder(user_id, folder, options, pagination)
+			.await
+			.map(|page| convert_page(page, &pagination.position, display))?;
+		self.add_duplicate_feeds(user_id, page).await
+	}
+}
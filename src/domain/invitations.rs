@@ -0,0 +1,148 @@
+//! Invitations for new users to register themselves, so sysops don't need
+//! shell access to add users. Each invitation has a link with a random token,
+//! which works once, and optionally only until it expires.
+
+use base32ct::{ Base32Unpadded, Encoding };
+use crate::domain::entries::format_date;
+use crate::domain::model::InvitationInfo;
+use crate::domain::RussetDomainService;
+use crate::model::{ DateDisplay, InvitationId, Timestamp, UserId, UserType };
+use crate::persistence::model::Invitation;
+use crate::persistence::RussetUserPersistenceLayer;
+use crate::Result;
+use sha2::{ Digest, Sha256 };
+use std::time::{ Duration, SystemTime };
+use tracing::info;
+use ulid::Ulid;
+
+/// The longest an invitation can be made to last for
+pub const MAX_INVITATION_DAYS: u32 = 365;
+
+impl <Persistence> RussetDomainService<Persistence>
+where Persistence: RussetUserPersistenceLayer {
+
+	/// Invite someone to register as a user of the given type, for
+	/// `expires_in_days`, or indefinitely. Returns the link to send them.
+	pub async fn create_invitation(
+		&self,
+		created_by: &UserId,
+		user_type: UserType,
+		expires_in_days: Option<u32>,
+	) -> Result<String> {
+		if expires_in_days.is_some_and(|days| days == 0 || days > MAX_INVITATION_DAYS) {
+			return Err(format!("Invitations can last between 1 and {MAX_INVITATION_DAYS} days").into())
+		}
+		let token = Self::generate_token()?.0;
+		let now = SystemTime::now();
+		let invitation = Invitation {
+			id: InvitationId(Ulid::new()),
+			token_hash: invitation_token_hash(&token),
+			user_type,
+			created_by: *created_by,
+			created: Timestamp::new(now),
+			expiration: expires_in_days
+				.map(|days| Timestamp::new(now + Duration::from_secs(u64::from(days) * 24 * 60 * 60))),
+		};
+		self.persistence.add_invitation(&invitation).await?;
+		info!("Created invitation {:?} for a {:?}", invitation.id, user_type);
+		Ok(self.invitation_link(&token))
+	}
+
+	/// Get every invitation not yet used, newest first
+	pub async fn get_invitations(&self, display: &DateDisplay) -> Result<Vec<InvitationInfo>> {
+		let now = SystemTime::now();
+		Ok(self.persistence
+			.get_invitations()
+			.await?
+			.into_iter()
+			.map(|(invitation, created_by)| InvitationInfo {
+				id: invitation.id,
+				user_type: invitation.user_type,
+				created_by,
+				created: format_date(invitation.created, display, now.into()),
+				expiration: invitation.expiration
+					.map(|expiration| format_date(expiration, display, now.into())),
+				expired: invitation.expiration.is_some_and(|expiration| expiration.0 < now),
+			} )
+			.collect())
+	}
+
+	/// Revoke the given invitation. Returns whether there was one to revoke.
+	pub async fn revoke_invitation(&self, invitation_id: &InvitationId) -> Result<bool> {
+		let revoked = self.persistence.delete_invitation(invitation_id).await?;
+		if revoked {
+			info!("Revoked invitation {:?}", invitation_id);
+		}
+		Ok(revoked)
+	}
+
+	/// The invitation with the given token, if it can still be used
+	pub async fn get_invitation(&self, token: &str) -> Result<Option<Invitation>> {
+		Ok(self.persistence
+			.get_invitation_by_token_hash(&invitation_token_hash(token))
+			.await?
+			.filter(|invitation| invitation.expiration
+				.is_none_or(|expiration| expiration.0 > SystemTime::now())))
+	}
+
+	/// Register a new user with the invitation with the given token, using
+	/// the invitation up. Returns false if there's no such invitation, or it
+	/// was already used or has expired.
+	pub async fn register_user(
+		&self,
+		token: &str,
+		user_name: &str,
+		plaintext_password: &str,
+	) -> Result<bool> {
+		let Some(invitation) = self.get_invitation(token).await? else {
+			return Ok(false)
+		};
+		// Checked here as well as by add_user, whose error would show whoever's
+		// registering the existing user's ID
+		if self.persistence.get_user_by_name(user_name).await?.is_some() {
+			return Err(format!("The user name {user_name} is taken").into())
+		}
+		// Use the invitation up first, so it can't be used twice at once
+		if !self.persistence.delete_invitation(&invitation.id).await? {
+			return Ok(false)
+		}
+		match self.add_user(user_name, plaintext_password, invitation.user_type).await {
+			Ok(()) => {
+				info!("Registered {:?} with invitation {:?}", user_name, invitation.id);
+				Ok(true)
+			},
+			Err(e) => {
+				// Give the invitation back, so they can try another name or
+				// password
+				self.persistence.add_invitation(&invitation).await?;
+				Err(e)
+			},
+		}
+	}
+
+	/// Forget invitations which can't be used any more
+	pub async fn cleanup_expired_invitations(&self) -> Result<()> {
+		let expiry = Timestamp::new(SystemTime::now());
+		let deleted = self.persistence.delete_expired_invitations(&expiry).await?;
+		if deleted > 0 {
+			info!("Removed {deleted} expired invitations");
+		}
+		Ok(())
+	}
+
+	/// The link to register with the given invitation token: absolute if we
+	/// know where we are, otherwise relative to the site root
+	fn invitation_link(&self, token: &str) -> String {
+		let base = self.public_url
+			.as_ref()
+			.map_or("", |public_url| public_url.as_str().trim_end_matches('/'));
+		format!("{base}/register/{token}")
+	}
+}
+
+/// Invitations are looked up by a hash of their token, so the links can't be
+/// recovered from the database. Tokens are random enough not to need salt or
+/// slow hashing.
+fn invitation_token_hash(token: &str) -> String {
+	Base32Unpadded::encode_string(&Sha256::digest(token.as_bytes()))
+}
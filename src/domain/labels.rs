@@ -0,0 +1,62 @@
+use crate::domain::entries::convert_page;
+use crate::domain::model::{ EntryPage, Label };
+use crate::domain::{ normalize_name, RussetDomainService };
+use crate::model::{ DateDisplay, EntryId, EntryPagination, ListingOptions, UserId };
+use crate::persistence::RussetEntryPersistenceLayer;
+use crate::Result;
+
+impl <Persistence> RussetDomainService<Persistence>
+where Persistence: RussetEntryPersistenceLayer {
+	/// Get the given user's labels, sorted by name
+	pub async fn get_labels(&self, user_id: &UserId) -> Result<Vec<Label>> {
+		self.persistence
+			.get_label_counts(user_id)
+			.await
+			.into_iter()
+			.map(|result| result.map(|(name, entry_count)| Label { name, entry_count }))
+			.collect()
+	}
+
+	pub async fn get_labeled_entries(
+		&self,
+		user_id: &UserId,
+		label: &str,
+		options: &ListingOptions,
+		pagination: &EntryPagination,
+		display: &DateDisplay,
+	) -> Result<EntryPage> {
+		let page = self.persistence
+			.get_labeled_entries_for_user(user_id, label, options, pagination)
+			.await
+			.map(|page| convert_page(page, &pagination.position, display))?;
+		self.add_duplicate_feeds(user_id, page).await
+	}
+
+	/// Apply the given label to the given entries for the given user
+	pub async fn label_entries(
+		&self,
+		entry_ids: &Vec<EntryId>,
+		user_id: &UserId,
+		label: &str,
+	) -> Result<()> {
+		let label = normalize_name("Label", label)?;
+		for entry_id in entry_ids {
+			self.persistence.add_entry_label(user_id, entry_id, &label).await?;
+		}
+		Ok(())
+	}
+
+	/// Remove the given label from the given entries for the given user
+	pub async fn unlabel_entries(
+		&self,
+		entry_ids: &Vec<EntryId>,
+		user_id: &UserId,
+		label: &str,
+	) -> Result<()> {
+		let label = normalize_name("Label", label)?;
+		for entry_id in entry_ids {
+			self.persistence.remove_entry_label(user_id, entry_id, &label).await?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,173 @@
+//! Limits on failed logins. Each client IP address and each user name gets a
+//! token bucket of failed attempts, which refills over time; once either is
+//! empty, logins are refused until it refills. Past a few consecutive failures,
+//! each further one also doubles how long until the next attempt is allowed,
+//! and with a lockout threshold configured, that many consecutive failures lock
+//! the user name for a while.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+
+/// Consecutive failures allowed before they start delaying further attempts
+const FREE_FAILURES: u32 = 3;
+/// The delay after the first failure past [FREE_FAILURES], which doubles with
+/// each further one
+const BASE_DELAY: Duration = Duration::from_secs(1);
+/// The longest delay between attempts
+const MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// How many failed logins are allowed, and what happens after
+#[derive(Clone, Copy, Debug)]
+pub struct LoginLimits {
+	/// Failed attempts from one IP address allowed in a burst
+	pub attempts_per_ip: u32,
+	/// Failed attempts for one user name allowed in a burst
+	pub attempts_per_user: u32,
+	/// How long it takes to earn back one attempt
+	pub refill_interval: Duration,
+	/// Consecutive failures for one user name which lock it. 0 never locks.
+	pub lockout_threshold: u32,
+	pub lockout_duration: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Client {
+	Ip(String),
+	User(String),
+}
+
+#[derive(Debug)]
+struct Attempts {
+	/// Failed attempts left, fractionally refilled
+	tokens: f64,
+	refilled: Instant,
+	consecutive_failures: u32,
+	not_before: Instant,
+	locked_until: Option<Instant>,
+}
+
+/// Failed login attempts, per client. These are only kept in memory, so they
+/// reset when Russet restarts.
+pub struct LoginAttempts {
+	limits: LoginLimits,
+	attempts: Mutex<HashMap<Client, Attempts>>,
+}
+impl std::fmt::Debug for LoginAttempts {
+	// Leave out the attempts themselves, which would put every recent client's
+	// address in any log line including the domain service
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("LoginAttempts")
+			.field("limits", &self.limits)
+			.finish_non_exhaustive()
+	}
+}
+impl LoginAttempts {
+	pub fn new(limits: LoginLimits) -> LoginAttempts {
+		LoginAttempts { limits, attempts: Mutex::new(HashMap::new()) }
+	}
+
+	/// If logging in as `user_name` from `ip` isn't allowed yet, how long until
+	/// it is
+	pub fn check(&self, ip: Option<&str>, user_name: &str) -> Option<Duration> {
+		let now = Instant::now();
+		let mut attempts = self.attempts.lock().expect("login attempts lock shouldn't be poisoned");
+		Self::clients(ip, user_name)
+			.filter_map(|client| {
+				let capacity = self.capacity(&client);
+				let attempts = attempts.get_mut(&client)?;
+				self.refill(attempts, capacity, now);
+				let mut wait = attempts.not_before.saturating_duration_since(now);
+				if let Some(locked_until) = attempts.locked_until {
+					wait = wait.max(locked_until.saturating_duration_since(now));
+				}
+				if attempts.tokens < 1.0 {
+					wait = wait.max(self.limits.refill_interval.mul_f64(1.0 - attempts.tokens));
+				}
+				(!wait.is_zero()).then_some(wait)
+			} )
+			.max()
+	}
+
+	/// Record a failed login. Returns whether it locked the user name.
+	pub fn record_failure(&self, ip: Option<&str>, user_name: &str) -> bool {
+		let now = Instant::now();
+		let mut attempts = self.attempts.lock().expect("login attempts lock shouldn't be poisoned");
+		let mut locked = false;
+		for client in Self::clients(ip, user_name) {
+			let capacity = self.capacity(&client);
+			let attempts = attempts.entry(client.clone()).or_insert_with(|| Attempts {
+				tokens: capacity,
+				refilled: now,
+				consecutive_failures: 0,
+				not_before: now,
+				locked_until: None,
+			} );
+			self.refill(attempts, capacity, now);
+			attempts.tokens = (attempts.tokens - 1.0).max(0.0);
+			attempts.consecutive_failures += 1;
+			if let Some(doublings) = attempts.consecutive_failures.checked_sub(FREE_FAILURES + 1) {
+				let delay = BASE_DELAY
+					.checked_mul(2u32.saturating_pow(doublings))
+					.map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY));
+				attempts.not_before = now + delay;
+			}
+			if matches!(client, Client::User(_))
+					&& self.limits.lockout_threshold > 0
+					&& attempts.consecutive_failures >= self.limits.lockout_threshold {
+				attempts.locked_until = Some(now + self.limits.lockout_duration);
+				attempts.consecutive_failures = 0;
+				locked = true;
+			}
+		}
+		locked
+	}
+
+	/// Record a successful login, which ends the run of consecutive failures
+	/// from the IP address and for the user name. Their buckets still have to
+	/// refill.
+	pub fn record_success(&self, ip: Option<&str>, user_name: &str) {
+		let now = Instant::now();
+		let mut attempts = self.attempts.lock().expect("login attempts lock shouldn't be poisoned");
+		for client in Self::clients(ip, user_name) {
+			if let Some(attempts) = attempts.get_mut(&client) {
+				attempts.consecutive_failures = 0;
+				attempts.not_before = now;
+			}
+		}
+	}
+
+	/// Forget clients whose buckets have refilled and who aren't locked, so
+	/// attempts from many addresses don't pile up
+	pub fn prune(&self) {
+		let now = Instant::now();
+		let mut attempts = self.attempts.lock().expect("login attempts lock shouldn't be poisoned");
+		attempts.retain(|client, attempts| {
+			let capacity = self.capacity(client);
+			self.refill(attempts, capacity, now);
+			attempts.tokens < capacity
+				|| attempts.not_before > now
+				|| attempts.locked_until.is_some_and(|locked_until| locked_until > now)
+		} );
+	}
+
+	fn clients(ip: Option<&str>, user_name: &str) -> impl Iterator<Item = Client> {
+		ip.map(|ip| Client::Ip(ip.to_string()))
+			.into_iter()
+			.chain(std::iter::once(Client::User(user_name.to_string())))
+	}
+
+	fn capacity(&self, client: &Client) -> f64 {
+		match client {
+			Client::Ip(_) => self.limits.attempts_per_ip.into(),
+			Client::User(_) => self.limits.attempts_per_user.into(),
+		}
+	}
+
+	fn refill(&self, attempts: &mut Attempts, capacity: f64, now: Instant) {
+		let elapsed = now.saturating_duration_since(attempts.refilled);
+		let earned = elapsed.as_secs_f64() / self.limits.refill_interval.as_secs_f64().max(f64::MIN_POSITIVE);
+		attempts.tokens = (attempts.tokens + earned).min(capacity);
+		attempts.refilled = now;
+	}
+}
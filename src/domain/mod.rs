@@ -1,10 +1,26 @@
+pub mod duplicates;
 pub mod entries;
 pub mod feeds;
+pub mod folders;
+pub mod invitations;
+pub mod labels;
+pub mod login_limits;
 pub mod model;
+pub mod opml;
+pub mod retention;
+pub mod rules;
+pub mod search;
+pub mod subscriptions;
+pub mod two_factor;
 pub mod user;
 
+use crate::domain::feeds::credentials::CredentialCipher;
+use crate::domain::login_limits::{ LoginAttempts, LoginLimits };
+use crate::domain::two_factor::PendingLogins;
 use crate::feed::RussetFeedReader;
 use crate::Result;
+use getrandom::getrandom;
+use reqwest::Url;
 use std::time::Duration;
 
 pub struct RussetDomainService<Persistence>
@@ -12,21 +28,38 @@ where Persistence: std::fmt::Debug {
 	persistence: Persistence,
 	readers: Vec<Box<dyn RussetFeedReader>>,
 	pepper: Vec<u8>,
+	/// Made up afresh each time Russet starts, so that CSRF tokens for users
+	/// authenticated by a reverse proxy don't outlive the process
+	proxy_csrf_key: [u8; 32],
+	credential_cipher: Option<CredentialCipher>,
 	min_feed_check_interval: Duration,
 	pub default_feed_check_interval: Duration,
 	max_feed_check_interval: Duration,
 	disable_logins: bool,
+	login_attempts: LoginAttempts,
+	pending_logins: PendingLogins,
+	local_feed_sources: bool,
+	public_url: Option<Url>,
+	/// How many days read entries are kept, for feeds which don't say. 0
+	/// keeps them forever.
+	entry_retention_days: u32,
 }
 impl <Persistence> RussetDomainService<Persistence>
 where Persistence: std::fmt::Debug {
+	#[allow(clippy::too_many_arguments)]
 	pub fn new(
 		persistence: Persistence,
 		readers: Vec<Box<dyn RussetFeedReader>>,
 		pepper: Vec<u8>,
+		credential_cipher: Option<CredentialCipher>,
 		min_feed_check_interval: Duration,
 		default_feed_check_interval: Duration,
 		max_feed_check_interval: Duration,
 		disable_logins: bool,
+		login_limits: LoginLimits,
+		local_feed_sources: bool,
+		public_url: Option<Url>,
+		entry_retention_days: u32,
 	) -> Result<RussetDomainService<Persistence>> {
 		if min_feed_check_interval > default_feed_check_interval {
 			let min_interval = min_feed_check_interval.as_secs_f64();
@@ -40,14 +73,23 @@ where Persistence: std::fmt::Debug {
 			return Err(format!("Default check interval ${default_interval}s is \
 				greater than max interval ${max_interval}s").into());
 		}
+		let mut proxy_csrf_key = [0u8; 32];
+		getrandom(&mut proxy_csrf_key)?;
 		Ok(RussetDomainService {
 			persistence,
 			readers,
 			pepper,
+			proxy_csrf_key,
+			credential_cipher,
 			min_feed_check_interval,
 			default_feed_check_interval,
 			max_feed_check_interval,
 			disable_logins,
+			login_attempts: LoginAttempts::new(login_limits),
+			pending_logins: PendingLogins::default(),
+			local_feed_sources,
+			public_url,
+			entry_retention_days,
 		} )
 	}
 }
@@ -58,10 +100,36 @@ where Persistence: std::fmt::Debug {
 			.field("persistence", &self.persistence)
 			.field("readers", &self.readers)
 			.field("pepper", &"<redacted>")
+			.field("proxy_csrf_key", &"<redacted>")
+			.field("credential_cipher", &self.credential_cipher)
 			.field("min_feed_check_interval", &self.min_feed_check_interval)
 			.field("default_feed_check_interval", &self.default_feed_check_interval)
 			.field("max_feed_check_interval", &self.max_feed_check_interval)
 			.field("disable_logins", &self.disable_logins)
+			.field("login_attempts", &self.login_attempts)
+			.field("pending_logins", &self.pending_logins)
+			.field("local_feed_sources", &self.local_feed_sources)
+			.field("public_url", &self.public_url)
+			.field("entry_retention_days", &self.entry_retention_days)
 			.finish()
 	}
 }
+
+/// Longest user-chosen name (of a label or folder) we'll accept, in characters
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Trim a user-chosen name (`kind` being e.g. "Label") and make sure it's one
+/// we can store and link to
+fn normalize_name(kind: &str, name: &str) -> Result<String> {
+	let name = name.trim();
+	if name.is_empty() {
+		return Err(format!("{kind} names can't be empty").into())
+	}
+	if name.chars().count() > MAX_NAME_LENGTH {
+		return Err(format!("{kind} names can be at most {MAX_NAME_LENGTH} characters").into())
+	}
+	if name.chars().any(|c| c.is_control() || c == '/') {
+		return Err(format!("Invalid {} name {name:?}", kind.to_lowercase()).into())
+	}
+	Ok(name.to_string())
+}
@@ -1,9 +1,13 @@
-use crate::model::{ EntryId, FeedId };
+use crate::Result;
+use crate::model::{ EntryCursor, EntryId, FeedId, InvitationId, RuleAction, RuleField, RuleId, RuleMatch, UserType };
+use crate::persistence::model::Session;
+use std::time::Duration;
 
 pub struct Feed {
 	pub id: FeedId,
 	pub url: String,
 	pub title: String,
+	pub has_credentials: bool,
 }
 impl From<crate::persistence::model::Feed> for Feed {
 	fn from(value: crate::persistence::model::Feed) -> Self {
@@ -11,10 +15,17 @@ impl From<crate::persistence::model::Feed> for Feed {
 			id: value.id,
 			url: value.url.to_string(),
 			title: value.title,
+			has_credentials: value.credentials.is_some(),
 		}
 	}
 }
 
+/// A user's subscription to a feed
+pub struct Subscription {
+	pub feed: Feed,
+	pub folder: Option<String>,
+}
+
 pub struct Entry {
 	pub id: EntryId,
 	pub feed_id: FeedId,
@@ -22,5 +33,132 @@ pub struct Entry {
 	pub title: String,
 	pub article_date: String,
 	pub read: bool,
-	pub tombstone: bool,
+	pub starred: bool,
+	/// The user's other feeds with duplicates of this entry
+	pub also_in: Vec<DuplicateFeed>,
+}
+
+/// Another feed with a duplicate of an entry
+pub struct DuplicateFeed {
+	pub id: FeedId,
+	pub title: String,
+}
+
+/// A page of an entry listing
+pub struct EntryPage {
+	pub entries: Vec<Result<Entry>>,
+	pub info: PageInfo,
+}
+
+/// Where a page of an entry listing sits in the whole listing
+#[derive(Clone, Copy, Debug)]
+pub struct PageInfo {
+	/// How many entries the whole listing has, read or not
+	pub total: u64,
+	pub unread: u64,
+	/// The page's first entry, if there are entries before it
+	pub prev: Option<EntryCursor>,
+	/// The page's last entry, if there are entries after it
+	pub next: Option<EntryCursor>,
+}
+
+/// One of a user's entry labels
+#[derive(Clone, Debug)]
+pub struct Label {
+	pub name: String,
+	/// How many of the user's entries have this label
+	pub entry_count: u64,
+}
+
+/// What a rule matches and what it does, as entered by a user. See
+/// [crate::domain::rules].
+#[derive(Clone, Debug)]
+pub struct RuleDefinition {
+	/// The feed the rule applies to, or `None` for all the user's feeds
+	pub feed_id: Option<FeedId>,
+	pub field: RuleField,
+	pub match_type: RuleMatch,
+	pub pattern: String,
+	pub action: RuleAction,
+	/// The label to apply, for [RuleAction::Label]
+	pub label: Option<String>,
+}
+
+/// One of a user's rules for filtering entries
+pub struct Rule {
+	pub id: RuleId,
+	pub definition: RuleDefinition,
+}
+
+/// One of a user's logged in sessions
+pub struct SessionInfo {
+	/// Identifies the session without giving away its token
+	pub id: String,
+	/// Whether this is the session the user is looking at the list from
+	pub current: bool,
+	pub created: Option<String>,
+	pub last_used: Option<String>,
+	pub expiration: String,
+	pub ip: Option<String>,
+	pub user_agent: Option<String>,
+}
+
+/// An invitation to register, for sysops to keep track of
+pub struct InvitationInfo {
+	pub id: InvitationId,
+	pub user_type: UserType,
+	/// The name of the sysop who created it
+	pub created_by: String,
+	pub created: String,
+	pub expiration: Option<String>,
+	pub expired: bool,
+}
+
+/// What came of an attempt to log in
+#[derive(Debug)]
+pub enum LoginOutcome {
+	LoggedIn { session: Session, permanent_session: bool },
+	/// The password was right, but the user has two-factor authentication, so
+	/// the login has to be finished with a code and `token`
+	SecondFactorRequired { token: String },
+	/// Wrong user name or password, or logins aren't allowed
+	Failed,
+	/// There have been too many failed attempts from this client or for this
+	/// user
+	Blocked { retry_after: Duration },
+}
+
+/// What came of a change to a user's account which needs their current
+/// password
+#[derive(Debug)]
+pub enum ReauthOutcome<T> {
+	Done(T),
+	/// Wrong password (or two-factor code)
+	Failed,
+	/// There have been too many failed attempts for this user, whether here or
+	/// logging in
+	Blocked { retry_after: Duration },
+}
+
+/// Whether a user has two-factor authentication turned on
+#[derive(Clone, Copy, Debug)]
+pub struct TwoFactorStatus {
+	pub enabled: bool,
+	/// How many unused recovery codes they have left
+	pub recovery_codes: usize,
+}
+
+/// A new TOTP secret, for a user to set up their authenticator app with
+pub struct TotpEnrollment {
+	/// The secret in base32, for entering by hand
+	pub secret: String,
+	/// An SVG QR code of the `otpauth://` URI, for scanning
+	pub qr_code: String,
+}
+impl std::fmt::Debug for TotpEnrollment {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("TotpEnrollment")
+			.field("secret", &"<redacted>")
+			.finish_non_exhaustive()
+	}
 }
@@ -0,0 +1,124 @@
+//! OPML import and export of a user's subscriptions.
+//!
+//! Folders map to top-level outlines containing feed outlines. Feeds nested
+//! more deeply on import are filed in their top-level folder, since Russet's
+//! folders don't nest.
+
+use crate::domain::feeds::sources::FeedSource;
+use crate::domain::RussetDomainService;
+use crate::model::UserId;
+use crate::persistence::{ RussetEntryPersistenceLayer, RussetFeedPersistenceLayer, RussetUserPersistenceLayer };
+use crate::Result;
+use opml::{ Head, OPML, Outline };
+use reqwest::Url;
+use std::collections::BTreeMap;
+use tracing::warn;
+
+/// The outcome of importing one feed from an OPML document
+pub struct OpmlImport {
+	pub url: String,
+	pub folder: Option<String>,
+	/// Why the feed couldn't be imported, if it couldn't
+	pub error: Option<String>,
+}
+
+impl <Persistence> RussetDomainService<Persistence>
+where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer + RussetUserPersistenceLayer {
+	/// Export the given user's subscriptions as an OPML document
+	pub async fn export_opml(&self, user_id: &UserId) -> Result<String> {
+		let mut outlines = Vec::new();
+		let mut folders = BTreeMap::<String, Vec<Outline>>::new();
+		for subscription in self.get_subscriptions(user_id).await? {
+			let outline = Outline {
+				text: subscription.feed.title.clone(),
+				title: Some(subscription.feed.title),
+				r#type: Some("rss".to_string()),
+				xml_url: Some(subscription.feed.url),
+				..Outline::default()
+			};
+			match subscription.folder {
+				Some(folder) => folders.entry(folder).or_default().push(outline),
+				None => outlines.push(outline),
+			}
+		}
+		outlines.extend(folders.into_iter().map(|(folder, feeds)| Outline {
+			text: folder.clone(),
+			title: Some(folder),
+			outlines: feeds,
+			..Outline::default()
+		} ));
+		let mut opml = OPML {
+			head: Some(Head {
+				title: Some(format!("{} subscriptions", crate::APP_NAME)),
+				..Head::default()
+			} ),
+			..OPML::default()
+		};
+		opml.body.outlines = outlines;
+		Ok(opml.to_string()?)
+	}
+
+	/// Subscribe the given user to every feed in an OPML document, filing
+	/// them in folders to match its outline. Feeds that can't be added are
+	/// reported rather than failing the whole import.
+	pub async fn import_opml(
+		&self,
+		user_id: &UserId,
+		opml: &str,
+		allow_local_sources: bool,
+	) -> Result<Vec<OpmlImport>> {
+		let opml = OPML::from_str(opml)?;
+		let mut feeds = Vec::new();
+		for outline in &opml.body.outlines {
+			match &outline.xml_url {
+				Some(url) => feeds.push((url.clone(), None)),
+				None => {
+					let folder = outline.title.clone().unwrap_or_else(|| outline.text.clone());
+					collect_feeds(&outline.outlines, &folder, &mut feeds);
+				},
+			}
+		}
+		let mut results = Vec::new();
+		for (url, folder) in feeds {
+			let error = self
+				.import_feed(user_id, &url, folder.as_deref(), allow_local_sources)
+				.await
+				.err()
+				.map(|e| {
+					warn!(error = e.as_ref(), "Failed to import {url} from OPML");
+					e.to_string()
+				} );
+			results.push(OpmlImport { url, folder, error });
+		}
+		Ok(results)
+	}
+
+	async fn import_feed(
+		&self,
+		user_id: &UserId,
+		url: &str,
+		folder: Option<&str>,
+		allow_local_sources: bool,
+	) -> Result<()> {
+		let url = Url::parse(url)?;
+		if FeedSource::from_url(&url)?.is_local() && !allow_local_sources {
+			return Err("You may not subscribe to local feed sources".into())
+		}
+		let feed_id = self.add_feed(&url, None).await?;
+		self.subscribe(user_id, &feed_id).await?;
+		if let Some(folder) = folder {
+			self.set_folder(user_id, &feed_id, folder).await?;
+		}
+		Ok(())
+	}
+}
+
+/// Collect all the feeds under `outlines`, filing them in `folder`
+fn collect_feeds(outlines: &[Outline], folder: &str, feeds: &mut Vec<(String, Option<String>)>) {
+	for outline in outlines {
+		if let Some(url) = &outline.xml_url {
+			feeds.push((url.clone(), Some(folder.to_string())));
+		}
+		collect_feeds(&outline.outlines, folder, feeds);
+	}
+}
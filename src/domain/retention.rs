@@ -0,0 +1,50 @@
+//! Entry retention. Entries every subscriber has read and none has starred or
+//! labeled are pruned once they're older than their feed's retention period, or
+//! the configured default for feeds without one. Pruned entries' internal IDs are
+//! kept, so they aren't added again while they're still in their feed.
+
+use crate::domain::model::Feed;
+use crate::domain::RussetDomainService;
+use crate::model::{ FeedId, Timestamp };
+use crate::persistence::{ RussetEntryPersistenceLayer, RussetFeedPersistenceLayer };
+use crate::Result;
+
+/// The longest retention period we'll accept, in days
+pub const MAX_RETENTION_DAYS: u32 = 36_500;
+
+impl <Persistence> RussetDomainService<Persistence>
+where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer {
+	/// Prune old, read entries. Returns each feed entries were pruned from,
+	/// with how many; with `dry_run`, nothing is pruned, and it returns what
+	/// would have been.
+	pub async fn prune_entries(&self, dry_run: bool) -> Result<Vec<(Feed, u64)>> {
+		let counts = self.persistence
+			.prune_entries(self.entry_retention_days, &Timestamp::now(), dry_run)
+			.await?;
+		let mut pruned = Vec::with_capacity(counts.len());
+		for (feed_id, count) in counts {
+			pruned.push((self.persistence.get_feed(&feed_id).await?.into(), count));
+		}
+		Ok(pruned)
+	}
+
+	/// Get how many days the given feed's read entries are kept, if it
+	/// overrides the default
+	pub async fn get_feed_retention(&self, feed_id: &FeedId) -> Result<Option<u32>> {
+		self.persistence.get_feed_retention(feed_id).await
+	}
+
+	/// Set how many days the given feed's read entries are kept, or (with
+	/// `None`) go back to the default. 0 keeps them forever.
+	pub async fn set_feed_retention(&self, feed_id: &FeedId, retention_days: Option<u32>) -> Result<()> {
+		if retention_days.is_some_and(|days| days > MAX_RETENTION_DAYS) {
+			return Err(format!("Entries can be kept for at most {MAX_RETENTION_DAYS} days").into())
+		}
+		self.persistence.set_feed_retention(feed_id, retention_days).await
+	}
+
+	/// How many days read entries are kept by default. 0 keeps them forever.
+	pub fn default_entry_retention_days(&self) -> u32 {
+		self.entry_retention_days
+	}
+}
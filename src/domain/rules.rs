@@ -0,0 +1,377 @@
+//! Rules for filtering entries.
+//!
+//! A rule matches one field of an entry (its title, author, categories or URL)
+//! against a pattern, and applies an action to each entry it matches for the
+//! user who defined it: marking the entry read, deleting it, starring it or
+//! labeling it. A rule applies to one of its user's feeds, or all of them.
+//! Rules run on new entries as they're fetched, and on existing entries on
+//! demand.
+//!
+//! A pattern is either
+//!
+//! * keywords: a comma-separated list, matching if any of the keywords appears
+//!   in the field, ignoring case; or
+//! * a regular expression in the syntax of the [regex] crate, matching if it
+//!   matches anywhere in the field. Start it with `(?i)` to ignore case.
+//!
+//! Entries with no author or URL never match rules on those fields. Category
+//! rules match if any of the entry's categories match.
+
+use crate::domain::entries::{ convert_entry, entry_cursor };
+use crate::domain::model::{ Entry, Rule, RuleDefinition };
+use crate::domain::{ normalize_name, RussetDomainService };
+use crate::model::{ DateDisplay, EntryId, EntryPagination, FeedId, ListingOptions, MarkReadScope, PagePosition, RuleAction, RuleField, RuleId, RuleMatch, Timestamp, UserId };
+use crate::persistence::model::{ Entry as PersistenceEntry, Rule as PersistenceRule, UserEntry };
+use crate::persistence::{ RussetEntryPersistenceLayer, RussetFeedPersistenceLayer };
+use crate::Result;
+use regex::{ Regex, RegexBuilder };
+use std::collections::{ BTreeMap, HashMap };
+use tracing::warn;
+use ulid::Ulid;
+
+/// Longest pattern we'll accept, in characters
+const MAX_PATTERN_LENGTH: usize = 1024;
+
+/// Limit on the compiled size of regex patterns, in bytes
+const MAX_REGEX_SIZE: usize = 1 << 20;
+
+/// How many of the user's most recent entries a rule is tested against
+const TEST_ENTRY_COUNT: usize = 500;
+
+/// How many entries to load at a time when applying rules to existing entries
+const APPLY_PAGE_SIZE: usize = 500;
+
+impl <Persistence> RussetDomainService<Persistence>
+where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer {
+	/// Get the given user's rules, oldest first
+	pub async fn get_rules(&self, user_id: &UserId) -> Result<Vec<Rule>> {
+		self.persistence
+			.get_rules_for_user(user_id)
+			.await
+			.into_iter()
+			.map(|rule| rule.map(|rule| Rule {
+				id: rule.id,
+				definition: RuleDefinition {
+					feed_id: rule.feed_id,
+					field: rule.field,
+					match_type: rule.match_type,
+					pattern: rule.pattern,
+					action: rule.action,
+					label: rule.label,
+				},
+			} ))
+			.collect()
+	}
+
+	/// Add a rule for the given user. It applies to entries fetched from now
+	/// on; see [apply_rules](Self::apply_rules) for existing entries.
+	pub async fn add_rule(&self, user_id: &UserId, definition: &RuleDefinition) -> Result<RuleId> {
+		let rule = self.validate_rule(user_id, definition).await?;
+		self.persistence.add_rule(&rule).await?;
+		Ok(rule.id)
+	}
+
+	pub async fn delete_rule(&self, user_id: &UserId, rule_id: &RuleId) -> Result<()> {
+		self.persistence.delete_rule(user_id, rule_id).await
+	}
+
+	/// Find which of the given user's most recent entries a rule would match,
+	/// without saving it or applying it
+	pub async fn test_rule(
+		&self,
+		user_id: &UserId,
+		definition: &RuleDefinition,
+		display: &DateDisplay,
+	) -> Result<Vec<Entry>> {
+		let rule = CompiledRule::new(self.validate_rule(user_id, definition).await?)?;
+		let pagination = EntryPagination { position: PagePosition::First, page_size: TEST_ENTRY_COUNT };
+		let page = self.persistence
+			.get_entries_for_user(user_id, &ListingOptions::default(), &pagination)
+			.await?;
+		page.entries
+			.into_iter()
+			.filter(|result| result.as_ref().map_or(true, |(entry, user_entry)| {
+				!is_deleted(user_entry.as_ref()) && rule.matches(entry)
+			} ))
+			.map(|result| result.map(|(entry, user_entry)| convert_entry(entry, user_entry, display)))
+			.collect()
+	}
+
+	/// Apply all the given user's rules to all their existing entries.
+	/// Returns how many entries the rules acted on.
+	pub async fn apply_rules(&self, user_id: &UserId) -> Result<usize> {
+		self.apply_user_rules(user_id, None).await
+	}
+
+	/// Apply the given user's rules to the existing entries in one feed.
+	/// Returns how many entries the rules acted on.
+	pub async fn apply_rules_to_feed(&self, user_id: &UserId, feed_id: &FeedId) -> Result<usize> {
+		self.apply_user_rules(user_id, Some(feed_id)).await
+	}
+
+	async fn apply_user_rules(&self, user_id: &UserId, feed_id: Option<&FeedId>) -> Result<usize> {
+		let rules = self.persistence
+			.get_rules_for_user(user_id)
+			.await
+			.into_iter()
+			.filter_map(compile_stored_rule)
+			.collect::<Vec<CompiledRule>>();
+		if rules.is_empty() {
+			return Ok(0)
+		}
+		let mut effects = RuleEffects::default();
+		let mut position = PagePosition::First;
+		loop {
+			let pagination = EntryPagination { position, page_size: APPLY_PAGE_SIZE };
+			let page = match feed_id {
+				Some(feed_id) => self.persistence
+					.get_entries_for_user_feed(user_id, feed_id, &ListingOptions::default(), &pagination)
+					.await?,
+				None => self.persistence
+					.get_entries_for_user(user_id, &ListingOptions::default(), &pagination)
+					.await?,
+			};
+			let entries = page.entries
+				.into_iter()
+				.collect::<Result<Vec<(PersistenceEntry, Option<UserEntry>)>>>()?;
+			for (entry, user_entry) in &entries {
+				for rule in &rules {
+					if rule.matches(entry) {
+						effects.add(&rule.rule, entry.id, user_entry.as_ref());
+					}
+				}
+			}
+			match entries.last() {
+				Some((entry, _)) if page.more => position = PagePosition::After(entry_cursor(entry)),
+				_ => break,
+			}
+		}
+		let changed = effects.entry_count();
+		self.apply_effects(user_id, effects).await?;
+		Ok(changed)
+	}
+
+	/// Apply the rules of all the given feed's subscribers to newly-added
+	/// entries from it
+	pub(super) async fn apply_rules_to_new_entries(&self, feed_id: &FeedId, entries: &[PersistenceEntry]) -> Result<()> {
+		if entries.is_empty() {
+			return Ok(())
+		}
+		let rules = self.persistence
+			.get_rules_for_feed(feed_id)
+			.await
+			.into_iter()
+			.filter_map(compile_stored_rule)
+			.collect::<Vec<CompiledRule>>();
+		let mut effects = HashMap::<UserId, RuleEffects>::new();
+		for entry in entries {
+			for rule in &rules {
+				if rule.matches(entry) {
+					effects
+						.entry(rule.rule.user_id)
+						.or_default()
+						.add(&rule.rule, entry.id, None);
+				}
+			}
+		}
+		for (user_id, effects) in effects {
+			self.apply_effects(&user_id, effects).await?;
+		}
+		Ok(())
+	}
+
+	/// Check a rule definition is valid for the given user, and build the rule
+	async fn validate_rule(&self, user_id: &UserId, definition: &RuleDefinition) -> Result<PersistenceRule> {
+		let pattern = definition.pattern.trim();
+		if pattern.is_empty() {
+			return Err("Rules need a pattern to match".into())
+		}
+		if pattern.chars().count() > MAX_PATTERN_LENGTH {
+			return Err(format!("Patterns can be at most {MAX_PATTERN_LENGTH} characters").into())
+		}
+		Pattern::new(definition.match_type, pattern)?;
+		let label = match definition.action {
+			RuleAction::Label => {
+				let label = definition.label
+					.as_deref()
+					.ok_or("Labeling rules need a label to apply")?;
+				Some(normalize_name("Label", label)?)
+			},
+			_ => None,
+		};
+		if let Some(feed_id) = definition.feed_id {
+			let subscribed = self.persistence
+				.get_subscribed_feeds(user_id)
+				.await
+				.into_iter()
+				.any(|feed| feed.is_ok_and(|feed| feed.id == feed_id));
+			if !subscribed {
+				return Err(format!("Not subscribed to feed {feed_id:?}").into())
+			}
+		}
+		Ok(PersistenceRule {
+			id: RuleId(Ulid::new()),
+			user_id: *user_id,
+			feed_id: definition.feed_id,
+			field: definition.field,
+			match_type: definition.match_type,
+			pattern: pattern.to_string(),
+			action: definition.action,
+			label,
+		} )
+	}
+
+	async fn apply_effects(&self, user_id: &UserId, effects: RuleEffects) -> Result<()> {
+		let now = Timestamp::now();
+		if !effects.read.is_empty() {
+			let scope = MarkReadScope { entry_ids: Some(effects.read), ..Default::default() };
+			self.persistence.mark_entries_read(user_id, &scope, &now).await?;
+		}
+		if !effects.deleted.is_empty() {
+			self.persistence.delete_entries(user_id, &effects.deleted, &now).await?;
+		}
+		for entry_id in &effects.starred {
+			self.persistence.set_userentry_starred(entry_id, user_id, Some(&now)).await?;
+		}
+		for (label, entry_ids) in &effects.labels {
+			for entry_id in entry_ids {
+				self.persistence.add_entry_label(user_id, entry_id, label).await?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// A rule with its pattern compiled, ready to match entries
+struct CompiledRule {
+	rule: PersistenceRule,
+	pattern: Pattern,
+}
+impl CompiledRule {
+	fn new(rule: PersistenceRule) -> Result<CompiledRule> {
+		let pattern = Pattern::new(rule.match_type, &rule.pattern)?;
+		Ok(CompiledRule { rule, pattern })
+	}
+
+	fn matches(&self, entry: &PersistenceEntry) -> bool {
+		if self.rule.feed_id.is_some_and(|feed_id| feed_id != entry.feed_id) {
+			return false
+		}
+		match self.rule.field {
+			RuleField::Title => self.pattern.is_match(&entry.title),
+			RuleField::Author => entry.author
+				.as_deref()
+				.is_some_and(|author| self.pattern.is_match(author)),
+			RuleField::Category => entry.categories
+				.iter()
+				.any(|category| self.pattern.is_match(category)),
+			RuleField::Url => entry.url
+				.as_ref()
+				.is_some_and(|url| self.pattern.is_match(url.as_str())),
+		}
+	}
+}
+
+/// Compile a rule loaded from the persistence layer, skipping it (with a
+/// warning) if it can't be loaded or compiled
+fn compile_stored_rule(rule: Result<PersistenceRule>) -> Option<CompiledRule> {
+	match rule.and_then(CompiledRule::new) {
+		Ok(rule) => Some(rule),
+		Err(e) => {
+			warn!(error = e.as_ref(), "Skipping invalid rule");
+			None
+		},
+	}
+}
+
+enum Pattern {
+	/// Lowercased keywords, any of which may match
+	Keywords(Vec<String>),
+	Regex(Regex),
+}
+impl Pattern {
+	fn new(match_type: RuleMatch, pattern: &str) -> Result<Pattern> {
+		match match_type {
+			RuleMatch::Keywords => {
+				let keywords = pattern
+					.split(',')
+					.map(|keyword| keyword.trim().to_lowercase())
+					.filter(|keyword| !keyword.is_empty())
+					.collect::<Vec<String>>();
+				if keywords.is_empty() {
+					return Err("Keyword patterns need at least one keyword".into())
+				}
+				Ok(Pattern::Keywords(keywords))
+			},
+			RuleMatch::Regex => RegexBuilder::new(pattern)
+				.size_limit(MAX_REGEX_SIZE)
+				.build()
+				.map(Pattern::Regex)
+				.map_err(|e| format!("Invalid regular expression: {e}").into()),
+		}
+	}
+
+	fn is_match(&self, text: &str) -> bool {
+		match self {
+			Pattern::Keywords(keywords) => {
+				let text = text.to_lowercase();
+				keywords.iter().any(|keyword| text.contains(keyword.as_str()))
+			},
+			Pattern::Regex(regex) => regex.is_match(text),
+		}
+	}
+}
+
+/// The changes rules call for to one user's entries, grouped by action so
+/// each can be applied in bulk
+#[derive(Default)]
+struct RuleEffects {
+	read: Vec<EntryId>,
+	deleted: Vec<EntryId>,
+	starred: Vec<EntryId>,
+	labels: BTreeMap<String, Vec<EntryId>>,
+}
+impl RuleEffects {
+	/// Record the effect of `rule` on an entry, unless the entry's
+	/// `user_entry` shows it's already had that effect
+	fn add(&mut self, rule: &PersistenceRule, entry_id: EntryId, user_entry: Option<&UserEntry>) {
+		if is_deleted(user_entry) {
+			return
+		}
+		match rule.action {
+			RuleAction::MarkRead => {
+				if user_entry.is_none_or(|user_entry| user_entry.read.is_none()) {
+					self.read.push(entry_id);
+				}
+			},
+			RuleAction::Delete => self.deleted.push(entry_id),
+			RuleAction::Star => {
+				if user_entry.is_none_or(|user_entry| user_entry.starred.is_none()) {
+					self.starred.push(entry_id);
+				}
+			},
+			RuleAction::Label => {
+				if let Some(label) = &rule.label {
+					self.labels.entry(label.clone()).or_default().push(entry_id);
+				}
+			},
+		}
+	}
+
+	/// How many distinct entries these effects apply to
+	fn entry_count(&self) -> usize {
+		let mut entry_ids = self.read.iter()
+			.chain(&self.deleted)
+			.chain(&self.starred)
+			.chain(self.labels.values().flatten())
+			.map(|entry_id| entry_id.0)
+			.collect::<Vec<Ulid>>();
+		entry_ids.sort();
+		entry_ids.dedup();
+		entry_ids.len()
+	}
+}
+
+fn is_deleted(user_entry: Option<&UserEntry>) -> bool {
+	user_entry.is_some_and(|user_entry| user_entry.tombstone.is_some())
+}
@@ -0,0 +1,103 @@
+//! Entry search.
+//!
+//! A search query is a list of space-separated terms, all of which must match:
+//!
+//! * `word` or `"a phrase"` must appear in the entry's title
+//! * `feed:name` or `feed:"feed name"` restricts results to feeds whose titles
+//!   contain the given text
+//! * `before:YYYY-MM-DD` and `after:YYYY-MM-DD` restrict results to entries
+//!   dated before the given day, or on or after it
+//! * `is:unread` restricts results to unread entries
+//!
+//! Dates are in UTC.
+
+use chrono::NaiveDate;
+use crate::domain::entries::convert_entry;
+use crate::domain::model::Entry;
+use crate::domain::RussetDomainService;
+use crate::model::{ DateDisplay, Pagination, SearchQuery, Timestamp, UserId };
+use crate::persistence::RussetEntryPersistenceLayer;
+use crate::Result;
+
+impl <Persistence> RussetDomainService<Persistence>
+where Persistence: RussetEntryPersistenceLayer {
+	/// Search the given user's subscribed entries
+	pub async fn search_entries(
+		&self,
+		user_id: &UserId,
+		query: &SearchQuery,
+		pagination: &Pagination,
+		display: &DateDisplay,
+	) -> impl IntoIterator<Item = Result<Entry>> {
+		self.persistence
+			.search_entries(user_id, query, pagination)
+			.await
+			.into_iter()
+			.map(|result| result.map(|(entry, user_entry)| convert_entry(entry, user_entry, display)))
+			.collect::<Vec<Result<Entry>>>()
+	}
+}
+
+/// Parse a search query string (see the [module docs](self) for the syntax)
+pub fn parse_search_query(query: &str) -> Result<SearchQuery> {
+	let mut search = SearchQuery::default();
+	for token in tokenize(query) {
+		let operator = token
+			.split_once(':')
+			.filter(|(operator, _)| !operator.contains('"'));
+		match operator {
+			Some(("feed", feed)) => search.feed = Some(unquote(feed)),
+			Some(("before", date)) => search.before = Some(parse_date(date)?),
+			Some(("after", date)) => search.after = Some(parse_date(date)?),
+			Some(("is", "unread")) => search.unread_only = true,
+			Some(("is", value)) => return Err(format!("Unknown search filter is:{value}").into()),
+			_ => {
+				let term = unquote(&token);
+				if !term.is_empty() {
+					search.terms.push(term);
+				}
+			},
+		}
+	}
+	Ok(search)
+}
+
+/// Split a query on whitespace, except within double quotes
+fn tokenize(query: &str) -> Vec<String> {
+	let mut tokens = Vec::new();
+	let mut token = String::new();
+	let mut quoted = false;
+	for c in query.chars() {
+		match c {
+			'"' => {
+				quoted = !quoted;
+				token.push(c);
+			},
+			c if c.is_whitespace() && !quoted => {
+				if !token.is_empty() {
+					tokens.push(std::mem::take(&mut token));
+				}
+			},
+			c => token.push(c),
+		}
+	}
+	if !token.is_empty() {
+		tokens.push(token);
+	}
+	tokens
+}
+
+fn unquote(token: &str) -> String {
+	token.replace('"', "").trim().to_string()
+}
+
+/// Parse a `YYYY-MM-DD` date as midnight UTC at its start
+pub fn parse_date(date: &str) -> Result<Timestamp> {
+	let date = NaiveDate::parse_from_str(&unquote(date), "%Y-%m-%d")
+		.map_err(|_| format!("Could not parse date {date:?}; dates should look like 2024-01-31"))?;
+	let date = date
+		.and_hms_opt(0, 0, 0)
+		.expect("midnight is a valid time")
+		.and_utc();
+	Ok(Timestamp::new(date.into()))
+}
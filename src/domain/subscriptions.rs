@@ -0,0 +1,125 @@
+//! Managing a user's subscriptions, with statistics about their feeds.
+
+use chrono::{ DateTime, Utc };
+use chrono_tz::Tz;
+use crate::domain::model::Feed;
+use crate::domain::RussetDomainService;
+use crate::model::{ FeedId, Timestamp, UserId };
+use crate::persistence::model::SubscriptionStats;
+use crate::persistence::{ RussetEntryPersistenceLayer, RussetFeedPersistenceLayer, RussetUserPersistenceLayer };
+use crate::Result;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::time::Duration;
+
+/// How many weeks of entries to average over for the posting rate
+const RATE_WEEKS: u32 = 4;
+const WEEK: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A user's subscription to a feed, with statistics about the feed
+pub struct SubscriptionSummary {
+	pub feed: Feed,
+	pub folder: Option<String>,
+	pub unread_count: u64,
+	/// Mean entries per week over the last few weeks
+	pub entries_per_week: f64,
+	/// When the feed was last checked without error
+	pub last_success: Option<String>,
+	/// When the feed is next due to be checked
+	pub next_check: Option<String>,
+	/// Why the latest check failed, if it did
+	pub error: Option<String>,
+}
+
+/// Orderings for [SubscriptionSummary]s. Feeds that tie are ordered by title.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionSort {
+	#[default]
+	Title,
+	/// By folder, with feeds not in a folder last
+	Folder,
+	/// Most unread entries first
+	Unread,
+	/// Least recently successful first, so stale feeds stand out
+	LastSuccess,
+	/// Soonest next check first
+	NextCheck,
+	/// Busiest feeds first
+	Rate,
+	/// Feeds whose latest check failed first
+	Error,
+}
+
+impl <Persistence> RussetDomainService<Persistence>
+where Persistence: RussetEntryPersistenceLayer + RussetFeedPersistenceLayer + RussetUserPersistenceLayer {
+	/// Get the given user's subscriptions with statistics about each feed
+	pub async fn get_subscription_summaries(
+		&self,
+		user_id: &UserId,
+		sort: SubscriptionSort,
+		timezone: Tz,
+	) -> Result<Vec<SubscriptionSummary>> {
+		let recent_since = Timestamp::new(Timestamp::now().0 - WEEK * RATE_WEEKS);
+		let mut stats = self.persistence
+			.get_subscription_stats(user_id, &recent_since)
+			.await
+			.into_iter()
+			.collect::<Result<Vec<SubscriptionStats>>>()?;
+		// Sorts are stable, so sorting by title first breaks every tie by it
+		stats.sort_by_cached_key(|stats| stats.feed.title.to_lowercase());
+		match sort {
+			SubscriptionSort::Title => (),
+			SubscriptionSort::Folder => stats.sort_by(|a, b| {
+				(a.folder.is_none(), &a.folder).cmp(&(b.folder.is_none(), &b.folder))
+			} ),
+			SubscriptionSort::Unread => stats.sort_by_key(|stats| Reverse(stats.unread_count)),
+			SubscriptionSort::LastSuccess => stats.sort_by_key(|stats| stats.last_success),
+			SubscriptionSort::NextCheck => stats.sort_by_key(|stats| {
+				stats.last_check.as_ref().map(|check| check.next_check_time)
+			} ),
+			SubscriptionSort::Rate => stats.sort_by_key(|stats| Reverse(stats.recent_entries)),
+			SubscriptionSort::Error => stats.sort_by_key(|stats| {
+				stats.last_check.as_ref().is_none_or(|check| check.error.is_none())
+			} ),
+		}
+		Ok(stats.into_iter().map(|stats| convert_stats(stats, timezone)).collect())
+	}
+
+	/// Subscribe the given user to the given feed. The feed's existing entries
+	/// are new to the user, so their rules are applied to them.
+	pub async fn subscribe(&self, user_id: &UserId, feed_id: &FeedId) -> Result<()> {
+		self.persistence.add_subscription(user_id, feed_id).await?;
+		self.apply_rules_to_feed(user_id, feed_id).await?;
+		Ok(())
+	}
+
+	/// Unsubscribe the given user from each of the given feeds
+	pub async fn unsubscribe_all(&self, user_id: &UserId, feed_ids: &[FeedId]) -> Result<()> {
+		for feed_id in feed_ids {
+			self.unsubscribe(user_id, feed_id).await?;
+		}
+		Ok(())
+	}
+}
+
+fn convert_stats(stats: SubscriptionStats, tz: Tz) -> SubscriptionSummary {
+	let (next_check, error) = match stats.last_check {
+		Some(check) => (Some(format_timestamp(check.next_check_time, tz)), check.error),
+		None => (None, None),
+	};
+	SubscriptionSummary {
+		feed: stats.feed.into(),
+		folder: stats.folder,
+		unread_count: stats.unread_count,
+		entries_per_week: stats.recent_entries as f64 / f64::from(RATE_WEEKS),
+		last_success: stats.last_success.map(|time| format_timestamp(time, tz)),
+		next_check,
+		error,
+	}
+}
+
+fn format_timestamp(timestamp: Timestamp, tz: Tz) -> String {
+	let time: DateTime<Utc> = timestamp.0.into();
+	time.with_timezone(&tz).format("%Y-%m-%d %H:%M").to_string()
+}
@@ -0,0 +1,325 @@
+//! Two-factor authentication. Users can set up a TOTP authenticator (RFC
+//! 6238, in the common SHA-1, six digit, 30 second flavor), after which logging
+//! in takes a code from it as well as their password. Each user also gets
+//! one-time recovery codes, for when they've lost their authenticator.
+
+use base32ct::{ Base32Unpadded, Base32Upper, Encoding };
+use crate::domain::model::{ LoginOutcome, ReauthOutcome, TotpEnrollment, TwoFactorStatus };
+use crate::domain::RussetDomainService;
+use crate::model::UserId;
+use crate::persistence::model::{ PasswordHash, SessionClient, Totp, User };
+use crate::persistence::RussetUserPersistenceLayer;
+use crate::Err;
+use crate::Result;
+use getrandom::getrandom;
+use hmac::{ Hmac, Mac };
+use percent_encoding::{ NON_ALPHANUMERIC, utf8_percent_encode };
+use qrcode::QrCode;
+use qrcode::render::svg;
+use sha1::Sha1;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{ Duration, Instant, SystemTime };
+use tracing::{ info, warn };
+
+/// How long each TOTP code lasts
+const TOTP_STEP: u64 = 30;
+const TOTP_DIGITS: usize = 6;
+/// How many steps either side of the current one codes are accepted from, to
+/// allow for clocks being a little off
+const TOTP_SKEW: u64 = 1;
+/// Length of TOTP secrets, as RFC 4226 recommends for HMAC-SHA-1
+const SECRET_BYTES: usize = 20;
+/// How many recovery codes each user gets
+const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+/// How long a user has to give their second factor after their password
+const PENDING_LOGIN_DURATION: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, Debug)]
+struct PendingLogin {
+	user_id: UserId,
+	user_name: String,
+	permanent_session: bool,
+	expires: Instant,
+}
+
+/// Logins whose password was right, waiting for a second factor, by token.
+/// These are only kept in memory, so a restart means starting those logins
+/// over.
+#[derive(Default)]
+pub struct PendingLogins {
+	logins: Mutex<HashMap<String, PendingLogin>>,
+}
+impl PendingLogins {
+	/// Start a login for the given user, returning the token to finish it with
+	pub fn start(&self, user: &User, permanent_session: bool) -> Result<String> {
+		let mut token = [0u8; 32];
+		getrandom(&mut token)?;
+		let token = Base32Unpadded::encode_string(&token);
+		let login = PendingLogin {
+			user_id: user.id,
+			user_name: user.name.clone(),
+			permanent_session,
+			expires: Instant::now() + PENDING_LOGIN_DURATION,
+		};
+		self.logins.lock().expect("pending logins lock shouldn't be poisoned").insert(token.clone(), login);
+		Ok(token)
+	}
+
+	fn get(&self, token: &str) -> Option<PendingLogin> {
+		self.logins
+			.lock()
+			.expect("pending logins lock shouldn't be poisoned")
+			.get(token)
+			.filter(|login| login.expires > Instant::now())
+			.cloned()
+	}
+
+	fn finish(&self, token: &str) {
+		self.logins.lock().expect("pending logins lock shouldn't be poisoned").remove(token);
+	}
+
+	/// Forget logins nobody finished in time
+	pub fn prune(&self) {
+		let now = Instant::now();
+		self.logins
+			.lock()
+			.expect("pending logins lock shouldn't be poisoned")
+			.retain(|_, login| login.expires > now);
+	}
+}
+impl std::fmt::Debug for PendingLogins {
+	// Leave out the tokens, which would let whoever read the log finish the
+	// logins
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("PendingLogins").finish_non_exhaustive()
+	}
+}
+
+impl <Persistence> RussetDomainService<Persistence>
+where Persistence: RussetUserPersistenceLayer {
+
+	/// Finish a login which [login_user](Self::login_user) said needs a second
+	/// factor, with a TOTP code or a recovery code
+	pub async fn login_second_factor(
+		&self,
+		token: &str,
+		code: &str,
+		client: SessionClient,
+	) -> Result<LoginOutcome> {
+		if self.disable_logins { return Ok(LoginOutcome::Failed) }
+		let Some(login) = self.pending_logins.get(token) else {
+			info!("Second factor for an unknown or expired login");
+			return Ok(LoginOutcome::Failed)
+		};
+		let ip = client.ip.clone();
+		if let Some(retry_after) = self.login_attempts.check(ip.as_deref(), &login.user_name) {
+			warn!(
+				"Blocked second factor for {:?} from {:?}; allowed again in {:.1?}",
+				login.user_name,
+				ip,
+				retry_after,
+			);
+			return Ok(LoginOutcome::Blocked { retry_after })
+		}
+		if !self.check_second_factor(&login.user_id, code).await? {
+			info!("Bad second factor for {:?} from {:?}", login.user_name, ip);
+			if self.login_attempts.record_failure(ip.as_deref(), &login.user_name) {
+				warn!("Too many failed logins for {:?}; locking it", login.user_name);
+			}
+			return Ok(LoginOutcome::SecondFactorRequired { token: token.to_string() })
+		}
+		self.pending_logins.finish(token);
+		self.login_attempts.record_success(ip.as_deref(), &login.user_name);
+		let user = self.persistence.get_user(&login.user_id).await?;
+		if user.disabled {
+			info!("Disabled user {:?} tried to log in", user.name);
+			return Ok(LoginOutcome::Failed)
+		}
+		let session = self.start_session(&user, login.permanent_session, client).await?;
+		Ok(LoginOutcome::LoggedIn { session, permanent_session: login.permanent_session })
+	}
+
+	pub async fn two_factor_status(&self, user_id: &UserId) -> Result<TwoFactorStatus> {
+		let enabled = self.persistence.get_totp(user_id).await?.is_some();
+		let recovery_codes = self.persistence.get_recovery_codes(user_id).await?.len();
+		Ok(TwoFactorStatus { enabled, recovery_codes })
+	}
+
+	/// A new TOTP secret for the given user to set up their authenticator
+	/// with. Nothing is saved until they confirm it with
+	/// [enable_totp](Self::enable_totp).
+	pub fn new_totp_enrollment(&self, user: &User) -> Result<TotpEnrollment> {
+		let mut secret = [0u8; SECRET_BYTES];
+		getrandom(&mut secret)?;
+		self.totp_enrollment(user, &Base32Upper::encode_string(&secret))
+	}
+
+	/// The given TOTP secret for the given user, from
+	/// [new_totp_enrollment](Self::new_totp_enrollment), to show again
+	pub fn totp_enrollment(&self, user: &User, secret: &str) -> Result<TotpEnrollment> {
+		decode_secret(secret)?;
+		let issuer = utf8_percent_encode(crate::APP_NAME, NON_ALPHANUMERIC);
+		let account = utf8_percent_encode(&user.name, NON_ALPHANUMERIC);
+		let uri = format!("otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}");
+		let qr_code = QrCode::new(uri.as_bytes())?
+			.render::<svg::Color>()
+			.min_dimensions(200, 200)
+			.build();
+		Ok(TotpEnrollment { secret: secret.to_string(), qr_code })
+	}
+
+	/// Turn on two-factor authentication for the given user with the given
+	/// secret from [new_totp_enrollment](Self::new_totp_enrollment), if
+	/// `password` is theirs and `code` shows their authenticator has the
+	/// secret. Returns their new recovery codes.
+	pub async fn enable_totp(
+		&self,
+		user_id: &UserId,
+		password: &str,
+		secret: &str,
+		code: &str,
+	) -> Result<ReauthOutcome<Vec<String>>> {
+		let user = self.persistence.get_user(user_id).await?;
+		if let Some(outcome) = self.check_current_password(&user, password, "enabling two-factor authentication")? {
+			return Ok(outcome)
+		}
+		let secret_bytes = decode_secret(secret)?;
+		let Some(step) = matching_step(&secret_bytes, &normalize_code(code), 0) else {
+			info!("Bad code enabling two-factor authentication for {:?}", user.name);
+			return Ok(ReauthOutcome::Failed)
+		};
+		let (recovery_codes, recovery_code_hashes) = self.generate_recovery_codes()?;
+		let totp = Totp { secret: secret.to_string(), last_step: step };
+		self.persistence.set_totp(user_id, &totp, &recovery_code_hashes).await?;
+		info!("Enabled two-factor authentication for {:?}", user.name);
+		Ok(ReauthOutcome::Done(recovery_codes))
+	}
+
+	/// Replace the given user's recovery codes with new ones, if `password` is
+	/// theirs. Returns the new codes.
+	pub async fn regenerate_recovery_codes(
+		&self,
+		user_id: &UserId,
+		password: &str,
+	) -> Result<ReauthOutcome<Vec<String>>> {
+		let user = self.persistence.get_user(user_id).await?;
+		if self.persistence.get_totp(user_id).await?.is_none() {
+			return Err("Two-factor authentication isn't enabled".into())
+		}
+		if let Some(outcome) = self.check_current_password(&user, password, "regenerating recovery codes")? {
+			return Ok(outcome)
+		}
+		let (recovery_codes, recovery_code_hashes) = self.generate_recovery_codes()?;
+		self.persistence.set_recovery_codes(user_id, &recovery_code_hashes).await?;
+		info!("Regenerated recovery codes for {:?}", user.name);
+		Ok(ReauthOutcome::Done(recovery_codes))
+	}
+
+	/// Turn off two-factor authentication for the given user, if `password` is
+	/// theirs
+	pub async fn disable_totp(&self, user_id: &UserId, password: &str) -> Result<ReauthOutcome<()>> {
+		let user = self.persistence.get_user(user_id).await?;
+		if let Some(outcome) = self.check_current_password(&user, password, "disabling two-factor authentication")? {
+			return Ok(outcome)
+		}
+		self.persistence.delete_totp(user_id).await?;
+		info!("Disabled two-factor authentication for {:?}", user.name);
+		Ok(ReauthOutcome::Done(()))
+	}
+
+	/// Turn off two-factor authentication for a user who's lost their
+	/// authenticator and recovery codes
+	pub async fn reset_two_factor(&self, user_name: &str) -> Result<()> {
+		let user = self.persistence
+			.get_user_by_name(user_name)
+			.await?
+			.ok_or_else(|| -> Err { format!("No such user {user_name}").into() })?;
+		self.persistence.delete_totp(&user.id).await?;
+		info!("Reset two-factor authentication for {:?}", user.name);
+		Ok(())
+	}
+
+	/// Check a TOTP or recovery code for the given user, using it up if it's
+	/// right
+	async fn check_second_factor(&self, user_id: &UserId, code: &str) -> Result<bool> {
+		let code = normalize_code(code);
+		if code.len() == TOTP_DIGITS && code.chars().all(|c| c.is_ascii_digit()) {
+			let Some(totp) = self.persistence.get_totp(user_id).await? else { return Ok(false) };
+			let secret = decode_secret(&totp.secret)?;
+			match matching_step(&secret, &code, totp.last_step) {
+				// Another login could have used the code in the meantime
+				Some(step) => self.persistence.use_totp_step(user_id, step).await,
+				None => Ok(false),
+			}
+		} else {
+			for recovery_code in self.persistence.get_recovery_codes(user_id).await? {
+				if self.verify_password_hash(&recovery_code, &code)? {
+					info!("Recovery code used for {:?}", user_id);
+					return self.persistence.delete_recovery_code(user_id, &recovery_code).await
+				}
+			}
+			Ok(false)
+		}
+	}
+
+	/// Some new recovery codes, and their hashes to save
+	fn generate_recovery_codes(&self) -> Result<(Vec<String>, Vec<PasswordHash>)> {
+		let mut codes = Vec::with_capacity(RECOVERY_CODES);
+		let mut hashes = Vec::with_capacity(RECOVERY_CODES);
+		for _ in 0..RECOVERY_CODES {
+			let mut bytes = [0u8; RECOVERY_CODE_LENGTH];
+			getrandom(&mut bytes)?;
+			let code = bytes
+				.iter()
+				.map(|byte| char::from(RECOVERY_CODE_ALPHABET[usize::from(*byte) % RECOVERY_CODE_ALPHABET.len()]))
+				.collect::<String>();
+			hashes.push(self.hash_password(&code)?);
+			// Split in two, to be easier to copy out
+			let (first, second) = code.split_at(RECOVERY_CODE_LENGTH / 2);
+			codes.push(format!("{first}-{second}"));
+		}
+		Ok((codes, hashes))
+	}
+}
+
+/// Strip the spaces authenticators show codes with, and the dash recovery
+/// codes are shown with
+fn normalize_code(code: &str) -> String {
+	code.chars()
+		.filter(|c| !c.is_whitespace() && *c != '-')
+		.collect::<String>()
+		.to_lowercase()
+}
+
+fn decode_secret(secret: &str) -> Result<Vec<u8>> {
+	let secret = Base32Upper::decode_vec(secret).map_err(|_| -> Err { "Malformed TOTP secret".into() })?;
+	if secret.len() != SECRET_BYTES {
+		return Err("Malformed TOTP secret".into())
+	}
+	Ok(secret)
+}
+
+/// The time step near now for which `code` is the code, if there's one after
+/// `last_step`
+fn matching_step(secret: &[u8], code: &str, last_step: u64) -> Option<u64> {
+	let now = SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.ok()?
+		.as_secs() / TOTP_STEP;
+	(now.saturating_sub(TOTP_SKEW)..=now + TOTP_SKEW)
+		.filter(|step| *step > last_step)
+		.find(|step| totp_code(secret, *step).as_deref() == Some(code))
+}
+
+/// The code for the given time step, per RFC 4226's HOTP
+fn totp_code(secret: &[u8], step: u64) -> Option<String> {
+	let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(secret).ok()?;
+	mac.update(&step.to_be_bytes());
+	let hash = mac.finalize().into_bytes();
+	let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+	let truncated = u32::from_be_bytes(hash[offset..offset + 4].try_into().ok()?) & 0x7fff_ffff;
+	Some(format!("{:0width$}", truncated % 10u32.pow(TOTP_DIGITS as u32), width = TOTP_DIGITS))
+}
@@ -3,17 +3,35 @@ use argon2::password_hash::Error::Password;
 use argon2::password_hash::rand_core::OsRng;
 use argon2::password_hash::SaltString;
 use base32ct::{ Base32Unpadded, Encoding };
+use crate::domain::entries::format_date;
 use crate::domain::RussetDomainService;
-use crate::model::{ FeedId, Timestamp, UserId, UserType };
-use crate::persistence::model::{ PasswordHash, Session, SessionToken, User };
+use crate::domain::model::{ LoginOutcome, ReauthOutcome, SessionInfo };
+use crate::model::{ DateDisplay, FeedId, Timestamp, UserId, UserType };
+use crate::persistence::model::{ PasswordHash, Session, SessionClient, SessionToken, User, UserPreferences };
 use crate::persistence::RussetUserPersistenceLayer;
 use crate::Err;
 use crate::Result;
 use getrandom::getrandom;
+use hmac::{ Hmac, Mac };
+use sha2::{ Digest, Sha256 };
 use std::time::{ Duration, SystemTime };
-use tracing::info;
+use tracing::{ info, warn };
 use ulid::Ulid;
 
+/// The largest page size a user can save as their default
+pub const MAX_PAGE_SIZE: usize = 1000;
+/// The most days a user may have dates shown with times for
+pub const MAX_RECENT_DAYS: u32 = 365;
+/// How often a session's last use is recorded, to save writing it on every
+/// request
+pub const SESSION_TOUCH_INTERVAL: Duration = Duration::from_secs(60);
+/// The longest user agent kept for a session
+const MAX_USER_AGENT_LEN: usize = 512;
+/// The shortest password a user may set
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+/// The longest password a user may set, so nobody can make us hash megabytes
+pub const MAX_PASSWORD_LENGTH: usize = 1024;
+
 impl <Persistence> RussetDomainService<Persistence>
 where Persistence: RussetUserPersistenceLayer {
 
@@ -22,45 +40,29 @@ where Persistence: RussetUserPersistenceLayer {
 		user_name: String,
 		plaintext_password: String,
 		permanent_session: bool,
-	) -> Result<Option<Session>> {
-		if self.disable_logins { return Ok(None) }
-		let password_hash = Argon2::new_with_secret(
-				self.pepper.as_slice(),
-				argon2::Algorithm::Argon2id,
-				argon2::Version::V0x13,
-				argon2::Params::DEFAULT,
-			)?;
+		client: SessionClient,
+	) -> Result<LoginOutcome> {
+		if self.disable_logins { return Ok(LoginOutcome::Failed) }
+		let ip = client.ip.clone();
+		if let Some(retry_after) = self.login_attempts.check(ip.as_deref(), &user_name) {
+			warn!(
+				"Blocked login for {:?} from {:?}; allowed again in {:.1?}",
+				user_name,
+				ip,
+				retry_after,
+			);
+			return Ok(LoginOutcome::Blocked { retry_after })
+		}
+		let password_hash = self.password_hasher()?;
 		let password_bytes = plaintext_password.into_bytes();
 		let user = self.persistence.get_user_by_name(&user_name).await?;
-		match user {
+		let verified = match &user {
 			Some(user) => {
 				let parsed_hash = argon2::PasswordHash::new(&user.password_hash.0)?;
 				match password_hash.verify_password(&password_bytes, &parsed_hash) {
-					Ok(_) => {
-						let token = Self::generate_token()?;
-						let session_duration = if permanent_session {
-							// Gigasecond is > 30 years. Should be long enough.
-							Duration::from_secs(1_000_000_000)
-						} else {
-							// Otherwise, we'll expire in a week (but set a
-							// session cookie)
-							Duration::from_secs(7 * 24 * 60 * 60)
-						};
-						let expiration = Timestamp::new(SystemTime::now() + session_duration);
-						let session = Session {
-							token,
-							user_id: user.id,
-							expiration,
-						};
-						self.persistence.add_session(&session).await?;
-						info!("Successfully logged in {:?} ({:?})", user.name, session);
-						Ok(Some(session))
-					},
-					Err(Password) => {
-						info!("Bad password for {:?}", user.name);
-						Ok(None)
-					},
-					Err(e) => Err(Box::new(e)),
+					Ok(_) => true,
+					Err(Password) => false,
+					Err(e) => return Err(Box::new(e)),
 				}
 			}
 			None => {
@@ -70,18 +72,85 @@ where Persistence: RussetUserPersistenceLayer {
 						IxgxUYNYPTvPTjez280uFJh166f+eNkCXntlVe5NaZQ"
 					).expect("hardcoded password hash should parse");
 				let _ = password_hash.verify_password(&password_bytes, &parsed_hash);
-				info!("User {:?} not found", user_name);
-				Ok(None)
+				false
 			}
+		};
+		match user {
+			Some(user) if verified => {
+				if user.disabled {
+					info!("Disabled user {:?} tried to log in", user.name);
+					return Ok(LoginOutcome::Failed)
+				}
+				if self.persistence.get_totp(&user.id).await?.is_some() {
+					// Not a success until the second factor's checked
+					let token = self.pending_logins.start(&user, permanent_session)?;
+					info!("Password accepted for {:?}, waiting for a second factor", user.name);
+					return Ok(LoginOutcome::SecondFactorRequired { token })
+				}
+				self.login_attempts.record_success(ip.as_deref(), &user_name);
+				let session = self.start_session(&user, permanent_session, client).await?;
+				Ok(LoginOutcome::LoggedIn { session, permanent_session })
+			},
+			_ => {
+				match user {
+					Some(user) => info!("Bad password for {:?} from {:?}", user.name, ip),
+					None => info!("User {:?} not found, from {:?}", user_name, ip),
+				}
+				if self.login_attempts.record_failure(ip.as_deref(), &user_name) {
+					warn!("Too many failed logins for {:?}; locking it", user_name);
+				}
+				Ok(LoginOutcome::Failed)
+			},
 		}
 	}
 
+	/// Log the given user in, now that they've proven who they are
+	pub(super) async fn start_session(
+		&self,
+		user: &User,
+		permanent_session: bool,
+		client: SessionClient,
+	) -> Result<Session> {
+		let token = Self::generate_token()?;
+		let session_duration = if permanent_session {
+			// Gigasecond is > 30 years. Should be long enough.
+			Duration::from_secs(1_000_000_000)
+		} else {
+			// Otherwise, we'll expire in a week (but set a session
+			// cookie)
+			Duration::from_secs(7 * 24 * 60 * 60)
+		};
+		let now = SystemTime::now();
+		let session = Session {
+			token,
+			user_id: user.id,
+			expiration: Timestamp::new(now + session_duration),
+			created: Some(Timestamp::new(now)),
+			last_used: Some(Timestamp::new(now)),
+			client: Self::truncate_client(client),
+		};
+		self.persistence.add_session(&session).await?;
+		info!("Successfully logged in {:?} ({:?})", user.name, session);
+		Ok(session)
+	}
+
+	/// Forget failed logins which no longer limit anything, and logins
+	/// abandoned before their second factor
+	pub fn prune_login_attempts(&self) {
+		self.login_attempts.prune();
+		self.pending_logins.prune();
+	}
+
 	pub async fn add_user(
 		&self,
 		user_name: &str,
 		plaintext_password: &str,
 		user_type: UserType
 	) -> Result<()> {
+		if user_name.trim().is_empty() {
+			return Err("User names can't be empty".into())
+		}
+		check_password_policy(user_name, plaintext_password)?;
 		if let Some(user) = self.persistence.get_user_by_name(&user_name).await? {
 			return Err(format!("User {} ({}) already exists", user.name, user.id.to_string()).into());
 		}
@@ -91,6 +160,7 @@ where Persistence: RussetUserPersistenceLayer {
 			name: user_name.to_string(),
 			password_hash,
 			user_type,
+			disabled: false,
 		};
 		self.persistence.add_user(&user).await?;
 		Ok(())
@@ -105,12 +175,69 @@ where Persistence: RussetUserPersistenceLayer {
 			.get_user_by_name(user_name)
 			.await?
 			.ok_or_else(|| -> Err { format!("No such user {user_name}").into() })?;
+		check_password_policy(&user.name, plaintext_password)?;
 		let password_hash = self.hash_password(plaintext_password)?;
 		let user = User { password_hash, ..user };
 		self.persistence.update_user(&user).await?;
 		Ok(())
 	}
 
+	/// Change the given user's password, if `current_password` is their
+	/// current one
+	pub async fn change_password(
+		&self,
+		user_id: &UserId,
+		current_password: &str,
+		new_password: &str,
+	) -> Result<ReauthOutcome<()>> {
+		let user = self.persistence.get_user(user_id).await?;
+		check_password_policy(&user.name, new_password)?;
+		if let Some(outcome) = self.check_current_password(&user, current_password, "changing password")? {
+			return Ok(outcome)
+		}
+		self.set_user_password(&user.name, new_password).await?;
+		info!("Changed password for {:?}", user.name);
+		Ok(ReauthOutcome::Done(()))
+	}
+
+	/// Check a logged in user's password before a change to their account,
+	/// counting wrong ones against the same per-user limit as logging in, so a
+	/// stolen session can't be used to guess it. Returns the outcome to give up
+	/// with, or `None` if the password was right. `action` is what they're
+	/// doing, for the log.
+	pub(super) fn check_current_password<T>(
+		&self,
+		user: &User,
+		plaintext_password: &str,
+		action: &str,
+	) -> Result<Option<ReauthOutcome<T>>> {
+		if let Some(retry_after) = self.login_attempts.check(None, &user.name) {
+			warn!(
+				"Blocked password check {} for {:?}; allowed again in {:.1?}",
+				action,
+				user.name,
+				retry_after,
+			);
+			return Ok(Some(ReauthOutcome::Blocked { retry_after }))
+		}
+		if self.check_password(user, plaintext_password)? {
+			self.login_attempts.record_success(None, &user.name);
+			Ok(None)
+		} else {
+			info!("Bad password {} for {:?}", action, user.name);
+			if self.login_attempts.record_failure(None, &user.name) {
+				warn!("Too many failed logins for {:?}; locking it", user.name);
+			}
+			Ok(Some(ReauthOutcome::Failed))
+		}
+	}
+
+	/// Log the given user out everywhere except the session with the given
+	/// token. Returns how many sessions were ended.
+	pub async fn delete_other_sessions(&self, user_id: &UserId, session_token: &str) -> Result<u32> {
+		self.persistence.delete_other_sessions_for_user(user_id, session_token).await
+	}
+
 	pub async fn delete_user(&self, user_name: &str) -> Result<()> {
 		let user = self.persistence
 			.get_user_by_name(user_name)
@@ -134,7 +261,7 @@ where Persistence: RussetUserPersistenceLayer {
 		self.persistence.delete_expired_sessions(&expiry).await
 	}
 
-	pub async fn auth_user(&self, token: &str) -> Result<Option<User>> {
+	pub async fn auth_user(&self, token: &str, client: SessionClient) -> Result<Option<User>> {
 		match self.persistence.get_user_by_session(&token).await? {
 			Some((user, session)) => {
 				if session.expiration.0 < SystemTime::now() {
@@ -142,7 +269,18 @@ where Persistence: RussetUserPersistenceLayer {
 					info!("Session {:?} expired, removing…", session.token);
 					self.persistence.delete_session(&session.token.0).await?;
 					Ok(None)
+				} else if user.disabled {
+					info!("Session {:?} belongs to disabled user {:?}", session.token, user.name);
+					Ok(None)
 				} else {
+					let client = Self::truncate_client(client);
+					let now = SystemTime::now();
+					let stale = session.last_used
+						.and_then(|last_used| now.duration_since(last_used.0).ok())
+						.is_none_or(|since| since >= SESSION_TOUCH_INTERVAL);
+					if stale || session.client != client {
+						self.persistence.touch_session(token, &Timestamp::new(now), &client).await?;
+					}
 					Ok(Some(user))
 				}
 			},
@@ -150,31 +288,256 @@ where Persistence: RussetUserPersistenceLayer {
 		}
 	}
 
+	/// The user a trusted reverse proxy says made a request, if they exist and
+	/// aren't disabled. With `provision_users`, users the proxy names who don't
+	/// exist yet are added as members, with a random password, since they
+	/// never log in with one.
+	pub async fn proxy_auth_user(&self, user_name: &str, provision_users: bool) -> Result<Option<User>> {
+		if let Some(user) = self.persistence.get_user_by_name(user_name).await? {
+			if user.disabled {
+				info!("Proxy authenticated disabled user {:?}", user.name);
+				return Ok(None)
+			}
+			return Ok(Some(user))
+		}
+		if !provision_users {
+			info!("Proxy authenticated unknown user {:?}", user_name);
+			return Ok(None)
+		}
+		let user = User {
+			id: UserId(Ulid::new()),
+			name: user_name.to_string(),
+			password_hash: self.hash_password(&Self::generate_token()?.0)?,
+			user_type: UserType::Member,
+			disabled: false,
+		};
+		if let Err(e) = self.persistence.add_user(&user).await {
+			// Another request may have added them first
+			return match self.persistence.get_user_by_name(user_name).await? {
+				Some(user) => Ok(Some(user)),
+				None => Err(e),
+			}
+		}
+		info!("Added proxy authenticated user {:?}", user.name);
+		Ok(Some(user))
+	}
+
+	/// End the session with the given token, if there is one
+	pub async fn logout(&self, token: &str) -> Result<()> {
+		if let Some((user, session)) = self.persistence.get_user_by_session(token).await? {
+			self.persistence.delete_session(token).await?;
+			info!("Logged out {:?} ({:?})", user.name, session);
+		}
+		Ok(())
+	}
+
+	/// Get all the given user's sessions, marking the one with the given token
+	/// as current
+	pub async fn get_sessions(
+		&self,
+		user_id: &UserId,
+		current_token: &str,
+		display: &DateDisplay,
+	) -> Result<Vec<SessionInfo>> {
+		let now = SystemTime::now().into();
+		let format = |timestamp: Option<Timestamp>| timestamp
+			.map(|timestamp| format_date(timestamp, display, now));
+		Ok(self.persistence
+			.get_sessions_for_user(user_id)
+			.await?
+			.into_iter()
+			.map(|session| SessionInfo {
+				id: Self::session_id(&session.token.0),
+				current: session.token.0 == current_token,
+				created: format(session.created),
+				last_used: format(session.last_used),
+				expiration: format_date(session.expiration, display, now),
+				ip: session.client.ip,
+				user_agent: session.client.user_agent,
+			} )
+			.collect())
+	}
+
+	/// End the given user's session with the given [SessionInfo] ID. Returns
+	/// whether they had one.
+	pub async fn delete_session_by_id(&self, user_id: &UserId, session_id: &str) -> Result<bool> {
+		let session = self.persistence
+			.get_sessions_for_user(user_id)
+			.await?
+			.into_iter()
+			.find(|session| Self::session_id(&session.token.0) == session_id);
+		match session {
+			Some(session) => {
+				self.persistence.delete_session(&session.token.0).await?;
+				info!("Ended session {:?}", session);
+				Ok(true)
+			},
+			None => Ok(false),
+		}
+	}
+
 	pub async fn get_user(&self, user_id: &UserId) -> Result<User> {
 		self.persistence.get_user(user_id).await
 	}
 
-	pub async fn subscribe(&self, user_id: &UserId, feed_id: &FeedId) -> Result<()> {
-		self.persistence.add_subscription(user_id, feed_id).await
+	/// Get every user, by name, with how many sessions each has
+	pub async fn get_users(&self) -> Result<Vec<(User, u32)>> {
+		self.persistence.get_users().await
+	}
+
+	/// Disable or re-enable the given user. Disabling a user also logs them
+	/// out everywhere.
+	pub async fn set_user_disabled(&self, user_id: &UserId, disabled: bool) -> Result<()> {
+		let user = self.persistence.get_user(user_id).await?;
+		self.persistence.update_user(&User { disabled, ..user.clone() }).await?;
+		if disabled {
+			let sessions = self.persistence.delete_sessions_for_user(user_id).await?;
+			info!("Disabled {:?}, ending {sessions} sessions", user.name);
+		} else {
+			info!("Enabled {:?}", user.name);
+		}
+		Ok(())
+	}
+
+	pub async fn set_user_type(&self, user_id: &UserId, user_type: UserType) -> Result<()> {
+		let user = self.persistence.get_user(user_id).await?;
+		info!("Changing {:?} from {:?} to {:?}", user.name, user.user_type, user_type);
+		self.persistence.update_user(&User { user_type, ..user }).await
+	}
+
+	/// Set a new password for the given user without needing their current
+	/// one, and log them out everywhere so whoever had the old one can't
+	/// carry on
+	pub async fn reset_user_password(&self, user_id: &UserId, plaintext_password: &str) -> Result<()> {
+		let user = self.persistence.get_user(user_id).await?;
+		check_password_policy(&user.name, plaintext_password)?;
+		let password_hash = self.hash_password(plaintext_password)?;
+		self.persistence.update_user(&User { password_hash, ..user.clone() }).await?;
+		let sessions = self.persistence.delete_sessions_for_user(user_id).await?;
+		info!("Reset password for {:?}, ending {sessions} sessions", user.name);
+		Ok(())
+	}
+
+	/// Log the given user out everywhere. Returns how many sessions were ended.
+	pub async fn revoke_user_sessions(&self, user_id: &UserId) -> Result<u32> {
+		self.persistence.delete_sessions_for_user(user_id).await
+	}
+
+	pub async fn delete_user_by_id(&self, user_id: &UserId) -> Result<()> {
+		let user = self.persistence.get_user(user_id).await?;
+		self.persistence.delete_user(user_id).await?;
+		info!("Deleted user {:?}", user.name);
+		Ok(())
+	}
+
+	/// Get the given user's saved preferences, or the defaults if they haven't
+	/// saved any
+	pub async fn get_user_preferences(&self, user_id: &UserId) -> Result<UserPreferences> {
+		Ok(self.persistence.get_user_preferences(user_id).await?.unwrap_or_default())
+	}
+
+	pub async fn set_user_preferences(&self, user_id: &UserId, preferences: &UserPreferences) -> Result<()> {
+		if !(1..=MAX_PAGE_SIZE).contains(&preferences.page_size) {
+			return Err(format!("Page size must be between 1 and {MAX_PAGE_SIZE}").into())
+		}
+		if preferences.dates.recent_days > MAX_RECENT_DAYS {
+			return Err(format!("Times can be shown for at most {MAX_RECENT_DAYS} days").into())
+		}
+		self.persistence.set_user_preferences(user_id, preferences).await
 	}
 
 	pub async fn unsubscribe(&self, user_id: &UserId, feed_id: &FeedId) -> Result<()> {
 		self.persistence.remove_subscription(user_id, feed_id).await
 	}
 
-	fn generate_token() -> Result<SessionToken> {
+	pub(super) fn generate_token() -> Result<SessionToken> {
 		let mut bytes = [0u8; 32];
 		getrandom(&mut bytes)?;
 		Ok(SessionToken(Base32Unpadded::encode_string(&bytes)))
 	}
 
-	fn hash_password(&self, plaintext_password: &str) -> Result<PasswordHash> {
-		let password_hasher = Argon2::new_with_secret(
-				self.pepper.as_slice(),
-				argon2::Algorithm::Argon2id,
-				argon2::Version::V0x13,
-				argon2::Params::DEFAULT,
-			)?;
+	/// The token forms on the given session's pages carry, to show requests
+	/// came from those pages and not some other site using the session's
+	/// cookie. It's derived from the session token, so it lasts as long as the
+	/// session and can't be worked out without it.
+	pub fn csrf_token(&self, session_token: &str) -> Result<String> {
+		let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.pepper)?;
+		mac.update(b"csrf:");
+		mac.update(session_token.as_bytes());
+		Ok(Base32Unpadded::encode_string(&mac.finalize().into_bytes()))
+	}
+
+	/// The tokens forms carry for a user authenticated by a reverse proxy, who
+	/// has no session to derive one from: today's, for new forms, and
+	/// yesterday's, which forms loaded before midnight still carry. They're
+	/// derived from the user's ID, the day and a key made when Russet started,
+	/// so they change daily and whenever Russet restarts.
+	pub fn proxy_auth_csrf_tokens(&self, user_id: &UserId) -> Result<(String, String)> {
+		let today = Timestamp::now().0
+			.duration_since(SystemTime::UNIX_EPOCH)?
+			.as_secs() / 86_400;
+		Ok((
+			self.proxy_auth_csrf_token(user_id, today)?,
+			self.proxy_auth_csrf_token(user_id, today.saturating_sub(1))?,
+		))
+	}
+
+	fn proxy_auth_csrf_token(&self, user_id: &UserId, day: u64) -> Result<String> {
+		let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.proxy_csrf_key)?;
+		mac.update(b"proxy-csrf:");
+		mac.update(user_id.to_string().as_bytes());
+		mac.update(b":");
+		mac.update(&day.to_be_bytes());
+		Ok(Base32Unpadded::encode_string(&mac.finalize().into_bytes()))
+	}
+
+	/// A random token for the login form, which has no session to derive one
+	/// from
+	pub fn login_csrf_token() -> Result<String> {
+		Ok(Self::generate_token()?.0)
+	}
+
+	/// Identify a session without giving away its token, which would let
+	/// whoever saw it use the session
+	fn session_id(token: &str) -> String {
+		Base32Unpadded::encode_string(&Sha256::digest(token.as_bytes()))
+	}
+
+	fn truncate_client(client: SessionClient) -> SessionClient {
+		SessionClient {
+			user_agent: client.user_agent
+				.map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LEN).collect()),
+			..client
+		}
+	}
+
+	/// Whether the given plaintext password is the one hashed to `hash`
+	pub(super) fn verify_password_hash(&self, hash: &PasswordHash, plaintext_password: &str) -> Result<bool> {
+		let parsed_hash = argon2::PasswordHash::new(&hash.0)?;
+		match self.password_hasher()?.verify_password(plaintext_password.as_bytes(), &parsed_hash) {
+			Ok(_) => Ok(true),
+			Err(Password) => Ok(false),
+			Err(e) => Err(Box::new(e)),
+		}
+	}
+
+	/// Whether the given plaintext password is the given user's
+	pub(super) fn check_password(&self, user: &User, plaintext_password: &str) -> Result<bool> {
+		self.verify_password_hash(&user.password_hash, plaintext_password)
+	}
+
+	/// The Argon2 instance passwords are hashed and verified with
+	fn password_hasher(&self) -> Result<Argon2<'_>> {
+		Ok(Argon2::new_with_secret(
+			self.pepper.as_slice(),
+			argon2::Algorithm::Argon2id,
+			argon2::Version::V0x13,
+			argon2::Params::DEFAULT,
+		)?)
+	}
+
+	pub(super) fn hash_password(&self, plaintext_password: &str) -> Result<PasswordHash> {
+		let password_hasher = self.password_hasher()?;
 		let salt = SaltString::generate(&mut OsRng);
 		Ok(PasswordHash(
 			password_hasher
@@ -183,3 +546,17 @@ where Persistence: RussetUserPersistenceLayer {
 		))
 	}
 }
+
+/// Make sure a new password for the named user is one they may set: between
+/// [MIN_PASSWORD_LENGTH] and [MAX_PASSWORD_LENGTH] characters, and not just the
+/// user name
+fn check_password_policy(user_name: &str, plaintext_password: &str) -> Result<()> {
+	let length = plaintext_password.chars().count();
+	if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length)
+			|| plaintext_password.to_lowercase() == user_name.trim().to_lowercase() {
+		return Err(format!(
+			"Passwords must be {MIN_PASSWORD_LENGTH} to {MAX_PASSWORD_LENGTH} characters long, and not the user name"
+		).into())
+	}
+	Ok(())
+}
@@ -18,6 +18,12 @@ impl RussetFeedReader for AtomFeedReader {
 	fn read_feed(&self, bytes: &[u8]) -> Result<Feed> {
 		let atom = AtomFeed::read_from(bytes)?;
 		let title = atom.title.value;
+		let link = |rel: &str| atom.links
+			.iter()
+			.find(|link| link.rel == rel)
+			.and_then(|link| Url::parse(&link.href).ok());
+		let hub = link("hub");
+		let self_url = link("self");
 		let entries = atom.entries.into_iter().map(|entry| {
 			Entry {
 				internal_id: entry.id,
@@ -29,11 +35,18 @@ impl RussetFeedReader for AtomFeedReader {
 					.map_or(None, |url| Url::parse(&url.href).ok()),
 				article_date: Timestamp::new(entry.updated.into()),
 				title: entry.title.value,
+				author: entry.authors.into_iter().next().map(|author| author.name),
+				categories: entry.categories
+					.into_iter()
+					.map(|category| category.label.unwrap_or(category.term))
+					.collect(),
 			}
 		}).collect();
 		Ok(Feed {
 			title,
 			entries,
+			hub,
+			self_url,
 		})
 	}
 }
@@ -0,0 +1,152 @@
+//! Character encoding detection, so the readers only ever see UTF-8.
+//!
+//! The encoding of a document is taken from, in order of precedence:
+//!
+//! 1. a byte order mark,
+//! 2. the `charset` parameter of the `Content-Type` the document was served
+//!    with,
+//! 3. the `encoding` of its XML declaration,
+//! 4. for HTML pages, a `<meta charset>` (or
+//!    `<meta http-equiv="Content-Type">`) near the start,
+//!
+//! falling back to UTF-8. (The first three are the order RFC 7303 specifies
+//! for XML media types.) Encoding names are resolved per the WHATWG Encoding
+//! Standard, so the usual aliases (`Shift_JIS`, `KOI8-R`, `windows-1250`, …)
+//! all work.
+
+use encoding_rs::{ Encoding, UTF_8 };
+use std::borrow::Cow;
+
+/// How far into the document to look for an XML declaration or `<meta>`
+/// charset
+const DECLARATION_SNIFF_LENGTH: usize = 1024;
+
+/// Transcode `bytes` to UTF-8. `content_type` is the value of the
+/// `Content-Type` header the bytes were served with, if any. `html` says the
+/// bytes are an HTML page whatever they were served as, e.g. one to scrape; a
+/// `<meta>` charset is only looked for in HTML pages, since a feed may well
+/// quote one in its entries' content.
+///
+/// If the document has an XML declaration naming some other encoding, it's
+/// rewritten to say UTF-8, so parsers don't try to decode it a second time.
+/// Undecodable sequences are replaced with U+FFFD rather than failing.
+pub fn to_utf8<'a>(bytes: &'a [u8], content_type: Option<&str>, html: bool) -> Cow<'a, [u8]> {
+	let html = html || content_type.is_some_and(is_html);
+	let (encoding, bom_length) = Encoding::for_bom(bytes)
+		.or_else(|| content_type
+			.and_then(charset_parameter)
+			.and_then(|charset| Encoding::for_label(charset.as_bytes()))
+			.map(|encoding| (encoding, 0)))
+		.or_else(|| xml_declaration_encoding(bytes).map(|encoding| (encoding, 0)))
+		.or_else(|| html.then(|| meta_charset_encoding(bytes)).flatten().map(|encoding| (encoding, 0)))
+		.unwrap_or((UTF_8, 0));
+	let bytes = &bytes[bom_length..];
+	if encoding == UTF_8 && declares_utf8(bytes) && std::str::from_utf8(bytes).is_ok() {
+		return Cow::Borrowed(bytes)
+	}
+	let decoded = encoding.decode_without_bom_handling(bytes).0;
+	let mut decoded = decoded.into_owned().into_bytes();
+	// Check the decoded document, not the original: a declaration in UTF-16
+	// can only be found once it's been decoded.
+	if !declares_utf8(&decoded) {
+		if let Some(range) = declaration_encoding_range(&decoded) {
+			decoded.splice(range, b"UTF-8".iter().copied());
+		}
+	}
+	Cow::Owned(decoded)
+}
+
+/// Whether the XML declaration at the start of `bytes` says UTF-8, or there
+/// isn't one to say otherwise
+fn declares_utf8(bytes: &[u8]) -> bool {
+	declaration_encoding_range(bytes)
+		.is_none_or(|range| Encoding::for_label(&bytes[range]) == Some(UTF_8))
+}
+
+/// Extract the `charset` parameter from a `Content-Type` header value
+fn charset_parameter(content_type: &str) -> Option<&str> {
+	content_type
+		.split(';')
+		.skip(1)
+		.filter_map(|parameter| parameter.split_once('='))
+		.find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+		.map(|(_, value)| value.trim().trim_matches('"'))
+}
+
+/// Whether a `Content-Type` header value is for an HTML page
+fn is_html(content_type: &str) -> bool {
+	content_type
+		.split(';')
+		.next()
+		.is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("text/html"))
+}
+
+fn xml_declaration_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+	let range = declaration_encoding_range(bytes)?;
+	// As with `<meta>`, a declaration which can be read as ASCII means the
+	// document isn't really UTF-16, whatever it says
+	Encoding::for_label(&bytes[range]).map(Encoding::output_encoding)
+}
+
+/// Find the charset named by a `<meta charset>` or `<meta http-equiv>` tag near
+/// the start of an HTML page. Like browsers, this takes the first `<meta>` naming
+/// one, wherever it is in the tag.
+fn meta_charset_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+	let head = bytes[..bytes.len().min(DECLARATION_SNIFF_LENGTH)].to_ascii_lowercase();
+	let mut rest = &head[..];
+	while let Some(start) = find(rest, b"<meta") {
+		let tag = &rest[start..];
+		let tag = &tag[..find(tag, b">").unwrap_or(tag.len())];
+		if let Some(charset) = find(tag, b"charset") {
+			let value = tag[charset + b"charset".len()..]
+				.trim_ascii_start()
+				.strip_prefix(b"=")
+				.map(|value| value.trim_ascii_start())
+				.map(|value| value.strip_prefix(b"\"").or_else(|| value.strip_prefix(b"'")).unwrap_or(value))
+				.map(|value| {
+					let end = value.iter()
+						.position(|byte| matches!(byte, b'"' | b'\'' | b';' | b'/') || byte.is_ascii_whitespace())
+						.unwrap_or(value.len());
+					&value[..end]
+				} );
+			// A page whose `<meta>` can be read as ASCII isn't really UTF-16,
+			// whatever it says, so this is taken to mean UTF-8
+			if let Some(encoding) = value.and_then(Encoding::for_label) {
+				return Some(encoding.output_encoding())
+			}
+		}
+		rest = &rest[start + b"<meta".len()..];
+	}
+	None
+}
+
+/// Locate the value of the `encoding` pseudo-attribute in the XML declaration
+/// at the start of `bytes`, if there is one
+fn declaration_encoding_range(bytes: &[u8]) -> Option<std::ops::Range<usize>> {
+	let head = &bytes[..bytes.len().min(DECLARATION_SNIFF_LENGTH)];
+	if !head.starts_with(b"<?xml") {
+		return None
+	}
+	let end = find(head, b"?>")?;
+	let attribute = find(&head[..end], b"encoding")? + b"encoding".len();
+	let mut position = attribute;
+	let skip_whitespace = |mut position: usize| {
+		while head.get(position).is_some_and(u8::is_ascii_whitespace) {
+			position += 1;
+		}
+		position
+	};
+	position = skip_whitespace(position);
+	if head.get(position) != Some(&b'=') {
+		return None
+	}
+	position = skip_whitespace(position + 1);
+	let quote = *head.get(position).filter(|quote| **quote == b'"' || **quote == b'\'')?;
+	let start = position + 1;
+	let length = head[start..end].iter().position(|byte| *byte == quote)?;
+	Some(start..start + length)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+	haystack.windows(needle.len()).position(|window| window == needle)
+}
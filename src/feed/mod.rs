@@ -1,6 +1,8 @@
 pub mod atom;
+pub mod encoding;
 pub mod model;
 pub mod rss;
+pub mod scrape;
 
 use crate::Result;
 use model::Feed;
@@ -5,6 +5,10 @@ use reqwest::Url;
 pub struct Feed {
 	pub title: String,
 	pub entries: Vec<Entry>,
+	/// WebSub hub advertised by the feed (`<link rel="hub">`), if any
+	pub hub: Option<Url>,
+	/// The feed's canonical URL (`<link rel="self">`), if it specifies one
+	pub self_url: Option<Url>,
 }
 
 #[derive(Debug)]
@@ -13,4 +17,6 @@ pub struct Entry {
 	pub url: Option<Url>,
 	pub article_date: Timestamp,
 	pub title: String,
+	pub author: Option<String>,
+	pub categories: Vec<String>,
 }
@@ -21,6 +21,13 @@ impl RussetFeedReader for RssFeedReader {
 	fn read_feed(&self, bytes: &[u8]) -> Result<Feed> {
 		let rss = Channel::read_from(bytes)?;
 		let title = rss.title;
+		// Hub and self links come from the Atom namespace, if present
+		let link = |rel: &str| rss.atom_ext
+			.as_ref()
+			.and_then(|atom| atom.links.iter().find(|link| link.rel == rel))
+			.and_then(|link| Url::parse(&link.href).ok());
+		let hub = link("hub");
+		let self_url = link("self");
 		let entries = rss.items.into_iter().map(|item| {
 			Entry {
 				internal_id: item.guid.map_or_else(
@@ -31,11 +38,22 @@ impl RussetFeedReader for RssFeedReader {
 					.map_or(None, |url| Url::parse(&url).ok()),
 				article_date: from_rss_timestamp(item.pub_date),
 				title: item.title.unwrap_or("<untitled>".to_string()),
+				// <author> is meant to be an email address, so most feeds use
+				// Dublin Core's <dc:creator> instead
+				author: item.dublin_core_ext
+					.and_then(|dc| dc.creators.into_iter().next())
+					.or(item.author),
+				categories: item.categories
+					.into_iter()
+					.map(|category| category.name)
+					.collect(),
 			}
 		}).collect();
 		Ok(Feed {
 			title,
 			entries,
+			hub,
+			self_url,
 		})
 	}
 }
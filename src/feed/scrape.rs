@@ -0,0 +1,138 @@
+use chrono::{ DateTime, NaiveDate, NaiveDateTime };
+use crate::feed::model::Entry;
+use crate::feed::model::Feed;
+use crate::feed::RussetFeedReader;
+use crate::model::Timestamp;
+use crate::{ Err, Result };
+use reqwest::Url;
+use scraper::{ ElementRef, Html, Selector };
+use std::time::SystemTime;
+
+/// Reads a [Feed] out of an arbitrary HTML page using CSS selectors.
+///
+/// Unlike the other readers, this one is specific to a page: it needs the
+/// selectors to apply and the page's URL (to resolve relative links), so one is
+/// constructed for each scraped feed rather than once at startup.
+#[derive(Debug)]
+pub struct ScrapeFeedReader {
+	item: Selector,
+	title: Option<Selector>,
+	link: Option<Selector>,
+	date: Option<Selector>,
+	base_url: Url,
+}
+impl ScrapeFeedReader {
+	/// `item` selects each entry on the page; the other selectors are applied
+	/// within each item. Without a `title` selector, the item's full text is
+	/// the title; without a `link` selector, the first link in the item is
+	/// used; and without a `date` selector (or if the date can't be parsed),
+	/// the time of the check is used.
+	pub fn new(
+		item: &str,
+		title: Option<&str>,
+		link: Option<&str>,
+		date: Option<&str>,
+		base_url: Url,
+	) -> Result<ScrapeFeedReader> {
+		Ok(ScrapeFeedReader {
+			item: parse_selector(item)?,
+			title: title.map(parse_selector).transpose()?,
+			link: link.map(parse_selector).transpose()?,
+			date: date.map(parse_selector).transpose()?,
+			base_url,
+		} )
+	}
+
+	fn read_entry(&self, item: ElementRef) -> Option<Entry> {
+		let title = match &self.title {
+			Some(selector) => item.select(selector).next().map(element_text)?,
+			None => element_text(item),
+		};
+		if title.is_empty() {
+			return None
+		}
+		let link = match &self.link {
+			Some(selector) => item.select(selector).next(),
+			None => item.select(&parse_selector("a[href]").expect("hard-coded selector should parse")).next(),
+		};
+		let url = link
+			.and_then(|link| link.value().attr("href"))
+			.and_then(|href| self.base_url.join(href).ok());
+		let article_date = self.date
+			.as_ref()
+			.and_then(|selector| item.select(selector).next())
+			.and_then(|date| {
+				// Prefer machine-readable dates, e.g. <time datetime="…">
+				date.value()
+					.attr("datetime")
+					.and_then(parse_date)
+					.or_else(|| parse_date(&element_text(date)))
+			} )
+			.unwrap_or_else(|| Timestamp::new(SystemTime::now()));
+		// Scraped items have no ID of their own; the link is the next best
+		// thing, falling back to the title.
+		let internal_id = url
+			.as_ref()
+			.map(|url| url.to_string())
+			.unwrap_or_else(|| title.clone());
+		Some(Entry { internal_id, url, article_date, title, author: None, categories: Vec::new() })
+	}
+}
+impl RussetFeedReader for ScrapeFeedReader {
+
+	fn read_feed(&self, bytes: &[u8]) -> Result<Feed> {
+		let html = Html::parse_document(&String::from_utf8_lossy(bytes));
+		let title = html
+			.select(&parse_selector("title").expect("hard-coded selector should parse"))
+			.next()
+			.map(element_text)
+			.filter(|title| !title.is_empty())
+			.unwrap_or_else(|| self.base_url.to_string());
+		let entries = html
+			.select(&self.item)
+			.filter_map(|item| self.read_entry(item))
+			.collect();
+		Ok(Feed {
+			title,
+			entries,
+			hub: None,
+			self_url: None,
+		})
+	}
+}
+
+fn parse_selector(selector: &str) -> Result<Selector> {
+	Selector::parse(selector)
+		.map_err(|e| -> Err { format!("Invalid selector {selector:?}: {e}").into() })
+}
+
+/// All the text in an element, with whitespace collapsed
+fn element_text(element: ElementRef) -> String {
+	element.text()
+		.flat_map(|text| text.split_whitespace())
+		.collect::<Vec<&str>>()
+		.join(" ")
+}
+
+/// Try a handful of common date formats. Pages are free to format dates
+/// however they like, so this is best-effort.
+fn parse_date(date: &str) -> Option<Timestamp> {
+	let date = date.trim();
+	if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+		return Some(Timestamp::new(date.into()))
+	}
+	if let Ok(date) = DateTime::parse_from_rfc2822(date) {
+		return Some(Timestamp::new(date.into()))
+	}
+	for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+		if let Ok(date) = NaiveDateTime::parse_from_str(date, format) {
+			return Some(Timestamp::new(date.and_utc().into()))
+		}
+	}
+	for format in ["%Y-%m-%d", "%B %d, %Y", "%b %d, %Y", "%d %B %Y", "%d %b %Y", "%Y/%m/%d"] {
+		if let Ok(date) = NaiveDate::parse_from_str(date, format) {
+			return Some(Timestamp::new(date.and_hms_opt(0, 0, 0)?.and_utc().into()))
+		}
+	}
+	None
+}
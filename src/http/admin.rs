@@ -0,0 +1,376 @@
+use axum::extract::{ Path, Query, State };
+use axum::response::{ Html, Redirect };
+use crate::domain::model::{ InvitationInfo, Label };
+use crate::http::{ AppState, AuthenticatedUser };
+use crate::http::csrf::CsrfForm;
+use crate::http::error::HttpError;
+use crate::model::{ InvitationId, Permission, UserId, UserType };
+use crate::persistence::model::User;
+use crate::persistence::RussetPersistenceLayer;
+use sailfish::TemplateOnce;
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+
+/// Only sysops may administer users
+fn check_admin<Persistence>(user: &AuthenticatedUser<Persistence>) -> Result<(), HttpError> {
+	if !user.user.user_type.has_permission(Permission::ManageUsers) {
+		return Err(HttpError::Forbidden);
+	}
+	Ok(())
+}
+
+/// Sysops can't lock themselves out by disabling, demoting or deleting their
+/// own account, which also means there's always at least one sysop left
+fn check_not_self<Persistence>(
+	page_user_id: &UserId,
+	user: &AuthenticatedUser<Persistence>,
+	action: &str,
+) -> Result<(), HttpError> {
+	if user.user.id == *page_user_id {
+		return Err(HttpError::BadRequest { description: format!("You can't {action} your own account") });
+	}
+	Ok(())
+}
+
+/// Destructive actions need their form's confirmation box ticked
+fn check_confirmed(confirm: &Option<String>) -> Result<(), HttpError> {
+	if confirm.is_none() {
+		return Err(HttpError::BadRequest { description: "Tick the box to confirm".to_string() });
+	}
+	Ok(())
+}
+
+/// Which change was just made, to confirm on the admin pages
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminUpdate {
+	Added,
+	Deleted,
+	Type,
+	Enabled,
+	Disabled,
+	Password,
+	Sessions,
+	Revoked,
+}
+impl AdminUpdate {
+	fn message(&self) -> &'static str {
+		match self {
+			AdminUpdate::Added => "User added.",
+			AdminUpdate::Deleted => "User deleted.",
+			AdminUpdate::Type => "User type changed.",
+			AdminUpdate::Enabled => "User enabled.",
+			AdminUpdate::Disabled => "User disabled and logged out everywhere.",
+			AdminUpdate::Password => "Password reset and user logged out everywhere.",
+			AdminUpdate::Sessions => "User logged out everywhere.",
+			AdminUpdate::Revoked => "Invitation revoked.",
+		}
+	}
+}
+#[derive(Debug, Deserialize)]
+pub struct AdminQuery {
+	updated: Option<AdminUpdate>,
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "admin_users.stpl")]
+struct UsersPageTemplate<'a> {
+	/// Every user, with how many sessions they have
+	users: &'a [(User, u32)],
+	message: Option<&'a str>,
+	user: Option<&'a User>,
+	csrf_token: &'a str,
+	labels: &'a [Label],
+	page_title: &'a str,
+	relative_root: &'a str,
+}
+#[tracing::instrument]
+pub async fn users_page<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	Query(query): Query<AdminQuery>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_admin(&user)?;
+	let users = state.domain_service.get_users().await?;
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	Ok(Html(
+		UsersPageTemplate {
+			users: &users,
+			message: query.updated.map(|updated| updated.message()),
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			labels: &labels,
+			page_title: "Users",
+			relative_root: "../",
+		}
+		.render_once()?
+	) )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddUserRequest {
+	user_name: String,
+	password: String,
+	confirm_password: String,
+	user_type: UserType,
+}
+#[tracing::instrument(skip(request))]
+pub async fn add_user<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<AddUserRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_admin(&user)?;
+	if request.password != request.confirm_password {
+		return Err(HttpError::BadRequest { description: "The passwords don't match".to_string() })
+	}
+	state.domain_service
+		.add_user(request.user_name.trim(), &request.password, request.user_type)
+		.await
+		.map_err(|e| HttpError::BadRequest { description: e.to_string() })?;
+	Ok(Redirect::to("/admin/users?updated=added"))
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "admin_user.stpl")]
+struct UserPageTemplate<'a> {
+	page_user: &'a User,
+	session_count: u32,
+	message: Option<&'a str>,
+	user: Option<&'a User>,
+	csrf_token: &'a str,
+	labels: &'a [Label],
+	page_title: &'a str,
+	relative_root: &'a str,
+}
+#[tracing::instrument]
+pub async fn user_page<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	Query(query): Query<AdminQuery>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_admin(&user)?;
+	let (page_user, session_count) = state.domain_service
+		.get_users()
+		.await?
+		.into_iter()
+		.find(|(page_user, _)| page_user.id == page_user_id)
+		.ok_or(HttpError::NotFound)?;
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	let page_title = format!("Manage User - {}", page_user.name);
+	Ok(Html(
+		UserPageTemplate {
+			page_user: &page_user,
+			session_count,
+			message: query.updated.map(|updated| updated.message()),
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			labels: &labels,
+			page_title: &page_title,
+			relative_root: "../../",
+		}
+		.render_once()?
+	) )
+}
+
+/// Where to go back to after changing a user
+fn user_redirect(user_id: &UserId, updated: &str) -> Redirect {
+	Redirect::to(&format!("/admin/users/{}?updated={updated}", user_id.to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetUserTypeRequest {
+	user_type: UserType,
+}
+#[tracing::instrument]
+pub async fn set_user_type<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<SetUserTypeRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_admin(&user)?;
+	check_not_self(&page_user_id, &user, "change the type of")?;
+	state.domain_service.set_user_type(&page_user_id, request.user_type).await?;
+	Ok(user_redirect(&page_user_id, "type"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetDisabledRequest {
+	disabled: bool,
+	confirm: Option<String>,
+}
+#[tracing::instrument]
+pub async fn set_disabled<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<SetDisabledRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_admin(&user)?;
+	check_not_self(&page_user_id, &user, "disable")?;
+	// Enabling a user is harmless, so only disabling needs confirming
+	if request.disabled {
+		check_confirmed(&request.confirm)?;
+	}
+	state.domain_service.set_user_disabled(&page_user_id, request.disabled).await?;
+	Ok(user_redirect(&page_user_id, if request.disabled { "disabled" } else { "enabled" }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+	new_password: String,
+	confirm_password: String,
+	confirm: Option<String>,
+}
+#[tracing::instrument(skip(request))]
+pub async fn reset_password<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<ResetPasswordRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_admin(&user)?;
+	check_confirmed(&request.confirm)?;
+	if request.new_password != request.confirm_password {
+		return Err(HttpError::BadRequest { description: "The new passwords don't match".to_string() })
+	}
+	state.domain_service
+		.reset_user_password(&page_user_id, &request.new_password)
+		.await
+		.map_err(|e| HttpError::BadRequest { description: e.to_string() })?;
+	Ok(user_redirect(&page_user_id, "password"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmRequest {
+	confirm: Option<String>,
+}
+#[tracing::instrument]
+pub async fn revoke_sessions<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<ConfirmRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_admin(&user)?;
+	check_confirmed(&request.confirm)?;
+	state.domain_service.revoke_user_sessions(&page_user_id).await?;
+	Ok(user_redirect(&page_user_id, "sessions"))
+}
+
+#[tracing::instrument]
+pub async fn delete_user<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<ConfirmRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_admin(&user)?;
+	check_not_self(&page_user_id, &user, "delete")?;
+	check_confirmed(&request.confirm)?;
+	state.domain_service.delete_user_by_id(&page_user_id).await?;
+	Ok(Redirect::to("/admin/users?updated=deleted"))
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "admin_invitations.stpl")]
+struct InvitationsPageTemplate<'a> {
+	/// Every unused invitation
+	invitations: &'a [InvitationInfo],
+	/// The link for a just-created invitation, which can't be shown again
+	new_link: Option<&'a str>,
+	message: Option<&'a str>,
+	user: Option<&'a User>,
+	csrf_token: &'a str,
+	labels: &'a [Label],
+	page_title: &'a str,
+	relative_root: &'a str,
+}
+async fn render_invitations_page<Persistence>(
+	state: &AppState<Persistence>,
+	user: &AuthenticatedUser<Persistence>,
+	new_link: Option<&str>,
+	message: Option<&str>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let invitations = state.domain_service.get_invitations(&user.preferences.dates).await?;
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	Ok(Html(
+		InvitationsPageTemplate {
+			invitations: &invitations,
+			new_link,
+			message,
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			labels: &labels,
+			page_title: "Invitations",
+			relative_root: "../",
+		}
+		.render_once()?
+	) )
+}
+#[tracing::instrument]
+pub async fn invitations_page<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	Query(query): Query<AdminQuery>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_admin(&user)?;
+	render_invitations_page(&state, &user, None, query.updated.map(|updated| updated.message())).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInvitationRequest {
+	user_type: UserType,
+	/// Blank for invitations which never expire
+	#[serde(default)]
+	expires_in_days: String,
+}
+#[tracing::instrument]
+pub async fn create_invitation<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<CreateInvitationRequest>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_admin(&user)?;
+	let expires_in_days = match request.expires_in_days.trim() {
+		"" => None,
+		days => Some(days
+			.parse::<u32>()
+			.map_err(|_| HttpError::BadRequest { description: "Expiry must be a whole number of days".to_string() })?),
+	};
+	let link = state.domain_service
+		.create_invitation(&user.user.id, request.user_type, expires_in_days)
+		.await
+		.map_err(|e| HttpError::BadRequest { description: e.to_string() })?;
+	// Only the hash of the token is kept, so this is the only chance to copy
+	// the link
+	render_invitations_page(&state, &user, Some(&link), Some("Invitation created.")).await
+}
+
+#[tracing::instrument]
+pub async fn revoke_invitation<Persistence>(
+	Path(invitation_id): Path<InvitationId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(_): CsrfForm<IgnoredAny>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_admin(&user)?;
+	if !state.domain_service.revoke_invitation(&invitation_id).await? {
+		return Err(HttpError::NotFound)
+	}
+	Ok(Redirect::to("/admin/invitations?updated=revoked"))
+}
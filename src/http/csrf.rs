@@ -0,0 +1,100 @@
+use axum::{ async_trait, RequestExt };
+use axum::body::Bytes;
+use axum::extract::{ FromRequest, Request };
+use crate::http::error::HttpError;
+use serde::de::DeserializeOwned;
+
+/// The form field CSRF tokens are sent in
+pub const CSRF_FIELD: &str = "csrf_token";
+
+/// The CSRF token for the request's session, which [AuthenticatedUser]
+/// leaves in the request's extensions for [CsrfForm] to check forms against
+///
+/// [AuthenticatedUser]: crate::http::session::AuthenticatedUser
+#[derive(Clone)]
+pub struct SessionCsrfToken {
+	pub token: String,
+	/// A token the session's forms may still carry from before `token`
+	/// replaced it
+	pub previous: Option<String>,
+}
+impl std::fmt::Debug for SessionCsrfToken {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("SessionCsrfToken")
+			.field("token", &"<redacted>")
+			.field("previous", &self.previous.as_ref().map(|_| "<redacted>"))
+			.finish()
+	}
+}
+impl SessionCsrfToken {
+	/// Check a form's CSRF token against the session's current and previous
+	/// ones
+	pub fn check(&self, token: Option<&str>) -> Result<(), HttpError> {
+		check_csrf_token(&self.token, token)
+			.or_else(|e| match &self.previous {
+				Some(previous) => check_csrf_token(previous, token),
+				None => Err(e),
+			} )
+	}
+}
+
+/// Like [Form](axum::extract::Form), but rejects forms without the session's
+/// CSRF token, so other sites can't submit them with the user's cookies.
+/// Handlers using it must also take an [AuthenticatedUser], which must come
+/// first.
+///
+/// The token field is taken out before deserializing the rest of the form.
+/// Handlers which don't care about the rest can use
+/// [IgnoredAny](serde::de::IgnoredAny).
+///
+/// [AuthenticatedUser]: crate::http::session::AuthenticatedUser
+#[derive(Debug)]
+pub struct CsrfForm<T>(pub T);
+#[async_trait]
+impl <S, T> FromRequest<S> for CsrfForm<T>
+where
+	S: Send + Sync,
+	T: DeserializeOwned,
+{
+	type Rejection = HttpError;
+	async fn from_request(mut request: Request, _state: &S) -> Result<Self, Self::Rejection> {
+		let expected = request.extensions_mut()
+			.remove::<SessionCsrfToken>()
+			.ok_or_else(|| HttpError::InternalError {
+				description: "CSRF-protected form without an authenticated user".to_string(),
+			} )?;
+		let body: Bytes = request.extract()
+			.await
+			.map_err(|e| HttpError::BadRequest { description: format!("Could not read form: {e}") })?;
+		let mut fields: Vec<(String, String)> = serde_urlencoded::from_bytes(&body)
+			.map_err(bad_form)?;
+		let token = fields.iter()
+			.position(|(key, _)| key == CSRF_FIELD)
+			.map(|i| fields.remove(i).1);
+		expected.check(token.as_deref())?;
+		let form = serde_urlencoded::to_string(&fields)
+			.map_err(|e| HttpError::InternalError { description: e.to_string() })?;
+		Ok(CsrfForm(serde_urlencoded::from_str(&form).map_err(bad_form)?))
+	}
+}
+
+/// Check a form's CSRF token against the expected one
+pub fn check_csrf_token(expected: &str, token: Option<&str>) -> Result<(), HttpError> {
+	let valid = token.is_some_and(|token| {
+		// Compare every byte, so how long this takes doesn't say how much of
+		// the token was right
+		token.len() == expected.len() && token.bytes()
+			.zip(expected.bytes())
+			.fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+	} );
+	if !valid {
+		return Err(HttpError::BadRequest {
+			description: "This form has expired or came from another site. Go back, reload the page and try again.".to_string(),
+		} )
+	}
+	Ok(())
+}
+
+fn bad_form(e: serde_urlencoded::de::Error) -> HttpError {
+	HttpError::BadRequest { description: format!("Could not read form: {e}") }
+}
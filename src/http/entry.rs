@@ -12,7 +12,7 @@ pub async fn mark_read_redirect<Persistence>(
 	user: AuthenticatedUser<Persistence>,
 ) -> Result<Redirect, HttpError>
 where Persistence: RussetPersistenceLayer {
-	let entry = state.domain_service.get_entry(&entry_id, &user.user.id).await?;
+	let entry = state.domain_service.get_entry(&entry_id, &user.user.id, &user.preferences.dates).await?;
 	match entry.url {
 		Some(url) => Ok(Redirect::to(&url)),
 		None => Ok(Redirect::to("/")),
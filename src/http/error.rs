@@ -1,9 +1,12 @@
+use axum::http::header::RETRY_AFTER;
 use axum::http::StatusCode;
 use axum::response::{ Html, IntoResponse, Redirect, Response };
+use crate::domain::model::Label;
 use crate::Err;
 use crate::persistence::model::User;
 use sailfish::RenderError;
 use sailfish::TemplateOnce;
+use std::time::Duration;
 use tracing::error;
 use ulid::Ulid;
 
@@ -12,6 +15,8 @@ pub enum HttpError {
 	Unauthenticated { redirect_to: Option<String> },
 	Forbidden,
 	NotFound,
+	/// Too many failed logins; they can be tried again after `retry_after`
+	TooManyRequests { retry_after: Duration },
 	InternalError { description: String },
 }
 impl From<Err> for HttpError {
@@ -35,11 +40,14 @@ pub struct ErrorPageTemplate<'a> {
 	error_code: &'a str,
 	error_description: &'a str,
 	user: Option<&'a User>,
+	csrf_token: &'a str,
+	labels: &'a [Label],
 	page_title: &'a str,
 	relative_root: &'a str,
 }
 impl IntoResponse for HttpError {
 	fn into_response(self) -> Response {
+		let mut retry_after_secs = None;
 		let (status, description) = match self {
 			HttpError::BadRequest { description } => (StatusCode::BAD_REQUEST, description),
 			HttpError::Unauthenticated { redirect_to } => {
@@ -51,6 +59,12 @@ impl IntoResponse for HttpError {
 			},
 			HttpError::Forbidden => (StatusCode::FORBIDDEN, "You do not have access to this resource.".to_string()),
 			HttpError::NotFound => (StatusCode::NOT_FOUND, "No resource exists at this URL.".to_string()),
+			HttpError::TooManyRequests { retry_after } => {
+				// Round up, so retrying right on time isn't still too early
+				let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+				retry_after_secs = Some(secs);
+				(StatusCode::TOO_MANY_REQUESTS, format!("Too many failed logins. Try again in {secs} seconds."))
+			},
 			HttpError::InternalError { description } => {
 				let correlation_id = Ulid::new();
 				let description = format!("Internal server error: {description} (Correlation ID: {correlation_id})");
@@ -74,6 +88,8 @@ impl IntoResponse for HttpError {
 					error_code: &status_str,
 					error_description: &description,
 					user: None,
+					csrf_token: "",
+					labels: &[],
 					page_title: &status_str,
 					relative_root: "/",
 				}
@@ -83,6 +99,9 @@ impl IntoResponse for HttpError {
 					format!("{status_str}\n<hr>\nAn error was encountered rendering the error page")
 				})
 			);
-		(status, page).into_response()
+		match retry_after_secs {
+			Some(secs) => (status, [(RETRY_AFTER, secs.to_string())], page).into_response(),
+			None => (status, page).into_response(),
+		}
 	}
 }
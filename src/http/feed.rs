@@ -1,48 +1,84 @@
 use axum::extract::{ Form, Path, State };
 use axum::response::{ Html, Redirect };
-use crate::domain::model::{ Entry, Feed };
-use crate::http::{ AppState, AuthenticatedUser, PageQuery };
+use crate::domain::model::{ Entry, Feed, Label, PageInfo };
+use crate::http::{ AppState, AuthenticatedUser, Listing, PageQuery };
+use crate::http::csrf::CsrfForm;
 use crate::http::error::HttpError;
-use crate::model::{ FeedId, Pagination };
+use crate::model::{ FeedId, Permission };
 use crate::persistence::model::User;
 use crate::persistence::RussetPersistenceLayer;
 use sailfish::TemplateOnce;
+use serde::Deserialize;
+use serde::de::IgnoredAny;
 
 #[derive(TemplateOnce)]
 #[template(path = "feed.stpl")]
 struct FeedPageTemplate<'a> {
 	user: Option<&'a User>,
+	csrf_token: &'a str,
 	entries: &'a [Entry],
 	feed: &'a Feed,
-	page_num: usize,
+	/// The folder the user has filed this feed in, if any
+	folder: Option<&'a str>,
+	listing: &'a Listing,
+	page: &'a PageInfo,
+	labels: &'a [Label],
+	/// The feed's retention settings, if the user may change them
+	retention: Option<&'a FeedRetention>,
 	page_title: &'a str,
 	relative_root: &'a str,
 }
+/// How long a feed's read entries are kept
+struct FeedRetention {
+	/// The feed's own retention period in days, if it overrides the default
+	days: Option<u32>,
+	/// The default retention period in days
+	default_days: u32,
+}
 #[tracing::instrument]
 pub async fn feed_page<Persistence>(
 	Path(feed_id): Path<FeedId>,
 	State(state): State<AppState<Persistence>>,
 	user: AuthenticatedUser<Persistence>,
-	Form(pagination): Form<PageQuery>,
+	Form(query): Form<PageQuery>,
 ) -> Result<Html<String>, HttpError>
 where Persistence: RussetPersistenceLayer {
-	let page_num = pagination.page_num.unwrap_or(0);
-	let page_size = pagination.page_size.unwrap_or(100);
-	let pagination = Pagination { page_num, page_size };
+	let listing = query.listing(&user.preferences, format!("/feed/{}", feed_id.to_string()))?;
 	let feed = state.domain_service.get_feed(&feed_id).await?;
-	let entries = state.domain_service
-		.get_feed_entries(&user.user.id, &feed_id, &pagination)
-		.await
+	let page = state.domain_service
+		.get_feed_entries(&user.user.id, &feed_id, &listing.options, &listing.pagination, &user.preferences.dates)
+		.await?;
+	let entries = page.entries
 		.into_iter()
 		.filter_map(|entry| entry.ok())
 		.collect::<Vec<Entry>>();
+	let folder = state.domain_service
+		.get_subscriptions(&user.user.id)
+		.await?
+		.into_iter()
+		.find(|subscription| subscription.feed.id == feed_id)
+		.and_then(|subscription| subscription.folder);
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	let retention = if user.user.user_type.has_permission(Permission::FeedRetention) {
+		Some(FeedRetention {
+			days: state.domain_service.get_feed_retention(&feed_id).await?,
+			default_days: state.domain_service.default_entry_retention_days(),
+		} )
+	} else {
+		None
+	};
 	let page_title = format!("Feed - {}", feed.title);
 	Ok(Html(
 		FeedPageTemplate {
 			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
 			entries: &entries.as_slice(),
 			feed: &feed,
-			page_num: pagination.page_num,
+			folder: folder.as_deref(),
+			listing: &listing,
+			page: &page.info,
+			labels: &labels,
+			retention: retention.as_ref(),
 			page_title: &page_title,
 			relative_root: "../",
 		}
@@ -55,8 +91,54 @@ pub async fn unsubscribe<Persistence>(
 	Path(feed_id): Path<FeedId>,
 	State(state): State<AppState<Persistence>>,
 	user: AuthenticatedUser<Persistence>,
+	CsrfForm(_): CsrfForm<IgnoredAny>,
 ) -> Result<Redirect, HttpError>
 where Persistence: RussetPersistenceLayer {
 	state.domain_service.unsubscribe(&user.user.id, &feed_id).await?;
 	Ok(Redirect::to("../"))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct SetFolderRequest {
+	folder: String,
+}
+#[tracing::instrument]
+pub async fn set_folder<Persistence>(
+	Path(feed_id): Path<FeedId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<SetFolderRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	state.domain_service.set_folder(&user.user.id, &feed_id, &request.folder).await?;
+	Ok(Redirect::to(&format!("../{}", feed_id.to_string())))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRetentionRequest {
+	/// Days to keep read entries for; empty to use the default
+	retention_days: String,
+}
+#[tracing::instrument]
+pub async fn set_retention<Persistence>(
+	Path(feed_id): Path<FeedId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<SetRetentionRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	if !user.user.user_type.has_permission(Permission::FeedRetention) {
+		return Err(HttpError::Forbidden);
+	}
+	let retention_days = match request.retention_days.trim() {
+		"" => None,
+		days => Some(days.parse::<u32>().map_err(|_| HttpError::BadRequest {
+			description: format!("Invalid retention period {days:?}"),
+		} )?),
+	};
+	state.domain_service
+		.set_feed_retention(&feed_id, retention_days)
+		.await
+		.map_err(|err| HttpError::BadRequest { description: err.to_string() })?;
+	Ok(Redirect::to(&format!("../{}", feed_id.to_string())))
+}
@@ -0,0 +1,78 @@
+use axum::extract::{ Query, State };
+use axum::response::{ Html, Redirect };
+use crate::domain::model::Label;
+use crate::domain::subscriptions::{ SubscriptionSort, SubscriptionSummary };
+use crate::http::{ AppState, AuthenticatedUser };
+use crate::http::csrf::CsrfForm;
+use crate::http::error::HttpError;
+use crate::model::FeedId;
+use crate::persistence::model::User;
+use crate::persistence::RussetPersistenceLayer;
+use sailfish::TemplateOnce;
+use serde::Deserialize;
+
+#[derive(TemplateOnce)]
+#[template(path = "feeds.stpl")]
+struct FeedsPageTemplate<'a> {
+	user: Option<&'a User>,
+	csrf_token: &'a str,
+	subscriptions: &'a [SubscriptionSummary],
+	labels: &'a [Label],
+	page_title: &'a str,
+	relative_root: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedsQuery {
+	#[serde(default)]
+	sort: SubscriptionSort,
+}
+
+#[tracing::instrument]
+pub async fn feeds_page<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	Query(query): Query<FeedsQuery>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let subscriptions = state.domain_service
+		.get_subscription_summaries(&user.user.id, query.sort, user.preferences.dates.timezone)
+		.await?;
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	Ok(Html(
+		FeedsPageTemplate {
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			subscriptions: &subscriptions,
+			labels: &labels,
+			page_title: "Subscriptions",
+			relative_root: "",
+		}
+		.render_once()?
+	) )
+}
+
+/// Unsubscribe from every feed selected on the subscriptions page. Feeds are
+/// selected by `select-<feed ID>` keys, like entries on the root page.
+#[tracing::instrument]
+pub async fn unsubscribe_feeds<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<Vec<(String, String)>>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let mut feed_ids = Vec::new();
+	for (key, _) in &request {
+		match key.strip_prefix("select-") {
+			Some(id) => {
+				let id = ulid::Ulid::from_string(id)
+					.map_err(|_| HttpError::BadRequest { description: format!("Bad feed ID: {id:?}") })?;
+				feed_ids.push(FeedId(id));
+			},
+			None if key == "action" => (),
+			None => return Err(HttpError::BadRequest { description: format!("Bad key: {key:?}") }),
+		}
+	}
+	state.domain_service.unsubscribe_all(&user.user.id, &feed_ids).await?;
+	Ok(Redirect::to("feeds"))
+}
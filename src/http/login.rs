@@ -1,11 +1,18 @@
 use axum::extract::{ Form, State };
 use axum_extra::extract::cookie::{ Cookie, CookieJar, Expiration };
 use axum::response::{ Html, Redirect };
-use crate::http::AppState;
+use crate::domain::model::{ Label, LoginOutcome };
+use crate::domain::RussetDomainService;
+use crate::http::{ AppState, AuthenticatedUser };
+use crate::http::csrf::{ check_csrf_token, CsrfForm };
 use crate::http::error::HttpError;
+use crate::http::session::cookie;
+use crate::persistence::model::SessionClient;
 use crate::persistence::RussetPersistenceLayer;
+use percent_encoding::{ NON_ALPHANUMERIC, utf8_percent_encode };
 use sailfish::TemplateOnce;
 use serde::Deserialize;
+use serde::de::IgnoredAny;
 
 #[derive(Debug, TemplateOnce)]
 #[template(path = "login.stpl")]
@@ -14,26 +21,87 @@ pub struct LoginPageTemplate<'a> {
 	page_title: &'a str,
 	relative_root: &'a str,
 	user: Option<&'a crate::persistence::model::User>,
+	csrf_token: &'a str,
+	labels: &'a [Label],
 }
 #[derive(Debug, Deserialize)]
 pub struct LoginPageQuery {
 	redirect_to: Option<String>,
 }
-#[tracing::instrument]
+/// The cookie holding the CSRF token for forms used without a session, like
+/// the login form. The form has to carry the same token, which other sites
+/// can't read.
+const LOGIN_CSRF_COOKIE: &str = "login_csrf";
+/// The cookie holding the token of a login waiting for its second factor
+const PENDING_LOGIN_COOKIE: &str = "login_pending";
+
+/// Get the CSRF token for forms used without a session, setting its cookie
+pub(super) fn anonymous_csrf_token<Persistence>(
+	state: &AppState<Persistence>,
+	cookies: CookieJar,
+) -> Result<(CookieJar, String), HttpError>
+where Persistence: RussetPersistenceLayer {
+	// Keep any token already set, so using a form from an older tab still works
+	let csrf_token = match cookies.get(LOGIN_CSRF_COOKIE) {
+		Some(csrf_cookie) => csrf_cookie.value().to_string(),
+		None => RussetDomainService::<Persistence>::login_csrf_token()?,
+	};
+	let csrf_cookie = cookie(LOGIN_CSRF_COOKIE, csrf_token.clone(), state.secure_cookies);
+	Ok((cookies.add(csrf_cookie), csrf_token))
+}
+
+/// With a reverse proxy authenticating users, there's no logging in with a
+/// password, nor registering to get one
+pub(super) fn check_password_logins<Persistence>(state: &AppState<Persistence>) -> Result<(), HttpError>
+where Persistence: RussetPersistenceLayer {
+	if state.proxy_auth.is_some() {
+		return Err(HttpError::BadRequest {
+			description: "Logging in is handled by the proxy in front of Russet.".to_string(),
+		})
+	}
+	Ok(())
+}
+
+/// With a reverse proxy authenticating users, Russet has no sessions of its
+/// own to log out of or manage
+pub(super) fn check_sessions<Persistence>(state: &AppState<Persistence>) -> Result<(), HttpError>
+where Persistence: RussetPersistenceLayer {
+	if state.proxy_auth.is_some() {
+		return Err(HttpError::BadRequest {
+			description: "Sessions aren't available with proxy authentication. Log out through the proxy in front of Russet.".to_string(),
+		})
+	}
+	Ok(())
+}
+
+/// Check a form used without a session carried the token from its cookie
+pub(super) fn check_anonymous_csrf_token(cookies: &CookieJar, csrf_token: &str) -> Result<(), HttpError> {
+	check_csrf_token(csrf_token, cookies.get(LOGIN_CSRF_COOKIE).map(|csrf_cookie| csrf_cookie.value()))
+}
+
+#[tracing::instrument(skip(cookies))]
 pub async fn login_page<Persistence>(
-	State(_state): State<AppState<Persistence>>,
+	State(state): State<AppState<Persistence>>,
+	cookies: CookieJar,
 	Form(login): Form<LoginPageQuery>,
-) -> Result<Html<String>, HttpError>
+) -> Result<(CookieJar, Html<String>), HttpError>
 where Persistence: RussetPersistenceLayer {
-	Ok(Html(
-		LoginPageTemplate{
-			redirect_to: login.redirect_to.as_ref().map(|redirect| redirect.as_str()),
-			page_title: "Login",
-			relative_root: "",
-			user: None,
-		}
-		.render_once()?
-	) )
+	check_password_logins(&state)?;
+	let (cookies, csrf_token) = anonymous_csrf_token(&state, cookies)?;
+	Ok((
+		cookies,
+		Html(
+			LoginPageTemplate{
+				redirect_to: login.redirect_to.as_ref().map(|redirect| redirect.as_str()),
+				page_title: "Login",
+				labels: &[],
+				relative_root: "",
+				user: None,
+				csrf_token: &csrf_token,
+			}
+			.render_once()?
+		),
+	))
 }
 
 #[derive(Deserialize, Clone)]
@@ -43,6 +111,8 @@ pub struct LoginRequest {
 	redirect_to: Option<String>,
 	#[serde(default = "default_permanent_session")]
 	permanent_session: bool,
+	#[serde(default)]
+	csrf_token: String,
 }
 // This is dumb.
 fn default_permanent_session() -> bool { false }
@@ -53,39 +123,157 @@ impl std::fmt::Debug for LoginRequest {
 			.field("plaintext_password", &"<redacted>")
 			.field("redirect_to", &self.redirect_to)
 			.field("permanent_session", &self.permanent_session)
+			.field("csrf_token", &"<redacted>")
 			.finish()
 	}
 }
-#[tracing::instrument]
+#[tracing::instrument(skip(cookies))]
 pub async fn login_user<Persistence>(
 	State(state): State<AppState<Persistence>>,
 	cookies: CookieJar,
+	client: SessionClient,
 	Form(login): Form<LoginRequest>,
 ) -> Result<(CookieJar, Redirect), HttpError>
 where Persistence: RussetPersistenceLayer {
-	let session = state.domain_service
+	check_password_logins(&state)?;
+	check_anonymous_csrf_token(&cookies, &login.csrf_token)?;
+	let outcome = state.domain_service
 		.login_user(
 			login.user_name,
 			login.plaintext_password,
 			login.permanent_session,
+			client,
 		)
 		.await?;
-	match session {
-		Some(session) => {
-			let cookie = Cookie::build(("session_id", session.token.0))
-				.expires(
-					if login.permanent_session {
-						Expiration::DateTime(session.expiration.0.into())
-					} else {
-						Expiration::Session
-					}
-				)
-				.build();
+	login_response(&state, cookies, outcome, login.redirect_to)
+}
+
+/// Respond to the outcome of either step of logging in
+fn login_response<Persistence>(
+	state: &AppState<Persistence>,
+	cookies: CookieJar,
+	outcome: LoginOutcome,
+	redirect_to: Option<String>,
+) -> Result<(CookieJar, Redirect), HttpError>
+where Persistence: RussetPersistenceLayer {
+	match outcome {
+		LoginOutcome::LoggedIn { session, permanent_session } => {
+			let mut session_cookie = cookie("session_id", session.token.0, state.secure_cookies);
+			session_cookie.set_expires(
+				if permanent_session {
+					Expiration::DateTime(session.expiration.0.into())
+				} else {
+					Expiration::Session
+				}
+			);
 			Ok((
-				cookies.add(cookie),
-				Redirect::to(&login.redirect_to.unwrap_or("/".to_string())),
+				cookies
+					.remove(Cookie::build(LOGIN_CSRF_COOKIE).path("/"))
+					.remove(Cookie::build(PENDING_LOGIN_COOKIE).path("/"))
+					.add(session_cookie),
+				Redirect::to(&redirect_to.unwrap_or("/".to_string())),
 			))
 		},
-		None => Err(HttpError::Unauthenticated { redirect_to: login.redirect_to }),
+		LoginOutcome::SecondFactorRequired { token } => {
+			let pending_cookie = cookie(PENDING_LOGIN_COOKIE, token, state.secure_cookies);
+			let redirect = format!("/login/code{}", redirect_to.map_or(
+					"".to_string(),
+					|redirect| format!("?redirect_to={}", utf8_percent_encode(&redirect, NON_ALPHANUMERIC)),
+				) );
+			Ok((cookies.add(pending_cookie), Redirect::to(&redirect)))
+		},
+		LoginOutcome::Failed => Err(HttpError::Unauthenticated { redirect_to }),
+		LoginOutcome::Blocked { retry_after } => Err(HttpError::TooManyRequests { retry_after }),
+	}
+}
+
+#[derive(Debug, TemplateOnce)]
+#[template(path = "login_code.stpl")]
+pub struct LoginCodePageTemplate<'a> {
+	redirect_to: Option<&'a str>,
+	page_title: &'a str,
+	relative_root: &'a str,
+	user: Option<&'a crate::persistence::model::User>,
+	csrf_token: &'a str,
+	labels: &'a [Label],
+}
+/// Ask for the second factor of a login whose password was right
+#[tracing::instrument(skip(cookies))]
+pub async fn login_code_page<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	cookies: CookieJar,
+	Form(login): Form<LoginPageQuery>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_password_logins(&state)?;
+	let csrf_token = match (cookies.get(LOGIN_CSRF_COOKIE), cookies.get(PENDING_LOGIN_COOKIE)) {
+		(Some(csrf_cookie), Some(_)) => csrf_cookie.value().to_string(),
+		_ => return Err(HttpError::Unauthenticated { redirect_to: login.redirect_to }),
+	};
+	Ok(Html(
+		LoginCodePageTemplate{
+			redirect_to: login.redirect_to.as_deref(),
+			page_title: "Login",
+			labels: &[],
+			relative_root: "../",
+			user: None,
+			csrf_token: &csrf_token,
+		}
+		.render_once()?
+	) )
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LoginCodeRequest {
+	code: String,
+	redirect_to: Option<String>,
+	#[serde(default)]
+	csrf_token: String,
+}
+impl std::fmt::Debug for LoginCodeRequest {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("LoginCodeRequest")
+			.field("code", &"<redacted>")
+			.field("redirect_to", &self.redirect_to)
+			.field("csrf_token", &"<redacted>")
+			.finish()
+	}
+}
+#[tracing::instrument(skip(cookies))]
+pub async fn login_code<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	cookies: CookieJar,
+	client: SessionClient,
+	Form(login): Form<LoginCodeRequest>,
+) -> Result<(CookieJar, Redirect), HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_password_logins(&state)?;
+	check_anonymous_csrf_token(&cookies, &login.csrf_token)?;
+	let Some(token) = cookies.get(PENDING_LOGIN_COOKIE).map(|pending_cookie| pending_cookie.value().to_string()) else {
+		return Err(HttpError::Unauthenticated { redirect_to: login.redirect_to })
+	};
+	let outcome = state.domain_service
+		.login_second_factor(&token, &login.code, client)
+		.await?;
+	login_response(&state, cookies, outcome, login.redirect_to)
+}
+
+/// Log out of the current session, ending it everywhere rather than just
+/// forgetting its cookie
+#[tracing::instrument(skip(cookies))]
+pub async fn logout<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	_user: AuthenticatedUser<Persistence>,
+	cookies: CookieJar,
+	CsrfForm(_): CsrfForm<IgnoredAny>,
+) -> Result<(CookieJar, Redirect), HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_sessions(&state)?;
+	if let Some(session_cookie) = cookies.get("session_id") {
+		state.domain_service.logout(session_cookie.value()).await?;
 	}
+	Ok((
+		cookies.remove(Cookie::build("session_id").path("/")),
+		Redirect::to("/login"),
+	))
 }
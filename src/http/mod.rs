@@ -1,29 +1,45 @@
-use axum::middleware::map_response;
+use axum::http::HeaderName;
+use axum::middleware::{ map_request, map_response };
 use axum::response::Response;
 use axum::Router;
 use axum::routing::{ any, get, post };
 use crate::domain::RussetDomainService;
+use crate::domain::user::MAX_PAGE_SIZE;
 use crate::http::session::AuthenticatedUser;
+use crate::http::error::HttpError;
+use crate::model::{ EntryCursor, EntryPagination, EntrySort, ListingOptions, PagePosition };
+use crate::persistence::model::UserPreferences;
 use crate::persistence::RussetPersistenceLayer;
 use serde::Deserialize;
+use std::net::IpAddr;
 use std::sync::Arc;
 use tokio::sync::Semaphore;
 use tower::limit::GlobalConcurrencyLimitLayer;
 use tower_http::compression::CompressionLayer;
 
+mod admin;
+mod csrf;
 mod entry;
 pub mod error;
 mod feed;
+mod feeds;
 mod login;
+mod opml;
+mod register;
 mod root;
+mod rules;
+mod scrape;
+mod search;
 mod session;
 mod static_routes;
 mod subscribe;
 mod user;
+mod websub;
 
 pub fn russet_router<Persistence>(
 	global_concurrent_limit: u32,
 	login_concurrent_limit: u32,
+	trusted_proxies: Vec<IpAddr>,
 ) -> Router<AppState<Persistence>>
 where Persistence: RussetPersistenceLayer {
 	let global_limit_semaphore = Arc::new(
@@ -40,19 +56,58 @@ where Persistence: RussetPersistenceLayer {
 				.expect("login concurrency limit should *definitely* fit in a usize")
 		)
 	);
+	let trusted_proxies = Arc::new(trusted_proxies);
 	Router::new()
 		.route("/login", post(login::login_user))
+		.route("/login/code", post(login::login_code))
+		.route("/register/:token", post(register::register))
 		.layer(GlobalConcurrencyLimitLayer::with_semaphore(login_limit_sempahore))
 		.route("/login", get(login::login_page))
+		.route("/login/code", get(login::login_code_page))
+		.route("/register/:token", get(register::register_page))
+		.route("/logout", post(login::logout))
 		.route("/styles.css", get(static_routes::styles))
 		.route("/", get(root::root).post(root::edit_userentries))
+		.route("/starred", get(root::starred))
+		.route("/label/:name", get(root::label_page))
+		.route("/folder/:name", get(root::folder_page))
 		.route("/entry/:id", get(entry::mark_read_redirect))
 		.route("/feed/:id", get(feed::feed_page).post(feed::unsubscribe))
+		.route("/feed/:id/folder", post(feed::set_folder))
+		.route("/feed/:id/retention", post(feed::set_retention))
+		.route("/feeds", get(feeds::feeds_page).post(feeds::unsubscribe_feeds))
 		.route("/user/:id", get(user::user_page))
+		.route("/user/:id/password", post(user::change_password))
+		.route("/user/:id/preferences", post(user::save_preferences))
+		.route("/user/:id/sessions", get(user::sessions_page).post(user::log_out_other_sessions))
+		.route("/user/:id/sessions/:session_id", post(user::end_session))
+		.route("/user/:id/two_factor", get(user::two_factor_page).post(user::enable_two_factor))
+		.route("/user/:id/two_factor/recovery_codes", post(user::regenerate_recovery_codes))
+		.route("/user/:id/two_factor/disable", post(user::disable_two_factor))
+		.route("/admin/users", get(admin::users_page).post(admin::add_user))
+		.route("/admin/users/:id", get(admin::user_page))
+		.route("/admin/users/:id/type", post(admin::set_user_type))
+		.route("/admin/users/:id/disabled", post(admin::set_disabled))
+		.route("/admin/users/:id/password", post(admin::reset_password))
+		.route("/admin/users/:id/sessions", post(admin::revoke_sessions))
+		.route("/admin/users/:id/delete", post(admin::delete_user))
+		.route("/admin/invitations", get(admin::invitations_page).post(admin::create_invitation))
+		.route("/admin/invitations/:id", post(admin::revoke_invitation))
+		.route("/preferences/listing", post(user::save_listing_defaults))
 		.route("/subscribe", get(subscribe::subscribe_page).post(subscribe::subscribe))
+		.route("/scrape", get(scrape::scrape_page).post(scrape::scrape))
+		.route("/opml", get(opml::opml_page).post(opml::import_opml))
+		.route("/opml/export", get(opml::export_opml))
+		.route("/rules", get(rules::rules_page).post(rules::add_rule))
+		.route("/rules/test", get(rules::test_rule))
+		.route("/rules/apply", post(rules::apply_rules))
+		.route("/rules/:id", post(rules::delete_rule))
+		.route("/search", get(search::search_page))
+		.route("/websub/:id", get(websub::verify_intent).post(websub::receive_content))
 		.route("/error", get(|| async { error::HttpError::InternalError { description: "Juicy details!".to_string() }}))
 		.route("/*any", any(|| async { error::HttpError::NotFound }))
 		.layer(GlobalConcurrencyLimitLayer::with_semaphore(global_limit_semaphore))
+		.layer(map_request(move |request| session::resolve_client_ip(trusted_proxies.clone(), request)))
 		.layer(map_response(csp_header))
 		.layer(CompressionLayer::new())
 }
@@ -74,15 +129,100 @@ async fn csp_header<B>(mut response: Response<B>) -> Response<B> {
 pub struct AppState<Persistence>
 where Persistence: RussetPersistenceLayer {
 	pub domain_service: Arc<RussetDomainService<Persistence>>,
+	/// Whether cookies should only be sent over HTTPS
+	pub secure_cookies: bool,
+	/// If set, users are authenticated by a reverse proxy instead of logging
+	/// in
+	pub proxy_auth: Option<Arc<ProxyAuth>>,
 }
 impl <Persistence> Clone for AppState<Persistence>
 where Persistence: RussetPersistenceLayer {
-	fn clone(&self) -> Self { AppState { domain_service: self.domain_service.clone() } }
+	fn clone(&self) -> Self {
+		AppState {
+			domain_service: self.domain_service.clone(),
+			secure_cookies: self.secure_cookies,
+			proxy_auth: self.proxy_auth.clone(),
+		}
+	}
 }
 
+/// Authentication by a reverse proxy: requests from one of `addresses` are
+/// taken to be from the user named in their `header`
+#[derive(Debug)]
+pub struct ProxyAuth {
+	pub header: HeaderName,
+	pub addresses: Vec<IpAddr>,
+	/// Whether to add users the proxy names who don't exist yet
+	pub provision_users: bool,
+}
+
+/// Query parameters for entry listings. Anything not given falls back to the
+/// user's saved [UserPreferences]. At most one of `after`, `before` and
+/// `last` may be given; with none of them, the first page is shown.
 #[derive(Debug, Deserialize)]
 struct PageQuery {
-	page_num: Option<usize>,
+	after: Option<EntryCursor>,
+	before: Option<EntryCursor>,
+	#[serde(default)]
+	last: bool,
 	page_size: Option<usize>,
+	unread_only: Option<bool>,
+	oldest_first: Option<bool>,
+	sort: Option<EntrySort>,
+}
+impl PageQuery {
+	/// Resolve this query against the user's preferences, for the listing at
+	/// the given path
+	fn listing(&self, preferences: &UserPreferences, path: String) -> Result<Listing, HttpError> {
+		let position = match (self.after, self.before, self.last) {
+			(None, None, false) => PagePosition::First,
+			(Some(cursor), None, false) => PagePosition::After(cursor),
+			(None, Some(cursor), false) => PagePosition::Before(cursor),
+			(None, None, true) => PagePosition::Last,
+			_ => return Err(HttpError::BadRequest {
+				description: "Give at most one of \"after\", \"before\" and \"last\"".to_string(),
+			} ),
+		};
+		let mut query = String::new();
+		if let Some(page_size) = self.page_size {
+			query.push_str(&format!("&page_size={page_size}"));
+		}
+		if let Some(unread_only) = self.unread_only {
+			query.push_str(&format!("&unread_only={unread_only}"));
+		}
+		if let Some(oldest_first) = self.oldest_first {
+			query.push_str(&format!("&oldest_first={oldest_first}"));
+		}
+		if let Some(sort) = self.sort {
+			query.push_str(&format!("&sort={}", sort.as_str()));
+		}
+		Ok(Listing {
+			pagination: EntryPagination {
+				position,
+				page_size: self.page_size.unwrap_or(preferences.page_size).clamp(1, MAX_PAGE_SIZE),
+			},
+			options: ListingOptions {
+				unread_only: self.unread_only.unwrap_or(preferences.listing.unread_only),
+				oldest_first: self.oldest_first.unwrap_or(preferences.listing.oldest_first),
+				sort: self.sort.unwrap_or(preferences.listing.sort),
+			},
+			path,
+			query,
+		} )
+	}
+}
+
+/// An entry listing page's resolved [PageQuery], for its listing options form
+/// and pagination links
+#[derive(Debug)]
+struct Listing {
+	pagination: EntryPagination,
+	options: ListingOptions,
+	/// The listing's own path, to come back to after saving its options as
+	/// the user's defaults
+	path: String,
+	/// Query parameters for the options the request gave, so pagination links
+	/// keep them. Empty, or starting with `&`.
+	query: String,
 }
 
@@ -0,0 +1,103 @@
+use axum::extract::{ Extension, Multipart, State };
+use axum::http::header;
+use axum::response::{ Html, IntoResponse, Response };
+use crate::domain::model::Label;
+use crate::domain::opml::OpmlImport;
+use crate::http::AppState;
+use crate::http::csrf::{ CSRF_FIELD, SessionCsrfToken };
+use crate::http::error::HttpError;
+use crate::http::session::AuthenticatedUser;
+use crate::model::Permission;
+use crate::persistence::model::User;
+use crate::persistence::RussetPersistenceLayer;
+use sailfish::TemplateOnce;
+
+#[derive(TemplateOnce)]
+#[template(path = "opml.stpl")]
+struct OpmlPageTemplate<'a> {
+	user: Option<&'a User>,
+	csrf_token: &'a str,
+	imports: Option<&'a [OpmlImport]>,
+	labels: &'a [Label],
+	page_title: &'a str,
+	relative_root: &'a str,
+}
+
+#[tracing::instrument]
+pub async fn opml_page<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	Ok(Html(
+		OpmlPageTemplate {
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			imports: None,
+			labels: &labels,
+			page_title: "Import/Export",
+			relative_root: "",
+		}
+		.render_once()?
+	) )
+}
+
+#[tracing::instrument]
+pub async fn export_opml<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+) -> Result<Response, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let opml = state.domain_service.export_opml(&user.user.id).await?;
+	Ok((
+		[
+			(header::CONTENT_TYPE, "text/x-opml; charset=utf-8"),
+			(header::CONTENT_DISPOSITION, "attachment; filename=\"subscriptions.opml\""),
+		],
+		opml,
+	).into_response())
+}
+
+#[tracing::instrument(skip(multipart))]
+pub async fn import_opml<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	Extension(expected_csrf_token): Extension<SessionCsrfToken>,
+	mut multipart: Multipart,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let mut opml = None;
+	let mut csrf_token = None;
+	while let Some(field) = multipart.next_field().await.map_err(bad_upload)? {
+		match field.name() {
+			Some("file") => opml = Some(field.text().await.map_err(bad_upload)?),
+			Some(CSRF_FIELD) => csrf_token = Some(field.text().await.map_err(bad_upload)?),
+			_ => (),
+		}
+	}
+	// Uploads aren't forms CsrfForm can read, so check the token here
+	expected_csrf_token.check(csrf_token.as_deref())?;
+	let opml = opml.ok_or_else(|| HttpError::BadRequest { description: "No OPML file uploaded".to_string() })?;
+	let allow_local_sources = user.user.user_type.has_permission(Permission::LocalFeedSources);
+	let imports = state.domain_service
+		.import_opml(&user.user.id, &opml, allow_local_sources)
+		.await
+		.map_err(|e| HttpError::BadRequest { description: format!("Could not import OPML: {e}") })?;
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	Ok(Html(
+		OpmlPageTemplate {
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			imports: Some(&imports),
+			labels: &labels,
+			page_title: "Import/Export",
+			relative_root: "",
+		}
+		.render_once()?
+	) )
+}
+
+fn bad_upload(e: axum::extract::multipart::MultipartError) -> HttpError {
+	HttpError::BadRequest { description: format!("Could not read upload: {e}") }
+}
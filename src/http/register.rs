@@ -0,0 +1,89 @@
+use axum::extract::{ Form, Path, State };
+use axum::response::{ Html, Redirect };
+use axum_extra::extract::cookie::CookieJar;
+use crate::domain::model::Label;
+use crate::http::AppState;
+use crate::http::error::HttpError;
+use crate::http::login::{ anonymous_csrf_token, check_anonymous_csrf_token, check_password_logins };
+use crate::model::UserType;
+use crate::persistence::RussetPersistenceLayer;
+use sailfish::TemplateOnce;
+use serde::Deserialize;
+
+#[derive(Debug, TemplateOnce)]
+#[template(path = "register.stpl")]
+pub struct RegisterPageTemplate<'a> {
+	token: &'a str,
+	user_type: UserType,
+	page_title: &'a str,
+	relative_root: &'a str,
+	user: Option<&'a crate::persistence::model::User>,
+	csrf_token: &'a str,
+	labels: &'a [Label],
+}
+
+fn invalid_invitation() -> HttpError {
+	HttpError::BadRequest { description: "This invitation doesn't exist, has already been used, or has expired.".to_string() }
+}
+
+/// Let whoever has an invitation's link register
+#[tracing::instrument(skip(token, cookies))]
+pub async fn register_page<Persistence>(
+	Path(token): Path<String>,
+	State(state): State<AppState<Persistence>>,
+	cookies: CookieJar,
+) -> Result<(CookieJar, Html<String>), HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_password_logins(&state)?;
+	let invitation = state.domain_service
+		.get_invitation(&token)
+		.await?
+		.ok_or_else(invalid_invitation)?;
+	let (cookies, csrf_token) = anonymous_csrf_token(&state, cookies)?;
+	Ok((
+		cookies,
+		Html(
+			RegisterPageTemplate {
+				token: &token,
+				user_type: invitation.user_type,
+				page_title: "Register",
+				relative_root: "../",
+				user: None,
+				csrf_token: &csrf_token,
+				labels: &[],
+			}
+			.render_once()?
+		),
+	))
+}
+
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+	user_name: String,
+	password: String,
+	confirm_password: String,
+	#[serde(default)]
+	csrf_token: String,
+}
+#[tracing::instrument(skip(token, cookies, request))]
+pub async fn register<Persistence>(
+	Path(token): Path<String>,
+	State(state): State<AppState<Persistence>>,
+	cookies: CookieJar,
+	Form(request): Form<RegisterRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_password_logins(&state)?;
+	check_anonymous_csrf_token(&cookies, &request.csrf_token)?;
+	if request.password != request.confirm_password {
+		return Err(HttpError::BadRequest { description: "The passwords don't match".to_string() })
+	}
+	let registered = state.domain_service
+		.register_user(&token, request.user_name.trim(), &request.password)
+		.await
+		.map_err(|e| HttpError::BadRequest { description: e.to_string() })?;
+	if !registered {
+		return Err(invalid_invitation())
+	}
+	Ok(Redirect::to("/login"))
+}
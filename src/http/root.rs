@@ -1,24 +1,40 @@
-use axum::extract::{ Form, State };
+use axum::extract::{ Form, Path, State };
 use axum::response::{ Html, Redirect };
-use crate::domain::model::{ Entry, Feed };
-use crate::http::{ AppState, PageQuery };
+use crate::domain::model::{ Entry, Feed, Label, PageInfo };
+use crate::domain::search::parse_date;
+use crate::http::{ AppState, Listing, PageQuery };
+use crate::http::csrf::CsrfForm;
 use crate::http::error::HttpError;
 use crate::http::session::AuthenticatedUser;
-use crate::model::{ EntryId, FeedId, Pagination, Timestamp };
-use crate::persistence::model::{ User, UserEntry };
+use crate::model::{ EntryId, FeedId, MarkReadScope };
+use crate::persistence::model::User;
 use crate::persistence::RussetPersistenceLayer;
 use sailfish::TemplateOnce;
 use std::collections::HashMap;
-use std::time::SystemTime;
+
+/// The entries a [RootPageTemplate] lists
+enum ListingScope<'a> {
+	All,
+	Starred,
+	Label(&'a str),
+	Folder(&'a str),
+}
 
 // Root (home/entries) page template
 #[derive(TemplateOnce)]
 #[template(path = "root.stpl")]
 struct RootPageTemplate<'a> {
 	user: Option<&'a User>,
+	csrf_token: &'a str,
 	entries: &'a [Entry],
 	feeds: &'a HashMap<FeedId, Feed>,
-	page_num: usize,
+	listing: &'a Listing,
+	page: &'a PageInfo,
+	folders: &'a [String],
+	/// Which entries are being shown, so marking them all read only marks
+	/// those
+	scope: ListingScope<'a>,
+	labels: &'a [Label],
 	page_title: &'a str,
 	relative_root: &'a str,
 }
@@ -26,18 +42,17 @@ struct RootPageTemplate<'a> {
 pub async fn root<Persistence>(
 	State(state): State<AppState<Persistence>>,
 	user: AuthenticatedUser<Persistence>,
-	Form(pagination): Form<PageQuery>,
+	Form(query): Form<PageQuery>,
 ) -> Result<Html<String>, HttpError>
 where Persistence: RussetPersistenceLayer {
-	let page_num = pagination.page_num.unwrap_or(0);
-	let page_size = pagination.page_size.unwrap_or(100);
-	let pagination = Pagination { page_num, page_size };
+	let listing = query.listing(&user.preferences, "/".to_string())?;
 	// TODO: If every element of entries or feeds is Err, we didn't partially
 	// succeed, we utterly failed, and we should indicate that.
 	// TODO: Also we should probably indicate partial failure.
-	let entries = state.domain_service
-		.get_subscribed_entries(&user.user.id, &pagination)
-		.await
+	let page = state.domain_service
+		.get_subscribed_entries(&user.user.id, &listing.options, &listing.pagination, &user.preferences.dates)
+		.await?;
+	let entries = page.entries
 		.into_iter()
 		.filter_map(|entry| entry.ok())
 		.collect::<Vec<Entry>>();
@@ -48,12 +63,19 @@ where Persistence: RussetPersistenceLayer {
 		.filter_map(|feed| feed.ok())
 		.map(|feed| (feed.id.clone(), feed))
 		.collect::<HashMap<FeedId, Feed>>();
+	let folders = state.domain_service.get_folders(&user.user.id).await?;
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
 	Ok(Html(
 		RootPageTemplate {
 			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
 			entries: entries.as_slice(),
 			feeds: &feeds,
-			page_num: pagination.page_num,
+			listing: &listing,
+			page: &page.info,
+			folders: &folders,
+			scope: ListingScope::All,
+			labels: &labels,
 			page_title: "Entries",
 			relative_root: "",
 		}
@@ -61,22 +83,191 @@ where Persistence: RussetPersistenceLayer {
 	) )
 }
 
+#[tracing::instrument]
+pub async fn starred<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	Form(query): Form<PageQuery>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let listing = query.listing(&user.preferences, "/starred".to_string())?;
+	let page = state.domain_service
+		.get_starred_entries(&user.user.id, &listing.options, &listing.pagination, &user.preferences.dates)
+		.await?;
+	let entries = page.entries
+		.into_iter()
+		.filter_map(|entry| entry.ok())
+		.collect::<Vec<Entry>>();
+	let feeds = state.domain_service
+		.feeds_for_user(&user.user.id)
+		.await
+		.into_iter()
+		.filter_map(|feed| feed.ok())
+		.map(|feed| (feed.id, feed))
+		.collect::<HashMap<FeedId, Feed>>();
+	let folders = state.domain_service.get_folders(&user.user.id).await?;
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	Ok(Html(
+		RootPageTemplate {
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			entries: entries.as_slice(),
+			feeds: &feeds,
+			listing: &listing,
+			page: &page.info,
+			folders: &folders,
+			scope: ListingScope::Starred,
+			labels: &labels,
+			page_title: "Starred",
+			relative_root: "",
+		}
+		.render_once()?
+	) )
+}
+
+#[tracing::instrument]
+pub async fn label_page<Persistence>(
+	Path(label): Path<String>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	Form(query): Form<PageQuery>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let path = format!(
+		"/label/{}",
+		percent_encoding::utf8_percent_encode(&label, percent_encoding::NON_ALPHANUMERIC),
+	);
+	let listing = query.listing(&user.preferences, path)?;
+	let page = state.domain_service
+		.get_labeled_entries(&user.user.id, &label, &listing.options, &listing.pagination, &user.preferences.dates)
+		.await?;
+	let entries = page.entries
+		.into_iter()
+		.filter_map(|entry| entry.ok())
+		.collect::<Vec<Entry>>();
+	let feeds = state.domain_service
+		.feeds_for_user(&user.user.id)
+		.await
+		.into_iter()
+		.filter_map(|feed| feed.ok())
+		.map(|feed| (feed.id, feed))
+		.collect::<HashMap<FeedId, Feed>>();
+	let folders = state.domain_service.get_folders(&user.user.id).await?;
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	let page_title = format!("Label - {label}");
+	Ok(Html(
+		RootPageTemplate {
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			entries: entries.as_slice(),
+			feeds: &feeds,
+			listing: &listing,
+			page: &page.info,
+			folders: &folders,
+			scope: ListingScope::Label(&label),
+			labels: &labels,
+			page_title: &page_title,
+			relative_root: "../",
+		}
+		.render_once()?
+	) )
+}
+
+#[tracing::instrument]
+pub async fn folder_page<Persistence>(
+	Path(folder): Path<String>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	Form(query): Form<PageQuery>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let path = format!(
+		"/folder/{}",
+		percent_encoding::utf8_percent_encode(&folder, percent_encoding::NON_ALPHANUMERIC),
+	);
+	let listing = query.listing(&user.preferences, path)?;
+	let page = state.domain_service
+		.get_folder_entries(&user.user.id, &folder, &listing.options, &listing.pagination, &user.preferences.dates)
+		.await?;
+	let entries = page.entries
+		.into_iter()
+		.filter_map(|entry| entry.ok())
+		.collect::<Vec<Entry>>();
+	let feeds = state.domain_service
+		.feeds_for_user(&user.user.id)
+		.await
+		.into_iter()
+		.filter_map(|feed| feed.ok())
+		.map(|feed| (feed.id, feed))
+		.collect::<HashMap<FeedId, Feed>>();
+	let folders = state.domain_service.get_folders(&user.user.id).await?;
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	let page_title = format!("Folder - {folder}");
+	Ok(Html(
+		RootPageTemplate {
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			entries: entries.as_slice(),
+			feeds: &feeds,
+			listing: &listing,
+			page: &page.info,
+			folders: &folders,
+			scope: ListingScope::Folder(&folder),
+			labels: &labels,
+			page_title: &page_title,
+			relative_root: "../",
+		}
+		.render_once()?
+	) )
+}
+
 #[derive(Debug)]
 enum Action {
 	MarkRead,
+	/// Mark every entry shown on the page read
+	MarkPageRead,
+	/// Mark every entry in the page's feed or folder (or every entry, on the
+	/// root page) read
+	MarkAllRead,
+	/// Like [MarkAllRead](Action::MarkAllRead), but only for entries dated
+	/// before a given day
+	MarkOlderRead,
 	Delete,
+	Star,
+	Unstar,
+	AddLabel,
+	RemoveLabel,
 }
 #[derive(Debug)]
 pub struct EditUserEntriesRequest {
 	action: Action,
 	select_all: bool,
 	selected_ids: Vec<EntryId>,
+	/// Every entry shown on the page the request came from
+	page_ids: Vec<EntryId>,
+	/// The feed whose page the request came from, if any
+	feed_id: Option<FeedId>,
+	/// The folder whose page the request came from, if any
+	folder: Option<String>,
+	/// Whether the request came from the starred entries page
+	starred: bool,
+	/// The label whose page the request came from, if any
+	page_label: Option<String>,
+	label: Option<String>,
+	before: Option<String>,
 }
 impl EditUserEntriesRequest {
 	fn from_raw_entries(entries: &Vec<(String, String)>) -> crate::Result<EditUserEntriesRequest> {
 		let mut action: Option<Action> = None;
 		let mut select_all = false;
 		let mut selected_ids: Vec<EntryId> = Vec::new();
+		let mut page_ids: Vec<EntryId> = Vec::new();
+		let mut feed_id: Option<FeedId> = None;
+		let mut folder: Option<String> = None;
+		let mut starred = false;
+		let mut page_label: Option<String> = None;
+		let mut label: Option<String> = None;
+		let mut before: Option<String> = None;
 		for (key, value) in entries {
 			match key.as_str() {
 				"action" => {
@@ -85,11 +276,25 @@ impl EditUserEntriesRequest {
 					}
 					action = Some(match value.as_str() {
 						"mark_read" => Action::MarkRead,
+						"mark_page_read" => Action::MarkPageRead,
+						"mark_all_read" => Action::MarkAllRead,
+						"mark_older_read" => Action::MarkOlderRead,
 						"delete" => Action::Delete,
+						"star" => Action::Star,
+						"unstar" => Action::Unstar,
+						"add_label" => Action::AddLabel,
+						"remove_label" => Action::RemoveLabel,
 						_ => return Err(format!("").into()),
 					});
 				},
 				"select-all" => select_all = true,
+				"page" => page_ids.push(EntryId(ulid::Ulid::from_string(value)?)),
+				"feed" => feed_id = Some(FeedId(ulid::Ulid::from_string(value)?)),
+				"folder" => folder = Some(value.clone()),
+				"starred" => starred = true,
+				"page_label" => page_label = Some(value.clone()),
+				"label" => label = Some(value.clone()).filter(|label| !label.trim().is_empty()),
+				"before" => before = Some(value.clone()).filter(|before| !before.trim().is_empty()),
 				key if key.starts_with("select-") => {
 					let suffix = key.strip_prefix("select-")
 						.expect("a string with starts with a given prefix has that prefix");
@@ -100,30 +305,109 @@ impl EditUserEntriesRequest {
 			}
 		}
 		let action = action.ok_or(Into::<crate::Err>::into("No action"))?;
+		// Without scripts, "All" can't check the other boxes, so it stands in
+		// for them instead.
+		if select_all {
+			selected_ids = page_ids.clone();
+		}
 		Ok(EditUserEntriesRequest{
 			action,
 			select_all,
 			selected_ids,
+			page_ids,
+			feed_id,
+			folder,
+			starred,
+			page_label,
+			label,
+			before,
+		})
+	}
+
+	/// The page the request came from, to go back to
+	fn return_path(&self) -> String {
+		if let Some(feed_id) = &self.feed_id {
+			format!("/feed/{}", feed_id.to_string())
+		} else if let Some(folder) = &self.folder {
+			format!(
+				"/folder/{}",
+				percent_encoding::utf8_percent_encode(folder, percent_encoding::NON_ALPHANUMERIC),
+			)
+		} else if let Some(label) = &self.page_label {
+			format!(
+				"/label/{}",
+				percent_encoding::utf8_percent_encode(label, percent_encoding::NON_ALPHANUMERIC),
+			)
+		} else if self.starred {
+			"/starred".to_string()
+		} else {
+			"/".to_string()
+		}
+	}
+
+	/// The entries a mark-read action applies to
+	fn mark_read_scope(&self) -> Result<MarkReadScope, HttpError> {
+		let page_scope = MarkReadScope {
+			feed_id: self.feed_id,
+			folder: self.folder.clone(),
+			starred_only: self.starred,
+			label: self.page_label.clone(),
+			..Default::default()
+		};
+		Ok(match self.action {
+			Action::MarkPageRead => MarkReadScope { entry_ids: Some(self.page_ids.clone()), ..Default::default() },
+			Action::MarkAllRead => page_scope,
+			Action::MarkOlderRead => {
+				let before = self.before.as_deref().ok_or_else(|| HttpError::BadRequest {
+					description: "Enter a date to mark entries older than it read".to_string(),
+				} )?;
+				let before = parse_date(before)
+					.map_err(|e| HttpError::BadRequest { description: e.to_string() })?;
+				MarkReadScope { before: Some(before), ..page_scope }
+			},
+			_ => MarkReadScope { entry_ids: Some(self.selected_ids.clone()), ..Default::default() },
 		})
 	}
 }
 pub async fn edit_userentries<Persistence>(
 	State(state): State<AppState<Persistence>>,
 	user: AuthenticatedUser<Persistence>,
-	Form(request): Form<Vec<(String, String)>>,
+	CsrfForm(request): CsrfForm<Vec<(String, String)>>,
 ) -> Result<Redirect, HttpError>
 where Persistence: RussetPersistenceLayer {
 	let request = EditUserEntriesRequest::from_raw_entries(&request)?;
-	let time = Some(Timestamp::new(SystemTime::now()));
-	let user_entry = match request.action {
-		Action::MarkRead => UserEntry { read: time, tombstone: None },
-		Action::Delete => UserEntry { read: time.clone(), tombstone: time },
-	};
-	state.domain_service.set_userentries(
-			&request.selected_ids,
-			&user.user.id,
-			&user_entry,
-		)
-		.await?;
-	Ok(Redirect::to("/"))
+	let return_path = request.return_path();
+	match request.action {
+		Action::MarkRead | Action::MarkPageRead | Action::MarkAllRead | Action::MarkOlderRead => {
+			let scope = request.mark_read_scope()?;
+			state.domain_service.mark_read(&user.user.id, &scope).await?;
+		},
+		Action::Delete => {
+			state.domain_service.delete_entries(&request.selected_ids, &user.user.id).await?;
+		},
+		Action::Star | Action::Unstar => {
+			let starred = matches!(request.action, Action::Star);
+			let found = state.domain_service
+				.set_starred(&request.selected_ids, &user.user.id, starred)
+				.await?;
+			if !found {
+				return Err(HttpError::NotFound)
+			}
+		},
+		Action::AddLabel | Action::RemoveLabel => {
+			let label = request.label.as_deref().ok_or_else(|| HttpError::BadRequest {
+				description: "Enter a label to add or remove".to_string(),
+			} )?;
+			if matches!(request.action, Action::AddLabel) {
+				state.domain_service
+					.label_entries(&request.selected_ids, &user.user.id, label)
+					.await?;
+			} else {
+				state.domain_service
+					.unlabel_entries(&request.selected_ids, &user.user.id, label)
+					.await?;
+			}
+		},
+	}
+	Ok(Redirect::to(&return_path))
 }
@@ -0,0 +1,156 @@
+use axum::extract::{ Path, Query, State };
+use axum::response::{ Html, Redirect };
+use crate::domain::model::{ Entry, Label, Rule, RuleDefinition, Subscription };
+use crate::http::{ AppState, AuthenticatedUser };
+use crate::http::csrf::CsrfForm;
+use crate::http::error::HttpError;
+use crate::model::{ FeedId, RuleAction, RuleField, RuleId, RuleMatch };
+use crate::persistence::model::User;
+use crate::persistence::RussetPersistenceLayer;
+use sailfish::TemplateOnce;
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+
+#[derive(TemplateOnce)]
+#[template(path = "rules.stpl")]
+struct RulesPageTemplate<'a> {
+	user: Option<&'a User>,
+	csrf_token: &'a str,
+	rules: &'a [Rule],
+	subscriptions: &'a [Subscription],
+	/// The rule being tested, to fill the form back in with
+	rule: Option<&'a RuleDefinition>,
+	/// Recent entries the rule being tested matches
+	matches: Option<&'a [Entry]>,
+	message: Option<&'a str>,
+	labels: &'a [Label],
+	page_title: &'a str,
+	relative_root: &'a str,
+}
+
+/// Render the rules page for the given user
+async fn render_rules_page<Persistence>(
+	state: &AppState<Persistence>,
+	user: &AuthenticatedUser<Persistence>,
+	rule: Option<&RuleDefinition>,
+	matches: Option<&[Entry]>,
+	message: Option<&str>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let rules = state.domain_service.get_rules(&user.user.id).await?;
+	let subscriptions = state.domain_service.get_subscriptions(&user.user.id).await?;
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	Ok(Html(
+		RulesPageTemplate {
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			rules: &rules,
+			subscriptions: &subscriptions,
+			rule,
+			matches,
+			message,
+			labels: &labels,
+			page_title: "Rules",
+			relative_root: "",
+		}
+		.render_once()?
+	) )
+}
+
+#[tracing::instrument]
+pub async fn rules_page<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	render_rules_page(&state, &user, None, None, None).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RuleRequest {
+	/// A feed ID, or empty for all feeds
+	#[serde(default)]
+	feed: String,
+	field: RuleField,
+	match_type: RuleMatch,
+	pattern: String,
+	action: RuleAction,
+	#[serde(default)]
+	label: String,
+}
+impl TryFrom<RuleRequest> for RuleDefinition {
+	type Error = HttpError;
+	fn try_from(request: RuleRequest) -> Result<RuleDefinition, HttpError> {
+		let feed_id = match request.feed.as_str() {
+			"" => None,
+			feed => Some(FeedId(ulid::Ulid::from_string(feed).map_err(|_| HttpError::BadRequest {
+				description: format!("Bad feed ID: {feed:?}"),
+			} )?)),
+		};
+		Ok(RuleDefinition {
+			feed_id,
+			field: request.field,
+			match_type: request.match_type,
+			pattern: request.pattern,
+			action: request.action,
+			label: Some(request.label).filter(|label| !label.trim().is_empty()),
+		} )
+	}
+}
+
+#[tracing::instrument]
+pub async fn add_rule<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<RuleRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let definition = request.try_into()?;
+	state.domain_service
+		.add_rule(&user.user.id, &definition)
+		.await
+		.map_err(|e| HttpError::BadRequest { description: format!("Could not add rule: {e}") })?;
+	Ok(Redirect::to("rules"))
+}
+
+/// Show which recent entries a rule would match, without adding it
+#[tracing::instrument]
+pub async fn test_rule<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	Query(request): Query<RuleRequest>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let definition = request.try_into()?;
+	let matches = state.domain_service
+		.test_rule(&user.user.id, &definition, &user.preferences.dates)
+		.await
+		.map_err(|e| HttpError::BadRequest { description: format!("Could not test rule: {e}") })?;
+	let message = format!("This rule matches {} of your recent entries.", matches.len());
+	render_rules_page(&state, &user, Some(&definition), Some(&matches), Some(&message)).await
+}
+
+/// Apply all the user's rules to their existing entries
+#[tracing::instrument]
+pub async fn apply_rules<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(_): CsrfForm<IgnoredAny>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let count = state.domain_service.apply_rules(&user.user.id).await?;
+	let message = format!("Applied your rules to {count} existing entries.");
+	render_rules_page(&state, &user, None, None, Some(&message)).await
+}
+
+#[tracing::instrument]
+pub async fn delete_rule<Persistence>(
+	Path(rule_id): Path<RuleId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(_): CsrfForm<IgnoredAny>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	state.domain_service.delete_rule(&user.user.id, &rule_id).await?;
+	Ok(Redirect::to("../rules"))
+}
@@ -0,0 +1,162 @@
+use axum::extract::{ State };
+use axum::response::{ Html, IntoResponse, Redirect, Response };
+use chrono::{ DateTime, Utc };
+use crate::domain::model::Label;
+use crate::feed::model::Feed as ReaderFeed;
+use crate::http::AppState;
+use crate::http::csrf::CsrfForm;
+use crate::http::error::HttpError;
+use crate::http::session::AuthenticatedUser;
+use crate::model::Permission;
+use crate::persistence::model::{ ScrapeSelectors, User };
+use crate::persistence::RussetPersistenceLayer;
+use reqwest::Url;
+use sailfish::TemplateOnce;
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ScrapeForm {
+	#[serde(default)]
+	url: String,
+	#[serde(default)]
+	item_selector: String,
+	#[serde(default)]
+	title_selector: String,
+	#[serde(default)]
+	link_selector: String,
+	#[serde(default)]
+	date_selector: String,
+}
+impl ScrapeForm {
+	fn parse(&self) -> Result<(Url, ScrapeSelectors), HttpError> {
+		let url = Url::parse(&self.url).map_err(|_| HttpError::BadRequest {
+			description: format!("Could not parse URL {:?}", self.url),
+		} )?;
+		if self.item_selector.trim().is_empty() {
+			return Err(HttpError::BadRequest { description: "An item selector is required".to_string() })
+		}
+		let optional = |selector: &str| {
+			let selector = selector.trim();
+			if selector.is_empty() { None } else { Some(selector.to_string()) }
+		};
+		Ok((url, ScrapeSelectors {
+			item: self.item_selector.trim().to_string(),
+			title: optional(&self.title_selector),
+			link: optional(&self.link_selector),
+			date: optional(&self.date_selector),
+		} ))
+	}
+}
+
+struct PreviewEntry {
+	title: String,
+	url: Option<String>,
+	article_date: String,
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "scrape.stpl")]
+struct ScrapePageTemplate<'a> {
+	user: Option<&'a User>,
+	csrf_token: &'a str,
+	form: &'a ScrapeForm,
+	preview_title: Option<&'a str>,
+	preview: Option<&'a [PreviewEntry]>,
+	preview_error: Option<&'a str>,
+	labels: &'a [Label],
+	page_title: &'a str,
+	relative_root: &'a str,
+}
+
+#[tracing::instrument]
+pub async fn scrape_page<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	if !user.user.user_type.has_permission(Permission::ScrapedFeeds) {
+		return Err(HttpError::Forbidden);
+	}
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	Ok(Html(
+		ScrapePageTemplate {
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			form: &ScrapeForm::default(),
+			preview_title: None,
+			preview: None,
+			preview_error: None,
+			labels: &labels,
+			page_title: "Scraped Feed",
+			relative_root: "",
+		}
+		.render_once()?
+	) )
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ScrapeAction {
+	Preview,
+	Subscribe,
+}
+#[derive(Debug, Deserialize)]
+pub struct ScrapeRequest {
+	action: ScrapeAction,
+	#[serde(flatten)]
+	form: ScrapeForm,
+}
+#[tracing::instrument]
+pub async fn scrape<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<ScrapeRequest>,
+) -> Result<Response, HttpError>
+where Persistence: RussetPersistenceLayer {
+	if !user.user.user_type.has_permission(Permission::ScrapedFeeds) {
+		return Err(HttpError::Forbidden);
+	}
+	let (url, selectors) = request.form.parse()?;
+	match request.action {
+		ScrapeAction::Preview => {
+			// Errors here are shown on the page, since figuring out what's
+			// wrong with the selectors is the point of the preview.
+			let result = state.domain_service.preview_scraped_feed(&url, &selectors).await;
+			let (preview_title, preview, preview_error) = match result {
+				Ok(feed) => (Some(feed.title.clone()), Some(preview_entries(feed)), None),
+				Err(e) => (None, None, Some(e.to_string())),
+			};
+			let labels = state.domain_service.get_labels(&user.user.id).await?;
+			Ok(Html(
+				ScrapePageTemplate {
+					user: Some(&user.user),
+					csrf_token: &user.csrf_token,
+					form: &request.form,
+					preview_title: preview_title.as_deref(),
+					preview: preview.as_deref(),
+					preview_error: preview_error.as_deref(),
+					labels: &labels,
+			page_title: "Scraped Feed",
+					relative_root: "",
+				}
+				.render_once()?
+			).into_response() )
+		},
+		ScrapeAction::Subscribe => {
+			let feed_id = state.domain_service.add_scraped_feed(&url, &selectors).await?;
+			state.domain_service.subscribe(&user.user.id, &feed_id).await?;
+			Ok(Redirect::to(&format!("feed/{}", feed_id.to_string())).into_response())
+		},
+	}
+}
+
+fn preview_entries(feed: ReaderFeed) -> Vec<PreviewEntry> {
+	feed.entries
+		.into_iter()
+		.map(|entry| PreviewEntry {
+			title: entry.title,
+			url: entry.url.map(|url| url.to_string()),
+			article_date: DateTime::<Utc>::from(entry.article_date.0).to_rfc3339(),
+		} )
+		.collect()
+}
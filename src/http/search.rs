@@ -0,0 +1,96 @@
+use axum::extract::{ Query, State };
+use axum::response::Html;
+use crate::domain::model::{ Entry, Feed, Label };
+use crate::domain::search::parse_search_query;
+use crate::domain::user::MAX_PAGE_SIZE;
+use crate::http::AppState;
+use crate::http::error::HttpError;
+use crate::http::session::AuthenticatedUser;
+use crate::model::{ FeedId, Pagination };
+use crate::persistence::model::User;
+use crate::persistence::RussetPersistenceLayer;
+use percent_encoding::{ NON_ALPHANUMERIC, utf8_percent_encode };
+use sailfish::TemplateOnce;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(TemplateOnce)]
+#[template(path = "search.stpl")]
+struct SearchPageTemplate<'a> {
+	user: Option<&'a User>,
+	csrf_token: &'a str,
+	query: &'a str,
+	encoded_query: &'a str,
+	entries: Option<&'a [Entry]>,
+	feeds: &'a HashMap<FeedId, Feed>,
+	query_error: Option<&'a str>,
+	page_num: usize,
+	labels: &'a [Label],
+	page_title: &'a str,
+	relative_root: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchRequest {
+	#[serde(default)]
+	q: String,
+	page_num: Option<usize>,
+	page_size: Option<usize>,
+}
+#[tracing::instrument]
+pub async fn search_page<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	Query(request): Query<SearchRequest>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let page_num = request.page_num.unwrap_or(0);
+	let page_size = request.page_size
+		.unwrap_or(user.preferences.page_size)
+		.clamp(1, MAX_PAGE_SIZE);
+	// The offset has to fit in the database's integers as well as ours
+	let page_offset = page_num.checked_mul(page_size).and_then(|offset| i64::try_from(offset).ok());
+	if page_offset.is_none() {
+		return Err(HttpError::BadRequest { description: format!("No such page {page_num}") })
+	}
+	let pagination = Pagination { page_num, page_size };
+	let query = request.q.trim();
+	// Query errors are shown on the page, alongside the query to fix
+	let (entries, query_error) = match parse_search_query(query) {
+		Ok(_) if query.is_empty() => (None, None),
+		Ok(search) => {
+			let entries = state.domain_service
+				.search_entries(&user.user.id, &search, &pagination, &user.preferences.dates)
+				.await
+				.into_iter()
+				.collect::<crate::Result<Vec<Entry>>>()?;
+			(Some(entries), None)
+		},
+		Err(e) => (None, Some(e.to_string())),
+	};
+	let feeds = state.domain_service
+		.feeds_for_user(&user.user.id)
+		.await
+		.into_iter()
+		.filter_map(|feed| feed.ok())
+		.map(|feed| (feed.id, feed))
+		.collect::<HashMap<FeedId, Feed>>();
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	let encoded_query = utf8_percent_encode(query, NON_ALPHANUMERIC).to_string();
+	Ok(Html(
+		SearchPageTemplate {
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			query,
+			encoded_query: &encoded_query,
+			entries: entries.as_deref(),
+			feeds: &feeds,
+			query_error: query_error.as_deref(),
+			page_num,
+			labels: &labels,
+			page_title: "Search",
+			relative_root: "",
+		}
+		.render_once()?
+	) )
+}
@@ -1,19 +1,49 @@
 use axum::{ async_trait, RequestPartsExt };
-use axum::extract::{ FromRef, FromRequestParts, State };
+use axum::extract::{ ConnectInfo, FromRef, FromRequestParts, Request, State };
+use axum::http::header::{ HeaderName, USER_AGENT };
 use axum::http::Uri;
-use axum_extra::extract::cookie::CookieJar;
+use axum_extra::extract::cookie::{ Cookie, CookieJar, SameSite };
 use axum::http::request::Parts;
-use crate::http::AppState;
+use crate::http::{ AppState, ProxyAuth };
+use crate::http::csrf::SessionCsrfToken;
 use crate::http::error::HttpError;
-use crate::persistence::model::User;
+use crate::persistence::model::{ SessionClient, User, UserPreferences };
 use crate::persistence::RussetPersistenceLayer;
+use std::convert::Infallible;
 use std::marker::PhantomData;
+use std::net::{ IpAddr, SocketAddr };
+use std::sync::Arc;
+
+/// Make a cookie with the attributes all Russet's cookies have: sent for the
+/// whole site, hidden from scripts, left off cross-site subrequests and form
+/// posts, and HTTPS-only if `secure`
+pub fn cookie(name: &'static str, value: String, secure: bool) -> Cookie<'static> {
+	let mut cookie = Cookie::new(name, value);
+	cookie.set_path("/");
+	cookie.set_http_only(true);
+	cookie.set_same_site(SameSite::Lax);
+	cookie.set_secure(secure);
+	cookie
+}
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
 
-#[derive(Debug)]
 pub struct AuthenticatedUser<Persistence> {
 	pub user: User,
+	pub preferences: UserPreferences,
+	/// For this session's forms. See [CsrfForm](crate::http::csrf::CsrfForm).
+	pub csrf_token: String,
 	phantom: PhantomData<Persistence>,
 }
+impl <Persistence> std::fmt::Debug for AuthenticatedUser<Persistence> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("AuthenticatedUser")
+			.field("user", &self.user)
+			.field("preferences", &self.preferences)
+			.field("csrf_token", &"<redacted>")
+			.finish()
+	}
+}
 #[async_trait]
 impl <S, Persistence> FromRequestParts<S> for AuthenticatedUser<Persistence>
 where
@@ -33,12 +63,24 @@ where
 			.await
 			.expect("Infallible is");
 
+		if let Some(proxy_auth) = state.proxy_auth.as_ref() {
+			return Self::from_proxy_auth(parts, &state, proxy_auth).await
+		}
+
 		let session_cookie = cookies.get("session_id");
 		match session_cookie {
 			Some(session_cookie) => {
-				let user = state.domain_service.auth_user(session_cookie.value()).await?;
+				let client = parts.extract::<SessionClient>()
+					.await
+					.expect("Infallible is");
+				let user = state.domain_service.auth_user(session_cookie.value(), client).await?;
 				match user {
-					Some(user) => Ok(AuthenticatedUser { user, phantom: PhantomData }),
+					Some(user) => {
+						let preferences = state.domain_service.get_user_preferences(&user.id).await?;
+						let csrf_token = state.domain_service.csrf_token(session_cookie.value())?;
+						parts.extensions.insert(SessionCsrfToken { token: csrf_token.clone(), previous: None });
+						Ok(AuthenticatedUser { user, preferences, csrf_token, phantom: PhantomData })
+					},
 					// Session cookie is present but invalid; user needs to reauthenticate
 					None => Err(HttpError::Unauthenticated { redirect_to: Some(path.to_string()) }),
 				}
@@ -48,3 +90,89 @@ where
 		}
 	}
 }
+
+impl <Persistence> AuthenticatedUser<Persistence>
+where Persistence: RussetPersistenceLayer {
+	/// Authenticate the user a trusted reverse proxy named in its header.
+	/// Session cookies are ignored, so requests which didn't come through the
+	/// proxy are refused; they're refused outright, rather than sent to the
+	/// login page, which is disabled.
+	async fn from_proxy_auth(
+		parts: &mut Parts,
+		state: &AppState<Persistence>,
+		proxy_auth: &ProxyAuth,
+	) -> Result<Self, HttpError> {
+		// This has to be the address the request actually came from: a client
+		// could claim to be a proxy in `X-Forwarded-For`
+		let peer = parts.extensions
+			.get::<ConnectInfo<SocketAddr>>()
+			.map(|ConnectInfo(address)| address.ip());
+		if !peer.is_some_and(|peer| proxy_auth.addresses.contains(&peer)) {
+			return Err(HttpError::Forbidden)
+		}
+		let user_name = parts.headers
+			.get(&proxy_auth.header)
+			.and_then(|user_name| user_name.to_str().ok())
+			.map(|user_name| user_name.trim())
+			.filter(|user_name| !user_name.is_empty())
+			.ok_or(HttpError::Forbidden)?;
+		let user = state.domain_service
+			.proxy_auth_user(user_name, proxy_auth.provision_users)
+			.await?
+			.ok_or(HttpError::Forbidden)?;
+		let preferences = state.domain_service.get_user_preferences(&user.id).await?;
+		let (csrf_token, previous) = state.domain_service.proxy_auth_csrf_tokens(&user.id)?;
+		parts.extensions.insert(SessionCsrfToken { token: csrf_token.clone(), previous: Some(previous) });
+		Ok(AuthenticatedUser { user, preferences, csrf_token, phantom: PhantomData })
+	}
+}
+
+/// The address of the client making a request, as far as we can tell. See
+/// [resolve_client_ip].
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+/// Work out which client a request came from, and leave it in the request's
+/// extensions as a [ClientIp]. Requests from `trusted_proxies` are taken to be
+/// from whoever their `X-Forwarded-For` header says, working back from the
+/// nearest proxy until an address which isn't a trusted proxy.
+pub async fn resolve_client_ip(trusted_proxies: Arc<Vec<IpAddr>>, mut request: Request) -> Request {
+	let peer = request.extensions()
+		.get::<ConnectInfo<SocketAddr>>()
+		.map(|ConnectInfo(address)| address.ip());
+	if let Some(peer) = peer {
+		let forwarded_for = request.headers()
+			.get_all(X_FORWARDED_FOR)
+			.iter()
+			.filter_map(|header| header.to_str().ok())
+			.flat_map(|header| header.split(','))
+			.map(|hop| hop.trim().to_string())
+			.collect::<Vec<_>>();
+		let mut client = peer;
+		for hop in forwarded_for.iter().rev() {
+			if !trusted_proxies.contains(&client) { break }
+			match hop.parse() {
+				Ok(hop) => client = hop,
+				Err(_) => break,
+			}
+		}
+		request.extensions_mut().insert(ClientIp(client));
+	}
+	request
+}
+
+#[async_trait]
+impl <S> FromRequestParts<S> for SessionClient
+where S: Send + Sync {
+	type Rejection = Infallible;
+	async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+		let ip = parts.extensions
+			.get::<ClientIp>()
+			.map(|ClientIp(ip)| ip.to_string());
+		let user_agent = parts.headers
+			.get(USER_AGENT)
+			.and_then(|user_agent| user_agent.to_str().ok())
+			.map(|user_agent| user_agent.to_string());
+		Ok(SessionClient { ip, user_agent })
+	}
+}
@@ -1,18 +1,31 @@
 use axum::http::StatusCode;
 use axum::http::header;
 use axum::response::Response;
+use crate::http::AuthenticatedUser;
+use crate::model::Theme;
+use crate::persistence::RussetPersistenceLayer;
 use sailfish::TemplateOnce;
 
 #[derive(TemplateOnce)]
 #[template(path = "static/styles.css.stpl")]
-struct Css { }
+struct Css {
+	theme: Theme,
+}
+/// The stylesheet, in the logged-in user's theme (or the default, for
+/// everyone else)
 #[tracing::instrument]
-pub async fn styles() -> Response<String> {
+pub async fn styles<Persistence>(
+	user: Option<AuthenticatedUser<Persistence>>,
+) -> Response<String>
+where Persistence: RussetPersistenceLayer {
+	let theme = user.map(|user| user.preferences.theme).unwrap_or_default();
 	// TODO: This isn't actually infallible. Do something vaguely reasonable if
 	// it fails.
 	Response::builder()
 		.status(StatusCode::OK)
 		.header(header::CONTENT_TYPE, "text/css")
-		.body(Css{}.render_once().expect("rendering a static asset should work"))
+		// The theme depends on who's asking
+		.header(header::CACHE_CONTROL, "private, no-cache")
+		.body(Css { theme }.render_once().expect("rendering a static asset should work"))
 		.expect("building a response with a static asset should work")
 }
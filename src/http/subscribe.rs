@@ -1,8 +1,13 @@
-use axum::extract::{ Form, State };
+use axum::extract::{ State };
 use axum::response::{ Html, Redirect };
+use crate::domain::feeds::credentials::FeedCredentials;
+use crate::domain::feeds::sources::FeedSource;
+use crate::domain::model::Label;
 use crate::http::AppState;
+use crate::http::csrf::CsrfForm;
 use crate::http::error::HttpError;
 use crate::http::session::AuthenticatedUser;
+use crate::model::Permission;
 use crate::persistence::model::User;
 use crate::persistence::RussetPersistenceLayer;
 use reqwest::Url;
@@ -13,18 +18,23 @@ use serde::Deserialize;
 #[template(path = "subscribe.stpl")]
 pub struct SubscribePage<'a> {
 	user: Option<&'a User>,
+	csrf_token: &'a str,
+	labels: &'a [Label],
 	page_title: &'a str,
 	relative_root: &'a str,
 }
 #[tracing::instrument]
 pub async fn subscribe_page<Persistence>(
-	State(_state): State<AppState<Persistence>>,
+	State(state): State<AppState<Persistence>>,
 	user: AuthenticatedUser<Persistence>,
 ) -> Result<Html<String>, HttpError>
 where Persistence: RussetPersistenceLayer {
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
 	Ok(Html(
 		SubscribePage{
 			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			labels: &labels,
 			page_title: "Subscribe",
 			relative_root: "",
 		}
@@ -32,22 +42,79 @@ where Persistence: RussetPersistenceLayer {
 	) )
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum AuthType {
+	#[default]
+	None,
+	Basic,
+	Bearer,
+	Cookie,
+}
+#[derive(Deserialize, Clone)]
 pub struct SubscribeRequest {
 	url: String,
+	#[serde(default)]
+	auth_type: AuthType,
+	#[serde(default)]
+	auth_user_name: String,
+	#[serde(default)]
+	auth_secret: String,
+	#[serde(default)]
+	headers: String,
+}
+impl SubscribeRequest {
+	/// Build the [FeedCredentials] described by this request, if any.
+	fn credentials(&self) -> crate::Result<Option<FeedCredentials>> {
+		let mut credentials = FeedCredentials::default();
+		match self.auth_type {
+			AuthType::None => (),
+			AuthType::Basic => credentials.add_basic_auth(&self.auth_user_name, &self.auth_secret)?,
+			AuthType::Bearer => credentials.add_bearer_token(&self.auth_secret)?,
+			AuthType::Cookie => credentials.add_cookie(&self.auth_secret)?,
+		}
+		for line in self.headers.lines().filter(|line| !line.trim().is_empty()) {
+			let (name, value) = line
+				.split_once(':')
+				.ok_or_else(|| -> crate::Err { format!("Headers must be \"Name: value\" (got {line:?})").into() })?;
+			credentials.add_header(name, value)?;
+		}
+		Ok(if credentials.is_empty() { None } else { Some(credentials) })
+	}
+}
+impl std::fmt::Debug for SubscribeRequest {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("SubscribeRequest")
+			.field("url", &self.url)
+			.field("auth_type", &self.auth_type)
+			.field("auth_user_name", &self.auth_user_name)
+			.field("auth_secret", &"<redacted>")
+			.field("headers", &"<redacted>")
+			.finish()
+	}
 }
 #[tracing::instrument]
 pub async fn subscribe<Persistence>(
 	State(state): State<AppState<Persistence>>,
 	user: AuthenticatedUser<Persistence>,
-	Form(subscribe): Form<SubscribeRequest>,
+	CsrfForm(subscribe): CsrfForm<SubscribeRequest>,
 ) -> Result<Redirect, HttpError>
 where Persistence: RussetPersistenceLayer {
 	let url = match Url::parse(&subscribe.url) {
 		Ok(url) => url,
 		Err(_) => return Err(HttpError::BadRequest { description: format!("Could not parse URL {:?}", subscribe.url) }),
 	};
-	let feed_id = state.domain_service.add_feed(&url).await?;
+	let is_local = FeedSource::from_url(&url)
+		.map_err(|e| HttpError::BadRequest { description: e.to_string() })?
+		.is_local();
+	if is_local && !user.user.user_type.has_permission(Permission::LocalFeedSources) {
+		return Err(HttpError::Forbidden);
+	}
+	let credentials = match subscribe.credentials() {
+		Ok(credentials) => credentials,
+		Err(e) => return Err(HttpError::BadRequest { description: e.to_string() }),
+	};
+	let feed_id = state.domain_service.add_feed(&url, credentials.as_ref()).await?;
 	state.domain_service.subscribe(&user.user.id, &feed_id).await?;
 	Ok(Redirect::to("/"))
 }
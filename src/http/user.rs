@@ -1,25 +1,60 @@
-use axum::extract::{ Path, State };
-use axum::response::Html;
+use axum::extract::{ Path, Query, State };
+use axum::response::{ Html, Redirect };
+use axum_extra::extract::cookie::CookieJar;
+use base64ct::{ Base64, Encoding };
+use chrono_tz::Tz;
+use crate::domain::model::{ Label, ReauthOutcome, SessionInfo, TotpEnrollment, TwoFactorStatus };
 use crate::http::{ AppState, AuthenticatedUser };
+use crate::http::csrf::CsrfForm;
 use crate::http::error::HttpError;
-use crate::model::{ UserId, UserType };
+use crate::http::login::check_sessions;
+use crate::model::{ DateDisplay, DateFormat, DateLocale, EntrySort, ListingOptions, Theme, UserId, UserType };
 use crate::persistence::RussetPersistenceLayer;
-use crate::persistence::model::User;
+use crate::persistence::model::{ User, UserPreferences };
 use sailfish::TemplateOnce;
+use serde::Deserialize;
+use serde::de::IgnoredAny;
 
 #[derive(Clone, Debug, TemplateOnce)]
 #[template(path = "user.stpl")]
 pub struct UserPage<'a> {
 	page_user: &'a User,
+	/// The page user's preferences, if they're the one viewing, so they can
+	/// change their settings
+	preferences: Option<&'a UserPreferences>,
+	/// What the last settings change did
+	message: Option<&'a str>,
+	/// Whether the page user has two-factor authentication, if they're the
+	/// one viewing
+	two_factor: Option<TwoFactorStatus>,
+	/// Whether Russet has sessions to manage, rather than a proxy in front of
+	/// it authenticating users
+	sessions: bool,
 	user: Option<&'a User>,
+	csrf_token: &'a str,
+	labels: &'a [Label],
 	page_title: &'a str,
 	relative_root: &'a str,
 }
+/// Which settings were just changed, to confirm on the settings page
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingsUpdate {
+	Password,
+	Preferences,
+	Sessions,
+	TwoFactor,
+}
+#[derive(Debug, Deserialize)]
+pub struct UserPageQuery {
+	updated: Option<SettingsUpdate>,
+}
 #[tracing::instrument]
 pub async fn user_page<Persistence>(
 	Path(page_user_id): Path<UserId>,
 	State(state): State<AppState<Persistence>>,
 	auth_user: AuthenticatedUser<Persistence>,
+	Query(query): Query<UserPageQuery>,
 ) -> Result<Html<String>, HttpError>
 where Persistence: RussetPersistenceLayer {
 	// Authentication rules. Sysops can see all user pages. Members can see only
@@ -29,14 +64,373 @@ where Persistence: RussetPersistenceLayer {
 		return Err(HttpError::Forbidden);
 	}
 	let page_user = state.domain_service.get_user(&page_user_id).await?;
+	let labels = state.domain_service.get_labels(&auth_user.user.id).await?;
 	let page_title = format!("User - {}", page_user.name);
+	let message = query.updated.map(|updated| match updated {
+		SettingsUpdate::Password => "Password changed.",
+		SettingsUpdate::Preferences => "Preferences saved.",
+		SettingsUpdate::Sessions => "Logged out of all other sessions.",
+		SettingsUpdate::TwoFactor => "Two-factor authentication turned off.",
+	} );
+	let two_factor = if page_user.id == auth_user.user.id {
+		Some(state.domain_service.two_factor_status(&page_user.id).await?)
+	} else {
+		None
+	};
 	Ok(Html(
 		UserPage{
 			page_user: &page_user,
+			preferences: (page_user.id == auth_user.user.id).then_some(&auth_user.preferences),
+			message,
+			two_factor,
+			sessions: state.proxy_auth.is_none(),
 			user: Some(&auth_user.user),
+			csrf_token: &auth_user.csrf_token,
+			labels: &labels,
 			page_title: &page_title,
 			relative_root: "../",
 		}
 		.render_once()?
 	) )
 }
+
+/// Users may only change their own settings
+fn check_self<Persistence>(
+	page_user_id: &UserId,
+	user: &AuthenticatedUser<Persistence>,
+) -> Result<(), HttpError> {
+	if user.user.id != *page_user_id {
+		return Err(HttpError::Forbidden);
+	}
+	Ok(())
+}
+
+/// Where to go back to after changing settings
+fn settings_redirect(user_id: &UserId, updated: &str) -> Redirect {
+	Redirect::to(&format!("/user/{}?updated={updated}", user_id.to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+	current_password: String,
+	new_password: String,
+	confirm_password: String,
+}
+#[tracing::instrument(skip(request))]
+pub async fn change_password<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<ChangePasswordRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_self(&page_user_id, &user)?;
+	if request.new_password != request.confirm_password {
+		return Err(HttpError::BadRequest { description: "The new passwords don't match".to_string() })
+	}
+	let outcome = state.domain_service
+		.change_password(&user.user.id, &request.current_password, &request.new_password)
+		.await
+		.map_err(|e| HttpError::BadRequest { description: e.to_string() })?;
+	match outcome {
+		ReauthOutcome::Done(()) => Ok(settings_redirect(&user.user.id, "password")),
+		ReauthOutcome::Failed => Err(HttpError::BadRequest { description: "The current password is incorrect".to_string() }),
+		ReauthOutcome::Blocked { retry_after } => Err(HttpError::TooManyRequests { retry_after }),
+	}
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreferencesRequest {
+	page_size: usize,
+	timezone: String,
+	date_format: DateFormat,
+	date_locale: DateLocale,
+	recent_days: u32,
+	theme: Theme,
+}
+#[tracing::instrument]
+pub async fn save_preferences<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<PreferencesRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_self(&page_user_id, &user)?;
+	let timezone = request.timezone
+		.parse::<Tz>()
+		.map_err(|_| HttpError::BadRequest { description: format!("Unrecognized timezone {:?}", request.timezone) })?;
+	let preferences = UserPreferences {
+		page_size: request.page_size,
+		dates: DateDisplay {
+			timezone,
+			format: request.date_format,
+			locale: request.date_locale,
+			recent_days: request.recent_days,
+		},
+		theme: request.theme,
+		..user.preferences
+	};
+	state.domain_service
+		.set_user_preferences(&user.user.id, &preferences)
+		.await
+		.map_err(|e| HttpError::BadRequest { description: e.to_string() })?;
+	Ok(settings_redirect(&user.user.id, "preferences"))
+}
+
+/// Log the user out of every session but this one
+#[tracing::instrument(skip(cookies))]
+pub async fn log_out_other_sessions<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	cookies: CookieJar,
+	CsrfForm(_): CsrfForm<IgnoredAny>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_self(&page_user_id, &user)?;
+	check_sessions(&state)?;
+	// The user was authenticated by this cookie, so it's there
+	let session_token = cookies.get("session_id")
+		.map(|cookie| cookie.value().to_string())
+		.ok_or(HttpError::Unauthenticated { redirect_to: None })?;
+	state.domain_service.delete_other_sessions(&user.user.id, &session_token).await?;
+	Ok(settings_redirect(&user.user.id, "sessions"))
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "sessions.stpl")]
+struct SessionsPageTemplate<'a> {
+	sessions: &'a [SessionInfo],
+	user: Option<&'a User>,
+	csrf_token: &'a str,
+	labels: &'a [Label],
+	page_title: &'a str,
+	relative_root: &'a str,
+}
+#[tracing::instrument(skip(cookies))]
+pub async fn sessions_page<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	cookies: CookieJar,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_self(&page_user_id, &user)?;
+	check_sessions(&state)?;
+	let session_token = cookies.get("session_id")
+		.map(|cookie| cookie.value().to_string())
+		.ok_or(HttpError::Unauthenticated { redirect_to: None })?;
+	let sessions = state.domain_service
+		.get_sessions(&user.user.id, &session_token, &user.preferences.dates)
+		.await?;
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	Ok(Html(
+		SessionsPageTemplate {
+			sessions: &sessions,
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			labels: &labels,
+			page_title: "Sessions",
+			relative_root: "../../",
+		}
+		.render_once()?
+	) )
+}
+
+/// End one of the user's sessions
+#[tracing::instrument]
+pub async fn end_session<Persistence>(
+	Path((page_user_id, session_id)): Path<(UserId, String)>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(_): CsrfForm<IgnoredAny>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_self(&page_user_id, &user)?;
+	check_sessions(&state)?;
+	if !state.domain_service.delete_session_by_id(&user.user.id, &session_id).await? {
+		return Err(HttpError::NotFound);
+	}
+	Ok(Redirect::to(&format!("/user/{}/sessions", user.user.id.to_string())))
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "two_factor.stpl")]
+struct TwoFactorPageTemplate<'a> {
+	status: TwoFactorStatus,
+	/// A secret to set up, if two-factor authentication is off
+	enrollment: Option<&'a TotpEnrollment>,
+	/// The enrollment's QR code, as an image source
+	qr_code_src: &'a str,
+	/// New recovery codes, shown just the once
+	recovery_codes: Option<&'a [String]>,
+	message: Option<&'a str>,
+	user: Option<&'a User>,
+	csrf_token: &'a str,
+	labels: &'a [Label],
+	page_title: &'a str,
+	relative_root: &'a str,
+}
+/// Render the two-factor authentication page, with a secret to set up if it's
+/// off
+async fn render_two_factor_page<Persistence>(
+	state: &AppState<Persistence>,
+	user: &AuthenticatedUser<Persistence>,
+	enrollment: Option<&TotpEnrollment>,
+	recovery_codes: Option<&[String]>,
+	message: Option<&str>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let status = state.domain_service.two_factor_status(&user.user.id).await?;
+	let new_enrollment = match (status.enabled, enrollment) {
+		(false, None) => Some(state.domain_service.new_totp_enrollment(&user.user)?),
+		_ => None,
+	};
+	let enrollment = enrollment.or(new_enrollment.as_ref()).filter(|_| !status.enabled);
+	let qr_code_src = enrollment.map_or(
+		"".to_string(),
+		|enrollment| format!("data:image/svg+xml;base64,{}", Base64::encode_string(enrollment.qr_code.as_bytes())),
+	);
+	let labels = state.domain_service.get_labels(&user.user.id).await?;
+	Ok(Html(
+		TwoFactorPageTemplate {
+			status,
+			enrollment,
+			qr_code_src: &qr_code_src,
+			recovery_codes,
+			message,
+			user: Some(&user.user),
+			csrf_token: &user.csrf_token,
+			labels: &labels,
+			page_title: "Two-Factor Authentication",
+			relative_root: "../../",
+		}
+		.render_once()?
+	) )
+}
+
+#[tracing::instrument]
+pub async fn two_factor_page<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_self(&page_user_id, &user)?;
+	render_two_factor_page(&state, &user, None, None, None).await
+}
+
+#[derive(Deserialize)]
+pub struct EnableTwoFactorRequest {
+	password: String,
+	secret: String,
+	code: String,
+}
+/// Turn on two-factor authentication, and show the new recovery codes
+#[tracing::instrument(skip(request))]
+pub async fn enable_two_factor<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<EnableTwoFactorRequest>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_self(&page_user_id, &user)?;
+	let enrollment = state.domain_service
+		.totp_enrollment(&user.user, &request.secret)
+		.map_err(|e| HttpError::BadRequest { description: e.to_string() })?;
+	let outcome = state.domain_service
+		.enable_totp(&user.user.id, &request.password, &request.secret, &request.code)
+		.await?;
+	match outcome {
+		ReauthOutcome::Done(recovery_codes) => render_two_factor_page(&state, &user, None, Some(&recovery_codes), None).await,
+		ReauthOutcome::Blocked { retry_after } => Err(HttpError::TooManyRequests { retry_after }),
+		// Same secret again, so there's no need to set up the authenticator
+		// over
+		ReauthOutcome::Failed => render_two_factor_page(
+				&state,
+				&user,
+				Some(&enrollment),
+				None,
+				Some("The password or code was incorrect."),
+			)
+			.await,
+	}
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorPasswordRequest {
+	password: String,
+}
+/// Replace the user's recovery codes, and show the new ones
+#[tracing::instrument(skip(request))]
+pub async fn regenerate_recovery_codes<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<TwoFactorPasswordRequest>,
+) -> Result<Html<String>, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_self(&page_user_id, &user)?;
+	let outcome = state.domain_service
+		.regenerate_recovery_codes(&user.user.id, &request.password)
+		.await
+		.map_err(|e| HttpError::BadRequest { description: e.to_string() })?;
+	match outcome {
+		ReauthOutcome::Done(recovery_codes) => render_two_factor_page(&state, &user, None, Some(&recovery_codes), None).await,
+		ReauthOutcome::Failed => Err(HttpError::BadRequest { description: "The password is incorrect".to_string() }),
+		ReauthOutcome::Blocked { retry_after } => Err(HttpError::TooManyRequests { retry_after }),
+	}
+}
+
+#[tracing::instrument(skip(request))]
+pub async fn disable_two_factor<Persistence>(
+	Path(page_user_id): Path<UserId>,
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<TwoFactorPasswordRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	check_self(&page_user_id, &user)?;
+	match state.domain_service.disable_totp(&user.user.id, &request.password).await? {
+		ReauthOutcome::Done(()) => Ok(settings_redirect(&user.user.id, "two_factor")),
+		ReauthOutcome::Failed => Err(HttpError::BadRequest { description: "The password is incorrect".to_string() }),
+		ReauthOutcome::Blocked { retry_after } => Err(HttpError::TooManyRequests { retry_after }),
+	}
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListingDefaultsRequest {
+	unread_only: bool,
+	oldest_first: bool,
+	sort: EntrySort,
+	page_size: usize,
+	/// The listing the options were saved from
+	return_to: String,
+}
+/// Save an entry listing's options as the user's defaults for all listings
+#[tracing::instrument]
+pub async fn save_listing_defaults<Persistence>(
+	State(state): State<AppState<Persistence>>,
+	user: AuthenticatedUser<Persistence>,
+	CsrfForm(request): CsrfForm<ListingDefaultsRequest>,
+) -> Result<Redirect, HttpError>
+where Persistence: RussetPersistenceLayer {
+	// Only go back to paths on this site
+	if !request.return_to.starts_with('/') || request.return_to.starts_with("//") {
+		return Err(HttpError::BadRequest { description: format!("Bad return path: {:?}", request.return_to) })
+	}
+	let mut preferences = user.preferences;
+	preferences.listing = ListingOptions {
+		unread_only: request.unread_only,
+		oldest_first: request.oldest_first,
+		sort: request.sort,
+	};
+	preferences.page_size = request.page_size;
+	state.domain_service
+		.set_user_preferences(&user.user.id, &preferences)
+		.await
+		.map_err(|e| HttpError::BadRequest { description: e.to_string() })?;
+	Ok(Redirect::to(&request.return_to))
+}
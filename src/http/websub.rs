@@ -0,0 +1,68 @@
+use axum::body::Bytes;
+use axum::extract::{ Path, Query, State };
+use axum::http::{ header, HeaderMap, StatusCode };
+use crate::domain::feeds::websub::Verification;
+use crate::http::AppState;
+use crate::http::error::HttpError;
+use crate::model::FeedId;
+use crate::persistence::RussetPersistenceLayer;
+use serde::Deserialize;
+
+// These endpoints are called by WebSub hubs, not users, so they aren't
+// authenticated. Verifications are only accepted for the topic we asked for,
+// while our request is pending, and hubs sign content with the secret we gave
+// them.
+
+#[derive(Debug, Deserialize)]
+pub struct HubVerification {
+	#[serde(rename = "hub.mode")]
+	mode: String,
+	#[serde(rename = "hub.topic")]
+	topic: Option<String>,
+	#[serde(rename = "hub.challenge")]
+	challenge: Option<String>,
+	#[serde(rename = "hub.lease_seconds")]
+	lease_seconds: Option<u64>,
+}
+#[tracing::instrument]
+pub async fn verify_intent<Persistence>(
+	Path(feed_id): Path<FeedId>,
+	State(state): State<AppState<Persistence>>,
+	Query(verification): Query<HubVerification>,
+) -> Result<String, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let verification = state.domain_service
+		.verify_websub_intent(
+			&feed_id,
+			&verification.mode,
+			verification.topic.as_deref(),
+			verification.challenge.as_deref(),
+			verification.lease_seconds,
+		)
+		.await?;
+	match verification {
+		Verification::Confirm(challenge) => Ok(challenge),
+		Verification::Reject => Err(HttpError::NotFound),
+	}
+}
+
+#[tracing::instrument(skip(headers, body))]
+pub async fn receive_content<Persistence>(
+	Path(feed_id): Path<FeedId>,
+	State(state): State<AppState<Persistence>>,
+	headers: HeaderMap,
+	body: Bytes,
+) -> Result<StatusCode, HttpError>
+where Persistence: RussetPersistenceLayer {
+	let signature = headers
+		.get("X-Hub-Signature")
+		.and_then(|signature| signature.to_str().ok());
+	let content_type = headers
+		.get(header::CONTENT_TYPE)
+		.and_then(|content_type| content_type.to_str().ok());
+	if state.domain_service.receive_websub_content(&feed_id, signature, content_type, &body).await? {
+		Ok(StatusCode::ACCEPTED)
+	} else {
+		Err(HttpError::NotFound)
+	}
+}
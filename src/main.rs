@@ -6,15 +6,20 @@ mod persistence;
 mod server;
 mod model;
 
+use axum::http::HeaderName;
 use clap::Parser;
 use crate::conf::{ Command, Config };
+use crate::domain::feeds::credentials::CredentialCipher;
+use crate::domain::login_limits::LoginLimits;
 use crate::domain::RussetDomainService;
 use crate::feed::atom::AtomFeedReader;
 use crate::feed::rss::RssFeedReader;
 use crate::feed::RussetFeedReader;
+use crate::http::ProxyAuth;
 use crate::persistence::sql::SqlDatabase;
 use crate::server::start;
 use merge::Merge;
+use reqwest::Url;
 use rpassword::prompt_password;
 use std::error::Error;
 use std::fs::read_to_string;
@@ -60,9 +65,18 @@ async fn main() -> Result<()> {
 	let db_file = config.db_file.expect("No db_file");
 	let listen_address = config.listen_address.expect("No listen_address");
 	let pepper = config.pepper.expect("No pepper");
+	let credential_cipher = config.credential_key
+		.map(|key| CredentialCipher::new(key.as_bytes()))
+		.transpose()?;
 	let feed_check_interval =
 		config.feed_check_interval.expect("No feed_check_interval");
 	let disable_logins = config.disable_logins.expect("No disable_logins");
+	let local_feed_sources = config.local_feed_sources.expect("No local_feed_sources");
+	let public_url = config.public_url
+		.map(|url| Url::parse(&url))
+		.transpose()?;
+	let entry_retention_days = config.entry_retention_days.expect("No entry_retention_days");
+	let secure_cookies = config.secure_cookies.expect("No secure_cookies");
 	let global_concurrent_limit = config
 		.rate_limiting
 		.global_concurrent_limit
@@ -71,6 +85,28 @@ async fn main() -> Result<()> {
 		.rate_limiting
 		.login_concurrent_limit
 		.expect("No login_concurrent_limit");
+	let login_limits = LoginLimits {
+		attempts_per_ip: config.rate_limiting.login_attempts_per_ip.expect("No login_attempts_per_ip"),
+		attempts_per_user: config.rate_limiting.login_attempts_per_user.expect("No login_attempts_per_user"),
+		refill_interval: config.rate_limiting.login_attempt_refill.expect("No login_attempt_refill"),
+		lockout_threshold: config.rate_limiting.login_lockout_threshold.expect("No login_lockout_threshold"),
+		lockout_duration: config.rate_limiting.login_lockout_duration.expect("No login_lockout_duration"),
+	};
+	let trusted_proxies = config.trusted_proxies.expect("No trusted_proxies");
+	let proxy_auth = match config.proxy_auth.proxy_auth_header {
+		Some(header) => {
+			let addresses = config.proxy_auth.proxy_auth_addresses.expect("No proxy_auth_addresses");
+			if addresses.is_empty() {
+				return Err("proxy_auth_header is set, but there are no proxy_auth_addresses to trust it from".into())
+			}
+			Some(ProxyAuth {
+				header: HeaderName::try_from(header)?,
+				addresses,
+				provision_users: config.proxy_auth.proxy_auth_provision_users.expect("No proxy_auth_provision_users"),
+			} )
+		},
+		None => None,
+	};
 
 	let db = SqlDatabase::new(Path::new(&db_file)).await?;
 	let readers: Vec<Box<dyn RussetFeedReader>> = vec![
@@ -81,11 +117,16 @@ async fn main() -> Result<()> {
 		db,
 		readers,
 		pepper.as_bytes().to_vec(),
+		credential_cipher,
 		// TODO
 		feed_check_interval,
 		feed_check_interval,
 		feed_check_interval,
 		disable_logins,
+		login_limits,
+		local_feed_sources,
+		public_url,
+		entry_retention_days,
 	)?);
 
 	match command {
@@ -93,7 +134,10 @@ async fn main() -> Result<()> {
 				domain_service,
 				listen_address,
 				global_concurrent_limit,
-				login_concurrent_limit
+				login_concurrent_limit,
+				secure_cookies,
+				trusted_proxies,
+				proxy_auth,
 			)
 			.await?,
 		Command::AddUser { user_name, password, user_type } => {
@@ -133,6 +177,20 @@ async fn main() -> Result<()> {
 			info!("Deleting sessions for {user_name}…");
 			domain_service.delete_user_sessions(&user_name).await?;
 		}
+		Command::ResetTwoFactor { user_name } => {
+			info!("Resetting two-factor authentication for {user_name}…");
+			domain_service.reset_two_factor(&user_name).await?;
+		}
+		Command::Prune { dry_run } => {
+			info!("Pruning entries{}…", if dry_run { " (dry run)" } else { "" });
+			let pruned = domain_service.prune_entries(dry_run).await?;
+			let verb = if dry_run { "Would prune" } else { "Pruned" };
+			for (feed, count) in &pruned {
+				println!("{verb} {count} entries from {} ({})", feed.title, feed.url);
+			}
+			let total: u64 = pruned.iter().map(|(_, count)| count).sum();
+			println!("{verb} {total} entries in total");
+		}
 		_ => { warn!("Not yet implemented") },
 	}
 	info!("Done!");
@@ -1,5 +1,6 @@
 /// Utility types
 
+use chrono_tz::Tz;
 use clap::ValueEnum;
 use crate::{ Err, Result };
 use serde::Deserialize;
@@ -21,6 +22,10 @@ impl Timestamp {
 	pub fn until(timestamp: Timestamp) -> Duration {
 		timestamp.0.duration_since(Self::now().0).unwrap_or(Duration::ZERO)
 	}
+	/// This [timestamp] plus `duration`, if that can be represented
+	pub fn checked_add(self, duration: Duration) -> Option<Timestamp> {
+		self.0.checked_add(duration).map(Timestamp)
+	}
 }
 impl std::fmt::Debug for Timestamp {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -46,6 +51,104 @@ pub struct Pagination {
 	pub page_size: usize,
 }
 
+/// The position of an entry in entry listings. Listings are ordered by these
+/// keys (in an order given by [ListingOptions]), so a page can start just
+/// after or before a cursor and stay put as new entries arrive.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct EntryCursor {
+	pub check_id: u64,
+	pub article_date: Timestamp,
+	pub id: EntryId,
+}
+impl std::fmt::Display for EntryCursor {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let article_date: i64 = self.article_date.try_into().map_err(|_| std::fmt::Error)?;
+		write!(f, "{}.{}.{}", self.check_id, article_date, self.id.to_string())
+	}
+}
+impl TryFrom<String> for EntryCursor {
+	type Error = Err;
+	fn try_from(str: String) -> Result<EntryCursor> {
+		let bad_cursor = || -> Err { format!("Bad page cursor {str:?}").into() };
+		let mut parts = str.split('.');
+		let (Some(check_id), Some(article_date), Some(id), None) =
+				(parts.next(), parts.next(), parts.next(), parts.next()) else {
+			return Err(bad_cursor())
+		};
+		Ok(EntryCursor {
+			check_id: check_id.parse().map_err(|_| bad_cursor())?,
+			article_date: article_date.parse::<i64>().map_err(|_| bad_cursor())?.into(),
+			id: EntryId(Ulid::from_string(id).map_err(|_| bad_cursor())?),
+		} )
+	}
+}
+
+/// Where a page of an entry listing is
+#[derive(Clone, Copy, Debug, Default)]
+pub enum PagePosition {
+	#[default]
+	First,
+	/// The page starting just after the given entry
+	After(EntryCursor),
+	/// The page ending just before the given entry
+	Before(EntryCursor),
+	Last,
+}
+impl PagePosition {
+	/// Whether the page is found by reading the listing backwards from its
+	/// position
+	pub fn is_backward(&self) -> bool {
+		matches!(self, PagePosition::Before(_) | PagePosition::Last)
+	}
+	pub fn cursor(&self) -> Option<&EntryCursor> {
+		match self {
+			PagePosition::After(cursor) | PagePosition::Before(cursor) => Some(cursor),
+			PagePosition::First | PagePosition::Last => None,
+		}
+	}
+}
+
+/// Keyset pagination for entry listings
+#[derive(Clone, Copy, Debug)]
+pub struct EntryPagination {
+	pub position: PagePosition,
+	pub page_size: usize,
+}
+
+/// A parsed entry search. See [crate::domain::search] for the query syntax.
+#[derive(Clone, Debug, Default)]
+pub struct SearchQuery {
+	/// Words and phrases which must all appear in the entry
+	pub terms: Vec<String>,
+	/// Only match entries from feeds whose titles contain this
+	pub feed: Option<String>,
+	/// Only match entries dated before this
+	pub before: Option<Timestamp>,
+	/// Only match entries dated at or after this
+	pub after: Option<Timestamp>,
+	/// Only match entries the user hasn't read
+	pub unread_only: bool,
+}
+
+/// Which of a user's entries to mark read in one go. Every condition given
+/// must hold, so the default scope is every entry the user is subscribed to.
+#[derive(Clone, Debug, Default)]
+pub struct MarkReadScope {
+	/// Only entries from this feed
+	pub feed_id: Option<FeedId>,
+	/// Only entries from feeds filed in this folder
+	pub folder: Option<String>,
+	/// Only entries the user has starred
+	pub starred_only: bool,
+	/// Only entries the user has given this label
+	pub label: Option<String>,
+	/// Only entries dated before this
+	pub before: Option<Timestamp>,
+	/// Only these entries
+	pub entry_ids: Option<Vec<EntryId>>,
+}
+
 #[derive(Clone, Copy, Deserialize, Eq, Hash, PartialEq)]
 pub struct FeedId(pub Ulid);
 impl Deref for FeedId { type Target = Ulid; fn deref(&self) -> &Self::Target { &self.0 } }
@@ -54,7 +157,7 @@ impl std::fmt::Debug for FeedId {
 		f.write_fmt(format_args!("\"{}\"", &self.to_string()))
 	}
 }
-#[derive(Clone, Copy, Deserialize)]
+#[derive(Clone, Copy, Deserialize, Eq, Hash, PartialEq)]
 pub struct EntryId(pub Ulid);
 impl Deref for EntryId{ type Target = Ulid; fn deref(&self) -> &Self::Target { &self.0 } }
 impl std::fmt::Debug for EntryId {
@@ -62,7 +165,7 @@ impl std::fmt::Debug for EntryId {
 		f.write_fmt(format_args!("\"{}\"", &self.to_string()))
 	}
 }
-#[derive(Clone, Copy, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Copy, Deserialize, Eq, Hash, PartialEq)]
 pub struct UserId(pub Ulid);
 impl Deref for UserId{ type Target = Ulid; fn deref(&self) -> &Self::Target { &self.0 } }
 impl std::fmt::Debug for UserId {
@@ -70,11 +173,180 @@ impl std::fmt::Debug for UserId {
 		f.write_fmt(format_args!("\"{}\"", &self.to_string()))
 	}
 }
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq)]
+pub struct RuleId(pub Ulid);
+impl Deref for RuleId { type Target = Ulid; fn deref(&self) -> &Self::Target { &self.0 } }
+impl std::fmt::Debug for RuleId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_fmt(format_args!("\"{}\"", &self.to_string()))
+	}
+}
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq)]
+pub struct InvitationId(pub Ulid);
+impl Deref for InvitationId { type Target = Ulid; fn deref(&self) -> &Self::Target { &self.0 } }
+impl std::fmt::Debug for InvitationId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_fmt(format_args!("\"{}\"", &self.to_string()))
+	}
+}
+
+/// Defines an enum of unit variants which convert to and from the given
+/// strings, for storage and forms
+macro_rules! string_enum {
+	($(#[$meta:meta])* $name:ident { $($(#[$variant_meta:meta])* $variant:ident => $str:literal,)* }) => {
+		$(#[$meta])*
+		#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+		pub enum $name {
+			$(
+				$(#[$variant_meta])*
+				#[serde(rename = $str)]
+				$variant,
+			)*
+		}
+		impl $name {
+			pub const ALL: &'static [$name] = &[$($name::$variant),*];
+			pub fn as_str(&self) -> &'static str {
+				match self {
+					$($name::$variant => $str,)*
+				}
+			}
+		}
+		impl TryFrom<String> for $name {
+			type Error = Err;
+			fn try_from(str: String) -> Result<$name> {
+				match str.as_str() {
+					$($str => Ok($name::$variant),)*
+					_ => Err(format!("Unrecognized {} {str:?}", stringify!($name)).into()),
+				}
+			}
+		}
+	};
+}
+
+string_enum! {
+	/// What entry listings are ordered by
+	#[derive(Default)]
+	EntrySort {
+		/// When Russet first saw the entry
+		#[default]
+		Discovered => "discovered",
+		/// The date the feed gave the entry
+		ArticleDate => "article_date",
+	}
+}
+/// Which of a user's entries a listing shows, and in what order. The default is
+/// every entry, newest first by discovery time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ListingOptions {
+	pub unread_only: bool,
+	pub oldest_first: bool,
+	pub sort: EntrySort,
+}
+
+string_enum! {
+	/// How entry dates are shown
+	#[derive(Default)]
+	DateFormat {
+		/// ISO 8601: the full timestamp for recent entries, the date alone for
+		/// older ones
+		#[default]
+		Iso => "iso",
+		/// The date in the [DateLocale]'s style, with the time for recent
+		/// entries
+		Absolute => "absolute",
+		/// How long ago, like "3h ago", for recent entries, and the date in
+		/// the [DateLocale]'s style for older ones
+		Relative => "relative",
+	}
+}
+string_enum! {
+	/// Conventions for writing dates and times
+	#[derive(Default)]
+	DateLocale {
+		#[default]
+		EnUs => "en-US",
+		EnGb => "en-GB",
+		De => "de",
+		Fr => "fr",
+		Ja => "ja",
+	}
+}
+string_enum! {
+	/// The colors pages are shown in
+	#[derive(Default)]
+	Theme {
+		#[default]
+		Dark => "dark",
+		Light => "light",
+	}
+}
+
+/// How a user's dates are shown. The default is ISO 8601 in UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateDisplay {
+	pub timezone: Tz,
+	pub format: DateFormat,
+	pub locale: DateLocale,
+	/// Entries newer than this many days are recent, and shown with times
+	pub recent_days: u32,
+}
+impl Default for DateDisplay {
+	fn default() -> DateDisplay {
+		DateDisplay {
+			timezone: Tz::UTC,
+			format: DateFormat::default(),
+			locale: DateLocale::default(),
+			recent_days: 2,
+		}
+	}
+}
+
+string_enum! {
+	/// The part of an entry a rule matches against
+	RuleField {
+		Title => "title",
+		Author => "author",
+		/// Matches if any of the entry's categories match
+		Category => "category",
+		Url => "url",
+	}
+}
+
+string_enum! {
+	/// How a rule's pattern is matched. See [crate::domain::rules].
+	RuleMatch {
+		Keywords => "keywords",
+		Regex => "regex",
+	}
+}
+
+string_enum! {
+	/// What a rule does to the entries it matches
+	RuleAction {
+		MarkRead => "mark_read",
+		Delete => "delete",
+		Star => "star",
+		Label => "label",
+	}
+}
+
 #[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq, ValueEnum)]
 pub enum UserType {
 	Sysop,
 	Member,
 }
+impl UserType {
+	/// Whether users of this type may perform the given privileged operation
+	pub fn has_permission(&self, permission: Permission) -> bool {
+		match (self, permission) {
+			(UserType::Sysop, _) => true,
+			(UserType::Member, Permission::LocalFeedSources) => false,
+			(UserType::Member, Permission::ScrapedFeeds) => false,
+			(UserType::Member, Permission::FeedRetention) => false,
+			(UserType::Member, Permission::ManageUsers) => false,
+		}
+	}
+}
 impl TryFrom<String> for UserType {
 	type Error = Err;
 	fn try_from(str: String) -> Result<UserType> {
@@ -93,3 +365,16 @@ impl Into<String> for UserType {
 		}
 	}
 }
+
+/// Privileged operations, which only some [UserType]s may perform
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+	/// Add feeds which read local files or run commands on the server
+	LocalFeedSources,
+	/// Define feeds scraped from HTML pages
+	ScrapedFeeds,
+	/// Change how long a feed's read entries are kept, for all its subscribers
+	FeedRetention,
+	/// Add, disable and otherwise administer other users
+	ManageUsers,
+}
@@ -2,8 +2,8 @@ pub mod model;
 pub mod sql;
 
 use crate::Result;
-use crate::model::{ EntryId, FeedId, Pagination, Timestamp, UserId };
-use model::{ Entry, Feed, FeedCheck, Session, User, UserEntry, WriteFeedCheck };
+use crate::model::{ EntryId, EntryPagination, FeedId, InvitationId, ListingOptions, MarkReadScope, Pagination, RuleId, SearchQuery, Timestamp, UserId };
+use model::{ Entry, Feed, FeedCheck, Invitation, PasswordHash, Rule, ScrapeSelectors, Session, SessionClient, SubscriptionStats, Totp, User, UserEntry, UserEntryPage, UserPreferences, WebSubSubscription, WriteFeedCheck };
 use reqwest::Url;
 use std::future::Future;
 
@@ -17,6 +17,15 @@ pub trait RussetFeedPersistenceLayer: Send + Sync + std::fmt::Debug + 'static {
 	/// Add the given [Feed] to this persistence layer
 	fn add_feed(&self, feed: &Feed) -> impl Future<Output = Result<()>> + Send;
 
+	/// Add the given [Feed] to this persistence layer, to be scraped with the
+	/// given [ScrapeSelectors]
+	fn add_scraped_feed(&self, feed: &Feed, selectors: &ScrapeSelectors)
+		-> impl Future<Output = Result<()>> + Send;
+
+	/// Get the [ScrapeSelectors] for the given feed, if it is scraped
+	fn get_scrape_selectors(&self, feed_id: &FeedId)
+		-> impl Future<Output = Result<Option<ScrapeSelectors>>> + Send;
+
 	/// Get all the [Feed]s stored by this persistence layer
 	fn get_feeds(&self)
 		-> impl Future<
@@ -31,7 +40,9 @@ pub trait RussetFeedPersistenceLayer: Send + Sync + std::fmt::Debug + 'static {
 	/// Get a specific [Feed] by ID
 	fn get_feed(&self, id: &FeedId) -> impl Future<Output = Result<Feed>> + Send;
 
-	/// Get a specific [Feed] by URL
+	/// Get a specific [Feed] by URL. Feeds with credentials are private to
+	/// their subscriber, and scraped feeds are specific to their selectors, so
+	/// neither is ever returned here.
 	fn get_feed_by_url(&self, url: &Url)
 		-> impl Future<Output = Result<Option<Feed>>> + Send;
 
@@ -39,6 +50,16 @@ pub trait RussetFeedPersistenceLayer: Send + Sync + std::fmt::Debug + 'static {
 	fn get_subscribed_feeds(&self, user_id: &UserId)
 		-> impl Future<Output = impl IntoIterator<Item = Result<Feed>>> + Send;
 
+	/// Get all the [Feed]s the given user is subscribed to, with the folder
+	/// each subscription is filed in
+	fn get_subscriptions(&self, user_id: &UserId)
+		-> impl Future<Output = impl IntoIterator<Item = Result<(Feed, Option<String>)>>> + Send;
+
+	/// Get all the given user's subscriptions, with statistics about each
+	/// feed. Entries dated since `recent_since` count as recent.
+	fn get_subscription_stats(&self, user_id: &UserId, recent_since: &Timestamp)
+		-> impl Future<Output = impl IntoIterator<Item = Result<SubscriptionStats>>> + Send;
+
 	/// Add the given [WriteFeedCheck] to the persistence layer. The persistence
 	/// layer will generate the `id`.
 	fn add_feed_check(&self, feed_check: WriteFeedCheck)
@@ -49,6 +70,33 @@ pub trait RussetFeedPersistenceLayer: Send + Sync + std::fmt::Debug + 'static {
 	fn get_feed_checks(&self, feed_id: &FeedId, pagination: &Pagination)
 		-> impl Future<Output = impl IntoIterator<Item = Result<FeedCheck>>> + Send;
 
+	/// Add or replace the [WebSubSubscription] for its feed
+	fn set_websub_subscription(&self, subscription: &WebSubSubscription)
+		-> impl Future<Output = Result<()>> + Send;
+
+	/// Get the [WebSubSubscription] for the given feed, if there is one
+	fn get_websub_subscription(&self, feed_id: &FeedId)
+		-> impl Future<Output = Result<Option<WebSubSubscription>>> + Send;
+
+	/// Get all verified [WebSubSubscription]s whose leases expire before the
+	/// given time
+	fn get_expiring_websub_subscriptions(&self, before: &Timestamp)
+		-> impl Future<Output = impl IntoIterator<Item = Result<WebSubSubscription>>> + Send;
+
+	/// Remove the [WebSubSubscription] for the given feed
+	fn delete_websub_subscription(&self, feed_id: &FeedId)
+		-> impl Future<Output = Result<()>> + Send;
+
+	/// Get how many days the given feed's read entries are kept, if it
+	/// overrides the default
+	fn get_feed_retention(&self, feed_id: &FeedId)
+		-> impl Future<Output = Result<Option<u32>>> + Send;
+
+	/// Set how many days the given feed's read entries are kept, or (with
+	/// `None`) go back to the default. 0 keeps them forever.
+	fn set_feed_retention(&self, feed_id: &FeedId, retention_days: Option<u32>)
+		-> impl Future<Output = Result<()>> + Send;
+
 	/// Get the latest feed check for the given feed.
 	///
 	/// The default implementation calls [get_feed_checks] with a [Pagination]
@@ -77,21 +125,122 @@ pub trait RussetEntryPersistenceLayer: Send + Sync + std::fmt::Debug + 'static {
 	fn get_entries_for_feed(&self, feed_id: &FeedId)
 		-> impl Future<Output = impl IntoIterator<Item = Result<Entry>>> + Send;
 
+	/// Get the [Entry]s from feeds other than the given one dated at or after
+	/// `since`, to find duplicates of new entries among
+	fn get_recent_entries_from_other_feeds(&self, feed_id: &FeedId, since: &Timestamp)
+		-> impl Future<Output = impl IntoIterator<Item = Result<Entry>>> + Send;
+
+	/// For each of the given entries, get the IDs and titles of the given
+	/// user's other feeds which have duplicates of it
+	fn get_duplicate_feeds(&self, user_id: &UserId, entry_ids: &[EntryId])
+		-> impl Future<Output = impl IntoIterator<Item = Result<(EntryId, FeedId, String)>>> + Send;
+
 	/// Get entries for all the feeds to which the given user is subscribed.
-	fn get_entries_for_user(&self, user_id: &UserId, pagination: &Pagination)
-		-> impl Future<Output = impl IntoIterator<Item = Result<(Entry, Option<UserEntry>)>>> + Send;
+	/// This and the other user entry listings filter and order their entries
+	/// by the given [ListingOptions], skip entries the user has deleted, and
+	/// return pages in listing order even when reading backwards. This and the
+	/// folder listing only include the first of a group of duplicates.
+	fn get_entries_for_user(&self, user_id: &UserId, options: &ListingOptions, pagination: &EntryPagination)
+		-> impl Future<Output = Result<UserEntryPage>> + Send;
 
 	/// Get entries for the given feed to which the given user is subscribed
-	fn get_entries_for_user_feed(&self, user_id: &UserId, feed_id: &FeedId, pagination: &Pagination)
+	fn get_entries_for_user_feed(&self, user_id: &UserId, feed_id: &FeedId, options: &ListingOptions, pagination: &EntryPagination)
+		-> impl Future<Output = Result<UserEntryPage>> + Send;
+
+	/// Get the entries the given user has starred
+	fn get_starred_entries_for_user(&self, user_id: &UserId, options: &ListingOptions, pagination: &EntryPagination)
+		-> impl Future<Output = Result<UserEntryPage>> + Send;
+
+	/// Get entries for the feeds the given user has filed in the given folder
+	fn get_entries_for_user_folder(&self, user_id: &UserId, folder: &str, options: &ListingOptions, pagination: &EntryPagination)
+		-> impl Future<Output = Result<UserEntryPage>> + Send;
+
+	/// Mark every unread entry in `scope` in the feeds the given user is
+	/// subscribed to as read at the given time, in a single update. Returns
+	/// how many entries were newly marked read.
+	fn mark_entries_read(&self, user_id: &UserId, scope: &MarkReadScope, read: &Timestamp)
+		-> impl Future<Output = Result<u64>> + Send;
+
+	/// Delete the given entries in the feeds the given user is subscribed to,
+	/// and their duplicates in the user's other feeds, for the user at the
+	/// given time, in a single update. Entries already deleted keep their
+	/// original times.
+	fn delete_entries(&self, user_id: &UserId, entry_ids: &[EntryId], time: &Timestamp)
+		-> impl Future<Output = Result<()>> + Send;
+
+	/// Get the entries the given user has applied the given label to
+	fn get_labeled_entries_for_user(&self, user_id: &UserId, label: &str, options: &ListingOptions, pagination: &EntryPagination)
+		-> impl Future<Output = Result<UserEntryPage>> + Send;
+
+	/// Get the internal IDs of the given feed's pruned entries
+	fn get_pruned_internal_ids(&self, feed_id: &FeedId)
+		-> impl Future<Output = impl IntoIterator<Item = Result<String>>> + Send;
+
+	/// Prune entries dated more than their feed's retention period (or, for
+	/// feeds without one, `default_retention_days`) before `now`, which every
+	/// subscriber has read and none has starred or labeled. A retention period
+	/// of 0 keeps entries forever. Pruned entries' internal IDs are kept, so
+	/// they aren't added again. Returns how many entries were pruned from each feed; with
+	/// `dry_run`, nothing is pruned, and it returns how many would have been.
+	fn prune_entries(&self, default_retention_days: u32, now: &Timestamp, dry_run: bool)
+		-> impl Future<Output = Result<Vec<(FeedId, u64)>>> + Send;
+
+	/// Get the given user's labels, with how many (undeleted) entries each is
+	/// applied to, sorted by name
+	fn get_label_counts(&self, user_id: &UserId)
+		-> impl Future<Output = impl IntoIterator<Item = Result<(String, u64)>>> + Send;
+
+	/// Apply the given label to the given entry for the given user. Applying a
+	/// label an entry already has is not an error; entries outside the user's
+	/// subscriptions are left alone.
+	fn add_entry_label(&self, user_id: &UserId, entry_id: &EntryId, label: &str)
+		-> impl Future<Output = Result<()>> + Send;
+
+	/// Remove the given label from the given entry for the given user
+	fn remove_entry_label(&self, user_id: &UserId, entry_id: &EntryId, label: &str)
+		-> impl Future<Output = Result<()>> + Send;
+
+	/// Search the entries in feeds the given user is subscribed to, skipping
+	/// any they've deleted, most recent first
+	fn search_entries(&self, user_id: &UserId, query: &SearchQuery, pagination: &Pagination)
 		-> impl Future<Output = impl IntoIterator<Item = Result<(Entry, Option<UserEntry>)>>> + Send;
 
+	/// Add the given [Rule]
+	fn add_rule(&self, rule: &Rule) -> impl Future<Output = Result<()>> + Send;
+
+	/// Get the given user's rules, oldest first
+	fn get_rules_for_user(&self, user_id: &UserId)
+		-> impl Future<Output = impl IntoIterator<Item = Result<Rule>>> + Send;
+
+	/// Get every rule that applies to entries in the given feed: its
+	/// subscribers' rules for that feed and for all their feeds
+	fn get_rules_for_feed(&self, feed_id: &FeedId)
+		-> impl Future<Output = impl IntoIterator<Item = Result<Rule>>> + Send;
+
+	/// Delete the given user's rule with the given ID
+	fn delete_rule(&self, user_id: &UserId, rule_id: &RuleId)
+		-> impl Future<Output = Result<()>> + Send;
+
 	/// Atomically get an entry and set the userentry for the given entry and user.
+	///
+	/// Only `read` and `tombstone` are set; an entry's starred state is only
+	/// changed by [set_userentry_starred](Self::set_userentry_starred).
 	fn get_entry_and_set_userentry(
 		&self,
 		entry_id: &EntryId,
 		user_id: &UserId,
 		user_entry: &UserEntry,
 	) -> impl Future<Output = Result<Entry>> + Send;
+
+	/// Star (with the time it was starred) or unstar (with `None`) the given
+	/// entry for the given user. Returns `false` if the entry isn't in one of
+	/// the user's subscriptions.
+	fn set_userentry_starred(
+		&self,
+		entry_id: &EntryId,
+		user_id: &UserId,
+		starred: Option<&Timestamp>,
+	) -> impl Future<Output = Result<bool>> + Send;
 }
 
 pub trait RussetUserPersistenceLayer: Send + Sync + std::fmt::Debug + 'static {
@@ -99,14 +248,26 @@ pub trait RussetUserPersistenceLayer: Send + Sync + std::fmt::Debug + 'static {
 	/// Get the [User] with the given [UserId]
 	fn get_user(&self, user_id: &UserId) -> impl Future<Output = Result<User>> + Send;
 
+	/// Get every [User], by name, with how many sessions each has
+	fn get_users(&self) -> impl Future<Output = Result<Vec<(User, u32)>>> + Send;
+
 	/// Add the given [User] to the persistence layer
-	async fn add_user(&self, user: &User) -> Result<()>;
+	fn add_user(&self, user: &User) -> impl Future<Output = Result<()>> + Send;
 
 	/// Update the user with the given [User] in the persistence layer
-	async fn update_user(&self, user: &User) -> Result<()>;
+	fn update_user(&self, user: &User)
+		-> impl Future<Output = Result<()>> + Send;
 
 	/// Delete the given [User] from the persistence layer
-	async fn delete_user(&self, user_id: &UserId) -> Result<()>;
+	fn delete_user(&self, user_id: &UserId) -> impl Future<Output = Result<()>> + Send;
+
+	/// Get the given user's saved [UserPreferences], if they have saved any
+	fn get_user_preferences(&self, user_id: &UserId)
+		-> impl Future<Output = Result<Option<UserPreferences>>> + Send;
+
+	/// Save the given user's [UserPreferences], replacing any already saved
+	fn set_user_preferences(&self, user_id: &UserId, preferences: &UserPreferences)
+		-> impl Future<Output = Result<()>> + Send;
 
 	/// Given a username, look up that user
 	fn get_user_by_name(&self, user_name: &str)
@@ -120,17 +281,93 @@ pub trait RussetUserPersistenceLayer: Send + Sync + std::fmt::Debug + 'static {
 	fn get_user_by_session(&self, session_token: &str)
 		-> impl Future<Output = Result<Option<(User, Session)>>> + Send;
 
+	/// Get all the given user's sessions, most recently used first
+	fn get_sessions_for_user(&self, user_id: &UserId)
+		-> impl Future<Output = Result<Vec<Session>>> + Send;
+
+	/// Record that the session with the given token was used by the given
+	/// client
+	fn touch_session(&self, session_token: &str, last_used: &Timestamp, client: &SessionClient)
+		-> impl Future<Output = Result<()>> + Send;
+
 	fn delete_session(&self, session_token: &str)
 		-> impl Future<Output = Result<()>> + Send;
 	
 	fn delete_expired_sessions(&self, expiry: &Timestamp)
 		-> impl Future<Output = Result<()>> + Send;
 
-	async fn delete_sessions_for_user(&self, user_id: &UserId) -> Result<u32>;
+	fn delete_sessions_for_user(&self, user_id: &UserId)
+		-> impl Future<Output = Result<u32>> + Send;
+
+	/// Delete all the given user's sessions except the one with the given
+	/// token. Returns how many were deleted.
+	fn delete_other_sessions_for_user(&self, user_id: &UserId, session_token: &str)
+		-> impl Future<Output = Result<u32>> + Send;
+
+	/// Get the given user's [Totp], if they've set up two-factor
+	/// authentication
+	fn get_totp(&self, user_id: &UserId)
+		-> impl Future<Output = Result<Option<Totp>>> + Send;
+
+	/// Set up two-factor authentication for the given user with the given
+	/// [Totp] and recovery codes, replacing any already set up
+	fn set_totp(&self, user_id: &UserId, totp: &Totp, recovery_codes: &[PasswordHash])
+		-> impl Future<Output = Result<()>> + Send;
+
+	/// Record that the given user's TOTP code for the given time step was
+	/// used. Returns false, recording nothing, if that step or a later one
+	/// already was.
+	fn use_totp_step(&self, user_id: &UserId, step: u64)
+		-> impl Future<Output = Result<bool>> + Send;
+
+	/// Turn off two-factor authentication for the given user, deleting their
+	/// [Totp] and recovery codes
+	fn delete_totp(&self, user_id: &UserId)
+		-> impl Future<Output = Result<()>> + Send;
+
+	/// Get the hashes of the given user's unused recovery codes
+	fn get_recovery_codes(&self, user_id: &UserId)
+		-> impl Future<Output = Result<Vec<PasswordHash>>> + Send;
+
+	/// Replace the given user's recovery codes
+	fn set_recovery_codes(&self, user_id: &UserId, recovery_codes: &[PasswordHash])
+		-> impl Future<Output = Result<()>> + Send;
+
+	/// Use up the given user's recovery code with the given hash. Returns
+	/// false if it had already been used.
+	fn delete_recovery_code(&self, user_id: &UserId, recovery_code: &PasswordHash)
+		-> impl Future<Output = Result<bool>> + Send;
+
+	fn add_invitation(&self, invitation: &Invitation)
+		-> impl Future<Output = Result<()>> + Send;
+
+	/// Get every [Invitation], newest first, with the name of the user who
+	/// created each
+	fn get_invitations(&self)
+		-> impl Future<Output = Result<Vec<(Invitation, String)>>> + Send;
+
+	/// Given a hash of an invitation's token, look up that invitation
+	fn get_invitation_by_token_hash(&self, token_hash: &str)
+		-> impl Future<Output = Result<Option<Invitation>>> + Send;
+
+	/// Delete the given invitation. Returns false if there was no such
+	/// invitation, e.g. because it was already used.
+	fn delete_invitation(&self, invitation_id: &InvitationId)
+		-> impl Future<Output = Result<bool>> + Send;
+
+	/// Delete invitations which expired before `expiry`. Returns how many were
+	/// deleted.
+	fn delete_expired_invitations(&self, expiry: &Timestamp)
+		-> impl Future<Output = Result<u32>> + Send;
 
 	fn add_subscription(&self, user_id: &UserId, feed_id: &FeedId)
 		-> impl Future<Output = Result<()>> + Send;
 
 	fn remove_subscription(&self, user_id: &UserId, feed_id: &FeedId)
 		-> impl Future<Output = Result<()>> + Send;
+
+	/// File the given user's subscription to the given feed in a folder, or
+	/// (with `None`) take it out of its folder
+	fn set_subscription_folder(&self, user_id: &UserId, feed_id: &FeedId, folder: Option<&str>)
+		-> impl Future<Output = Result<()>> + Send;
 }
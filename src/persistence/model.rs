@@ -1,4 +1,5 @@
-use crate::model::{ EntryId, FeedId, UserId, UserType, Timestamp };
+use crate::model::{ DateDisplay, EntryId, FeedId, InvitationId, ListingOptions, Theme, RuleAction, RuleField, RuleId, RuleMatch, UserId, UserType, Timestamp };
+use crate::Result;
 use reqwest::Url;
 
 /// Metadata for a feed, e.g. title and feed URL
@@ -7,6 +8,27 @@ pub struct Feed {
 	pub id: FeedId,
 	pub title: String,
 	pub url: Url,
+	pub credentials: Option<EncryptedCredentials>,
+}
+
+/// CSS selectors for scraping a feed from an HTML page. See
+/// [ScrapeFeedReader](crate::feed::scrape::ScrapeFeedReader).
+#[derive(Clone, Debug)]
+pub struct ScrapeSelectors {
+	pub item: String,
+	pub title: Option<String>,
+	pub link: Option<String>,
+	pub date: Option<String>,
+}
+
+/// Credentials to send when fetching a feed, encrypted by the domain layer. The
+/// persistence layer treats this as opaque.
+#[derive(Clone)]
+pub struct EncryptedCredentials(pub String);
+impl std::fmt::Debug for EncryptedCredentials {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("\"<redacted>\"")
+	}
 }
 
 /// Individual entry from a given feed
@@ -19,6 +41,11 @@ pub struct Entry {
 	pub article_date: Timestamp,
 	pub title: String,
 	pub url: Option<Url>,
+	pub author: Option<String>,
+	pub categories: Vec<String>,
+	/// The first entry of the group of entries from other feeds this one
+	/// duplicates, if it's a duplicate
+	pub duplicate_of: Option<EntryId>,
 }
 
 #[derive(Clone)]
@@ -34,6 +61,28 @@ pub struct User {
 	pub name: String,
 	pub password_hash: PasswordHash,
 	pub user_type: UserType,
+	/// Disabled users can't log in
+	pub disabled: bool,
+}
+
+/// A user's saved defaults. Users who haven't saved any get [Default::default].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UserPreferences {
+	/// Applied to entry listings unless the request overrides them
+	pub listing: ListingOptions,
+	pub page_size: usize,
+	pub dates: DateDisplay,
+	pub theme: Theme,
+}
+impl Default for UserPreferences {
+	fn default() -> UserPreferences {
+		UserPreferences {
+			listing: ListingOptions::default(),
+			page_size: 100,
+			dates: DateDisplay::default(),
+			theme: Theme::default(),
+		}
+	}
 }
 
 #[derive(Clone)]
@@ -48,12 +97,96 @@ pub struct Session {
 	pub token: SessionToken,
 	pub user_id: UserId,
 	pub expiration: Timestamp,
+	/// When the session was logged in. Unknown for sessions from before this
+	/// was recorded.
+	pub created: Option<Timestamp>,
+	/// When the session was last used, give or take
+	/// [SESSION_TOUCH_INTERVAL](crate::domain::user::SESSION_TOUCH_INTERVAL)
+	pub last_used: Option<Timestamp>,
+	/// Where the session was last used from
+	pub client: SessionClient,
+}
+
+/// The client a session is being used from, so users can recognize their
+/// sessions
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SessionClient {
+	pub ip: Option<String>,
+	pub user_agent: Option<String>,
+}
+
+/// A user's TOTP second factor
+#[derive(Clone)]
+pub struct Totp {
+	/// The shared secret, base32-encoded as authenticator apps take it
+	pub secret: String,
+	/// The time step of the last code accepted, so none is accepted twice
+	pub last_step: u64,
+}
+impl std::fmt::Debug for Totp {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Totp")
+			.field("secret", &"<redacted>")
+			.field("last_step", &self.last_step)
+			.finish()
+	}
+}
+
+/// An invitation for someone to register as a new user; see
+/// [crate::domain::invitations]
+#[derive(Clone, Debug)]
+pub struct Invitation {
+	pub id: InvitationId,
+	/// A hash of the invitation's token, which is only ever in its link
+	pub token_hash: String,
+	/// The type of user registering with the invitation makes
+	pub user_type: UserType,
+	pub created_by: UserId,
+	pub created: Timestamp,
+	/// When the invitation stops working, if it ever does
+	pub expiration: Option<Timestamp>,
 }
 
 #[derive(Clone, Debug)]
 pub struct UserEntry {
 	pub read: Option<Timestamp>,
 	pub tombstone: Option<Timestamp>,
+	pub starred: Option<Timestamp>,
+}
+
+/// A page of a user's entry listing, in listing order
+pub struct UserEntryPage {
+	pub entries: Vec<Result<(Entry, Option<UserEntry>)>>,
+	/// Whether the listing goes on past the page, in the direction it was read
+	pub more: bool,
+	/// How many undeleted entries the whole listing has, ignoring
+	/// [ListingOptions::unread_only]
+	pub total: u64,
+	/// How many of those the user hasn't read
+	pub unread: u64,
+}
+
+#[derive(Clone)]
+pub struct HubSecret(pub String);
+impl std::fmt::Debug for HubSecret {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("\"<redacted>\"")
+	}
+}
+/// A WebSub subscription to a feed's hub
+#[derive(Clone, Debug)]
+pub struct WebSubSubscription {
+	pub feed_id: FeedId,
+	pub hub: Url,
+	pub topic: Url,
+	/// Secret shared with the hub, used to sign pushed content
+	pub secret: HubSecret,
+	/// When the hub's lease on this subscription runs out. `None` if the hub
+	/// hasn't verified the subscription yet.
+	pub lease_expiration: Option<Timestamp>,
+	/// When we last asked the hub to (re)subscribe, if it hasn't verified or
+	/// denied that request yet
+	pub pending_since: Option<Timestamp>,
 }
 
 /// Maintaining the feed check ID is a concern of the persistence layer, so the
@@ -64,6 +197,8 @@ pub struct WriteFeedCheck {
 	pub check_time: Timestamp,
 	pub next_check_time: Timestamp,
 	pub etag: Option<String>,
+	/// Why the check failed, if it did
+	pub error: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -73,6 +208,8 @@ pub struct FeedCheck {
 	pub check_time: Timestamp,
 	pub next_check_time: Timestamp,
 	pub etag: Option<String>,
+	/// Why the check failed, if it did
+	pub error: Option<String>,
 }
 impl FeedCheck {
 	pub fn from_write_feed_check(id: u64, check: WriteFeedCheck) -> FeedCheck {
@@ -82,6 +219,37 @@ impl FeedCheck {
 			check_time: check.check_time,
 			next_check_time: check.next_check_time,
 			etag: check.etag,
+			error: check.error,
 		}
 	}
 }
+
+/// A user's subscription to a feed, with statistics about the feed
+#[derive(Clone, Debug)]
+pub struct SubscriptionStats {
+	pub feed: Feed,
+	pub folder: Option<String>,
+	/// Entries in the feed the user hasn't read or deleted
+	pub unread_count: u64,
+	/// Entries in the feed dated since the cutoff the stats were requested for
+	pub recent_entries: u64,
+	/// When the feed was last checked without error
+	pub last_success: Option<Timestamp>,
+	/// The latest check of the feed, successful or not
+	pub last_check: Option<FeedCheck>,
+}
+
+/// A user's rule for filtering entries; see [crate::domain::rules]
+#[derive(Clone, Debug)]
+pub struct Rule {
+	pub id: RuleId,
+	pub user_id: UserId,
+	/// The feed the rule applies to, or `None` for all the user's feeds
+	pub feed_id: Option<FeedId>,
+	pub field: RuleField,
+	pub match_type: RuleMatch,
+	pub pattern: String,
+	pub action: RuleAction,
+	/// The label to apply, for [RuleAction::Label]
+	pub label: Option<String>,
+}
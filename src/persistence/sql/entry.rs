@@ -1,9 +1,12 @@
-use crate::model::{ EntryId, FeedId, Pagination, UserId };
+use crate::model::{ EntryId, EntryPagination, EntrySort, FeedId, ListingOptions, MarkReadScope, Pagination, RuleId, SearchQuery, Timestamp, UserId };
 use crate::persistence::RussetEntryPersistenceLayer;
 use crate::persistence::sql::SqlDatabase;
-use crate::persistence::model::{ Entry, UserEntry };
+use crate::persistence::model::{ Entry, Rule, UserEntry, UserEntryPage };
 use crate::Result;
 use reqwest::Url;
+use std::collections::{ BTreeMap, HashMap };
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
 use ulid::Ulid;
 
 impl RussetEntryPersistenceLayer for SqlDatabase {
@@ -15,10 +18,13 @@ impl RussetEntryPersistenceLayer for SqlDatabase {
 		let check_id: i64 = entry.check_id.try_into()?;
 		let article_date: i64 = entry.article_date.clone().try_into()?;
 		let entry_url = entry.url.clone().map(|url| url.to_string());
+		let categories = join_categories(&entry.categories);
+		let duplicate_of = entry.duplicate_of.map(|id| id.to_string());
+		let mut tx = self.pool.begin().await?;
 		sqlx::query!("
 				INSERT INTO entries (
-					id, feed_id, internal_id, check_id, article_date, title, url
-				) VALUES ( ?, ?, ?, ?, ?, ?, ? )",
+					id, feed_id, internal_id, check_id, article_date, title, url, author, categories, duplicate_of
+				) VALUES ( ?, ?, ?, ?, ?, ?, ?, ?, ?, ? )",
 				entry_id,
 				feed_id,
 				entry.internal_id,
@@ -26,9 +32,42 @@ impl RussetEntryPersistenceLayer for SqlDatabase {
 				article_date,
 				entry.title,
 				entry_url,
+				entry.author,
+				categories,
+				duplicate_of,
 			)
-			.execute(&self.pool)
+			.execute(&mut *tx)
+			.await?;
+		sqlx::query!("
+				INSERT INTO entry_search (entry_id, title) VALUES ( ?, ? )",
+				entry_id,
+				entry.title,
+			)
+			.execute(&mut *tx)
 			.await?;
+		// Subscribers who've already read a duplicate have read this too
+		sqlx::query!("
+				INSERT INTO user_entry_settings (user_id, entry_id, read)
+				SELECT s.user_id, ?, MIN(u.read)
+				FROM subscriptions AS s
+				INNER JOIN user_entry_settings AS u
+					ON s.user_id = u.user_id
+				INNER JOIN entries AS g
+					ON u.entry_id = g.id
+				WHERE s.feed_id = ?
+					AND COALESCE(g.duplicate_of, g.id) = ?
+					AND g.id != ?
+					AND u.read IS NOT NULL
+				GROUP BY s.user_id;",
+				entry_id,
+				feed_id,
+				duplicate_of,
+				entry_id,
+			)
+			.execute(&mut *tx)
+			.await?;
+		tx.commit().await?;
+		self.listing_counts.forget_all();
 		Ok(())
 	}
 
@@ -37,7 +76,7 @@ impl RussetEntryPersistenceLayer for SqlDatabase {
 		let entry_id = id.to_string();
 		let row = sqlx::query!("
 				SELECT
-					id, feed_id, internal_id, check_id, article_date, title, url
+					id, feed_id, internal_id, check_id, article_date, title, url, author, categories, duplicate_of
 				FROM entries
 				WHERE id = ?;",
 				entry_id,
@@ -55,6 +94,9 @@ impl RussetEntryPersistenceLayer for SqlDatabase {
 			article_date: row.article_date.into(),
 			title: row.title,
 			url,
+			author: row.author,
+			categories: split_categories(row.categories),
+			duplicate_of: parse_duplicate_of(row.duplicate_of)?,
 		} )
 	}
 
@@ -64,7 +106,7 @@ impl RussetEntryPersistenceLayer for SqlDatabase {
 		// TODO: Maybe do paging later. Or figure out how to stream from sqlx.
 		let rows = sqlx::query!("
 				SELECT
-					id, feed_id, internal_id, check_id, article_date, title, url
+					id, feed_id, internal_id, check_id, article_date, title, url, author, categories, duplicate_of
 				FROM entries
 				WHERE feed_id = ?
 				ORDER BY check_id DESC, article_date DESC;",
@@ -86,6 +128,9 @@ impl RussetEntryPersistenceLayer for SqlDatabase {
 						article_date: row.article_date.into(),
 						title: row.title,
 						url,
+						author: row.author,
+						categories: split_categories(row.categories),
+						duplicate_of: parse_duplicate_of(row.duplicate_of)?,
 					} )
 				} )
 					.collect()
@@ -95,13 +140,102 @@ impl RussetEntryPersistenceLayer for SqlDatabase {
 		rv
 	}
 
+	#[tracing::instrument]
+	async fn get_recent_entries_from_other_feeds(
+		&self,
+		feed_id: &FeedId,
+		since: &Timestamp,
+	) -> impl IntoIterator<Item = Result<Entry>> {
+		let feed_id = feed_id.to_string();
+		let since: i64 = match (*since).try_into() {
+			Ok(since) => since,
+			Err(e) => return vec![Err(e)],
+		};
+		let rows = sqlx::query!("
+				SELECT
+					id, feed_id, internal_id, check_id, article_date, title, url, author, categories, duplicate_of
+				FROM entries
+				WHERE feed_id != ? AND article_date >= ?
+				ORDER BY id;",
+				feed_id,
+				since,
+			)
+			.fetch_all(&self.pool)
+			.await;
+		match rows {
+			Ok(rows) => {
+				rows.into_iter().map(|row| {
+					let id = EntryId(Ulid::from_string(&row.id)?);
+					let feed_id = FeedId(Ulid::from_string(&row.feed_id)?);
+					let url = row.url.map(|url| Url::parse(&url)).transpose()?;
+					Ok(Entry {
+						id,
+						feed_id,
+						internal_id: row.internal_id,
+						check_id: row.check_id.try_into()?,
+						article_date: row.article_date.into(),
+						title: row.title,
+						url,
+						author: row.author,
+						categories: split_categories(row.categories),
+						duplicate_of: parse_duplicate_of(row.duplicate_of)?,
+					} )
+				} )
+					.collect()
+			},
+			Err(e) => vec![Err(Box::new(e))],
+		}
+	}
+
+	#[tracing::instrument]
+	async fn get_duplicate_feeds(
+		&self,
+		user_id: &UserId,
+		entry_ids: &[EntryId],
+	) -> impl IntoIterator<Item = Result<(EntryId, FeedId, String)>> {
+		let user_id = user_id.to_string();
+		let entry_ids = json_id_array(entry_ids);
+		let rows = sqlx::query!(r#"
+				SELECT DISTINCT
+					e.id AS "entry_id!",
+					f.id AS "feed_id!",
+					f.title AS "feed_title!"
+				FROM entries AS e
+				INNER JOIN entries AS g
+					ON COALESCE(g.duplicate_of, g.id) = COALESCE(e.duplicate_of, e.id)
+				INNER JOIN subscriptions AS s
+					ON g.feed_id = s.feed_id
+				INNER JOIN feeds AS f
+					ON g.feed_id = f.id
+				WHERE e.id IN (SELECT value FROM json_each(?))
+					AND s.user_id = ?
+					AND g.feed_id != e.feed_id
+				ORDER BY e.id, f.title;"#,
+				entry_ids,
+				user_id,
+			)
+			.fetch_all(&self.pool)
+			.await;
+		let rv: Vec<Result<(EntryId, FeedId, String)>> = match rows {
+			Ok(rows) => rows.into_iter().map(|row| Ok( (
+					EntryId(Ulid::from_string(&row.entry_id)?),
+					FeedId(Ulid::from_string(&row.feed_id)?),
+					row.feed_title,
+				) ) )
+				.collect(),
+			Err(e) => vec![Err(Box::new(e))],
+		};
+		rv
+	}
+
 	#[tracing::instrument]
 	async fn get_entries_for_user(
 		&self,
 		user_id: &UserId,
-		pagination: &Pagination,
-	) -> Vec<Result<(Entry, Option<UserEntry>)>> {
-		self.get_userentries(user_id, None, None, pagination).await
+		options: &ListingOptions,
+		pagination: &EntryPagination,
+	) -> Result<UserEntryPage> {
+		self.get_userentries(user_id, &UserEntryFilter::default(), options, pagination).await
 	}
 
 	#[tracing::instrument]
@@ -109,9 +243,527 @@ impl RussetEntryPersistenceLayer for SqlDatabase {
 		&self,
 		user_id: &UserId,
 		feed_id: &FeedId,
+		options: &ListingOptions,
+		pagination: &EntryPagination,
+	) -> Result<UserEntryPage> {
+		self.get_userentries(
+				user_id,
+				&UserEntryFilter { feed_id: Some(feed_id), ..Default::default() },
+				options,
+				pagination,
+			).await
+	}
+
+	#[tracing::instrument]
+	async fn get_starred_entries_for_user(
+		&self,
+		user_id: &UserId,
+		options: &ListingOptions,
+		pagination: &EntryPagination,
+	) -> Result<UserEntryPage> {
+		self.get_userentries(
+				user_id,
+				&UserEntryFilter { starred_only: true, ..Default::default() },
+				options,
+				pagination,
+			).await
+	}
+
+	#[tracing::instrument]
+	async fn get_entries_for_user_folder(
+		&self,
+		user_id: &UserId,
+		folder: &str,
+		options: &ListingOptions,
+		pagination: &EntryPagination,
+	) -> Result<UserEntryPage> {
+		self.get_userentries(
+				user_id,
+				&UserEntryFilter { folder: Some(folder), ..Default::default() },
+				options,
+				pagination,
+			)
+			.await
+	}
+
+	#[tracing::instrument]
+	async fn mark_entries_read(
+		&self,
+		user_id: &UserId,
+		scope: &MarkReadScope,
+		read: &Timestamp,
+	) -> Result<u64> {
+		let user_id = user_id.to_string();
+		let read: i64 = (*read).try_into()?;
+		let no_feed = scope.feed_id.is_none();
+		let feed_id = scope.feed_id.map(|id| id.to_string());
+		let no_folder = scope.folder.is_none();
+		let starred_only = scope.starred_only;
+		let no_label = scope.label.is_none();
+		let no_before = scope.before.is_none();
+		let before: Option<i64> = scope.before.map(i64::try_from).transpose()?;
+		let no_entries = scope.entry_ids.is_none();
+		let entry_ids = scope.entry_ids.as_deref().map(json_id_array);
+		// As in `get_userentries`, the (? OR ...) clauses skip conditions
+		// outside the scope, so the query can stay static. Marking an entry
+		// read marks its duplicates in the user's other feeds read too.
+		let result = sqlx::query!("
+				INSERT INTO user_entry_settings (user_id, entry_id, read)
+				SELECT DISTINCT s.user_id, g.id, ?
+				FROM entries AS e
+				INNER JOIN subscriptions AS s
+					ON e.feed_id = s.feed_id
+				INNER JOIN entries AS g
+					ON COALESCE(g.duplicate_of, g.id) = COALESCE(e.duplicate_of, e.id)
+				INNER JOIN subscriptions AS gs
+					ON g.feed_id = gs.feed_id AND s.user_id = gs.user_id
+				LEFT OUTER JOIN user_entry_settings AS u
+					ON s.user_id = u.user_id AND e.id = u.entry_id
+				WHERE s.user_id = ?
+					AND (? OR s.feed_id = ?)
+					AND (? OR s.folder = ?)
+					AND (NOT ? OR u.starred IS NOT NULL)
+					AND (? OR e.id IN (
+						SELECT entry_id FROM entry_labels
+						WHERE user_id = s.user_id AND label = ?
+					))
+					AND (? OR e.article_date < ?)
+					AND (? OR e.id IN (SELECT value FROM json_each(?)))
+				ON CONFLICT (user_id, entry_id)
+				DO UPDATE SET read = excluded.read
+				WHERE read IS NULL;",
+				read,
+				user_id,
+				no_feed,
+				feed_id,
+				no_folder,
+				scope.folder,
+				starred_only,
+				no_label,
+				scope.label,
+				no_before,
+				before,
+				no_entries,
+				entry_ids,
+			)
+			.execute(&self.pool)
+			.await?;
+		self.listing_counts.forget_user(&user_id);
+		Ok(result.rows_affected())
+	}
+
+	#[tracing::instrument]
+	async fn delete_entries(&self, user_id: &UserId, entry_ids: &[EntryId], time: &Timestamp) -> Result<()> {
+		let user_id = user_id.to_string();
+		let time: i64 = (*time).try_into()?;
+		let entry_ids = json_id_array(entry_ids);
+		// As in `mark_entries_read`, only entries in the user's own
+		// subscriptions are deleted, along with their duplicates in the user's
+		// other feeds
+		sqlx::query!("
+				INSERT INTO user_entry_settings (user_id, entry_id, read, tombstone)
+				SELECT DISTINCT s.user_id, g.id, ?, ?
+				FROM entries AS e
+				INNER JOIN subscriptions AS s
+					ON e.feed_id = s.feed_id
+				INNER JOIN entries AS g
+					ON COALESCE(g.duplicate_of, g.id) = COALESCE(e.duplicate_of, e.id)
+				INNER JOIN subscriptions AS gs
+					ON g.feed_id = gs.feed_id AND s.user_id = gs.user_id
+				WHERE s.user_id = ?
+					AND e.id IN (SELECT value FROM json_each(?))
+				ON CONFLICT (user_id, entry_id)
+				DO UPDATE SET
+					read = COALESCE(read, excluded.read),
+					tombstone = COALESCE(tombstone, excluded.tombstone);",
+				time,
+				time,
+				user_id,
+				entry_ids,
+			)
+			.execute(&self.pool)
+			.await?;
+		self.listing_counts.forget_user(&user_id);
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn get_labeled_entries_for_user(
+		&self,
+		user_id: &UserId,
+		label: &str,
+		options: &ListingOptions,
+		pagination: &EntryPagination,
+	) -> Result<UserEntryPage> {
+		self.get_userentries(
+				user_id,
+				&UserEntryFilter { label: Some(label), ..Default::default() },
+				options,
+				pagination,
+			).await
+	}
+
+	#[tracing::instrument]
+	async fn get_pruned_internal_ids(&self, feed_id: &FeedId) -> impl IntoIterator<Item = Result<String>> {
+		let feed_id = feed_id.to_string();
+		let rows = sqlx::query!("
+				SELECT internal_id
+				FROM pruned_entries
+				WHERE feed_id = ?;",
+				feed_id,
+			)
+			.fetch_all(&self.pool)
+			.await;
+		let rv: Vec<Result<String>> = match rows {
+			Ok(rows) => rows.into_iter().map(|row| Ok(row.internal_id)).collect(),
+			Err(e) => vec![Err(Box::new(e))],
+		};
+		rv
+	}
+
+	#[tracing::instrument]
+	async fn prune_entries(&self, default_retention_days: u32, now: &Timestamp, dry_run: bool) -> Result<Vec<(FeedId, u64)>> {
+		let now: i64 = (*now).try_into()?;
+		let mut tx = self.pool.begin().await?;
+		// An entry is unread by a subscriber if they have no settings for it
+		// or haven't set read
+		let rows = sqlx::query!("
+				SELECT e.id, e.feed_id
+				FROM entries AS e
+				INNER JOIN feeds AS f
+					ON e.feed_id = f.id
+				WHERE COALESCE(f.retention_days, ?) > 0
+					AND e.article_date < ? - COALESCE(f.retention_days, ?) * 86400000
+					AND NOT EXISTS (
+						SELECT 1 FROM user_entry_settings AS u
+						WHERE u.entry_id = e.id AND u.starred IS NOT NULL
+					)
+					AND NOT EXISTS (
+						SELECT 1 FROM entry_labels AS l
+						WHERE l.entry_id = e.id
+					)
+					AND NOT EXISTS (
+						SELECT 1 FROM subscriptions AS s
+						LEFT OUTER JOIN user_entry_settings AS u
+							ON s.user_id = u.user_id AND u.entry_id = e.id
+						WHERE s.feed_id = e.feed_id AND u.read IS NULL
+					);",
+				default_retention_days,
+				now,
+				default_retention_days,
+			)
+			.fetch_all(&mut *tx)
+			.await?;
+		let mut counts = BTreeMap::<String, u64>::new();
+		for row in &rows {
+			*counts.entry(row.feed_id.clone()).or_default() += 1;
+		}
+		if !dry_run && !rows.is_empty() {
+			let entry_ids = rows.iter()
+				.map(|row| Ok(EntryId(Ulid::from_string(&row.id)?)))
+				.collect::<Result<Vec<EntryId>>>()?;
+			let entry_ids = json_id_array(&entry_ids);
+			sqlx::query!("
+					INSERT OR IGNORE INTO pruned_entries (feed_id, internal_id)
+					SELECT feed_id, internal_id FROM entries
+					WHERE id IN (SELECT value FROM json_each(?));
+					DELETE FROM entry_search
+					WHERE entry_id IN (SELECT value FROM json_each(?));
+					DELETE FROM entry_labels
+					WHERE entry_id IN (SELECT value FROM json_each(?));
+					DELETE FROM user_entry_settings
+					WHERE entry_id IN (SELECT value FROM json_each(?));
+					DELETE FROM entries
+					WHERE id IN (SELECT value FROM json_each(?));",
+					entry_ids,
+					entry_ids,
+					entry_ids,
+					entry_ids,
+					entry_ids,
+				)
+				.execute(&mut *tx)
+				.await?;
+		}
+		tx.commit().await?;
+		if !dry_run {
+			self.listing_counts.forget_all();
+		}
+		counts.into_iter()
+			.map(|(feed_id, count)| Ok((FeedId(Ulid::from_string(&feed_id)?), count)))
+			.collect()
+	}
+
+	#[tracing::instrument]
+	async fn get_label_counts(&self, user_id: &UserId) -> impl IntoIterator<Item = Result<(String, u64)>> {
+		let user_id = user_id.to_string();
+		let rows = sqlx::query!(r#"
+				SELECT
+					l.label,
+					COUNT(*) AS "count!: i64"
+				FROM entry_labels AS l
+				INNER JOIN entries AS e
+					ON l.entry_id = e.id
+				INNER JOIN subscriptions AS s
+					ON e.feed_id = s.feed_id AND l.user_id = s.user_id
+				LEFT OUTER JOIN user_entry_settings AS u
+					ON l.user_id = u.user_id AND l.entry_id = u.entry_id
+				WHERE l.user_id = ?
+					AND u.tombstone IS NULL
+				GROUP BY l.label
+				ORDER BY l.label;"#,
+				user_id,
+			)
+			.fetch_all(&self.pool)
+			.await;
+		let rv: Vec<Result<(String, u64)>> = match rows {
+			Ok(rows) => rows
+				.into_iter()
+				.map(|row| Ok((row.label, row.count.try_into()?)))
+				.collect(),
+			Err(e) => vec![Err(Box::new(e))],
+		};
+		rv
+	}
+
+	#[tracing::instrument]
+	async fn add_entry_label(&self, user_id: &UserId, entry_id: &EntryId, label: &str) -> Result<()> {
+		let user_id = user_id.to_string();
+		let entry_id = entry_id.to_string();
+		// As in `set_userentry_starred`, only entries in the user's own
+		// subscriptions can be labeled
+		sqlx::query!("
+				INSERT INTO entry_labels (user_id, entry_id, label)
+				SELECT s.user_id, e.id, ?
+				FROM entries AS e
+				INNER JOIN subscriptions AS s
+					ON e.feed_id = s.feed_id
+				WHERE s.user_id = ?
+					AND e.id = ?
+				ON CONFLICT DO NOTHING;",
+				label,
+				user_id,
+				entry_id,
+			)
+			.execute(&self.pool)
+			.await?;
+		self.listing_counts.forget_user(&user_id);
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn remove_entry_label(&self, user_id: &UserId, entry_id: &EntryId, label: &str) -> Result<()> {
+		let user_id = user_id.to_string();
+		let entry_id = entry_id.to_string();
+		sqlx::query!("
+				DELETE FROM entry_labels
+				WHERE user_id = ? AND entry_id = ? AND label = ?;",
+				user_id,
+				entry_id,
+				label,
+			)
+			.execute(&self.pool)
+			.await?;
+		self.listing_counts.forget_user(&user_id);
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn search_entries(
+		&self,
+		user_id: &UserId,
+		query: &SearchQuery,
 		pagination: &Pagination,
 	) -> impl IntoIterator<Item = Result<(Entry, Option<UserEntry>)>> {
-		self.get_userentries(user_id, Some(feed_id), None, pagination).await
+		let user_id = user_id.to_string();
+		let no_terms = query.terms.is_empty();
+		let match_expression = fts_match_expression(&query.terms);
+		let no_feed = query.feed.is_none();
+		let feed_pattern = query.feed.as_deref().map(like_pattern);
+		let no_before = query.before.is_none();
+		let no_after = query.after.is_none();
+		let (before, after): (Option<i64>, Option<i64>) = match (
+			query.before.map(TryInto::try_into).transpose(),
+			query.after.map(TryInto::try_into).transpose(),
+		) {
+			(Ok(before), Ok(after)) => (before, after),
+			(Err(e), _) | (_, Err(e)) => return vec![Err(e)],
+		};
+		let unread_only = query.unread_only;
+		let page_size: i64 = match pagination.page_size.try_into() {
+			Ok(i) => i,
+			Err(e) => return vec![Err(e.into())]
+		};
+		let page_offset: Option<i64> = pagination.page_num
+			.checked_mul(pagination.page_size)
+			.and_then(|page_offset| page_offset.try_into().ok());
+		let Some(page_offset) = page_offset else {
+			return vec![Err(format!("Page {} is out of range", pagination.page_num).into())]
+		};
+		// As in get_userentries, the (? OR …) clauses let us skip filters we
+		// weren't given while keeping the query static.
+		let rows = sqlx::query!(r#"
+				SELECT
+					e.id AS "id!",
+					e.feed_id AS "feed_id!",
+					e.internal_id AS "internal_id!",
+					e.check_id AS "check_id!",
+					e.article_date AS "article_date!",
+					e.title AS "title!",
+					e.url,
+					e.author,
+					e.categories,
+					e.duplicate_of,
+					u.user_id AS "user_entry_user_id",
+					u.read,
+					u.tombstone,
+					u.starred
+				FROM entries AS e
+				INNER JOIN subscriptions AS s
+					ON e.feed_id = s.feed_id
+				INNER JOIN feeds AS f
+					ON e.feed_id = f.id
+				LEFT OUTER JOIN user_entry_settings AS u
+					ON s.user_id = u.user_id AND e.id = u.entry_id
+				WHERE s.user_id = ?
+					AND (? OR e.id IN (
+						SELECT entry_id FROM entry_search WHERE entry_search MATCH ?
+					))
+					AND (? OR f.title LIKE ? ESCAPE '!')
+					AND (? OR e.article_date < ?)
+					AND (? OR e.article_date >= ?)
+					AND (NOT ? OR u.read IS NULL)
+					AND u.tombstone IS NULL
+				ORDER BY e.article_date DESC
+				LIMIT ?
+				OFFSET ?;"#,
+				user_id,
+				no_terms,
+				match_expression,
+				no_feed,
+				feed_pattern,
+				no_before,
+				before,
+				no_after,
+				after,
+				unread_only,
+				page_size,
+				page_offset,
+			)
+			.fetch_all(&self.pool)
+			.await;
+		match rows {
+			Ok(rows) => {
+				rows.into_iter().map(|row| {
+					let id = EntryId(Ulid::from_string(&row.id)?);
+					let feed_id = FeedId(Ulid::from_string(&row.feed_id)?);
+					let url = row.url.map(|url| Url::parse(&url)).transpose()?;
+					let entry = Entry {
+						id,
+						feed_id,
+						internal_id: row.internal_id,
+						check_id: row.check_id.try_into()?,
+						article_date: row.article_date.into(),
+						title: row.title,
+						url,
+						author: row.author,
+						categories: split_categories(row.categories),
+						duplicate_of: parse_duplicate_of(row.duplicate_of)?,
+					};
+					let user_entry = row.user_entry_user_id.map(|_| UserEntry {
+						read: row.read.map(|read| read.into()),
+						tombstone: row.tombstone.map(|tombstone| tombstone.into()),
+						starred: row.starred.map(|starred| starred.into()),
+					} );
+					Ok((entry, user_entry))
+				} )
+					.collect()
+			},
+			Err(e) => vec![Err(Box::new(e))],
+		}
+	}
+
+	#[tracing::instrument]
+	async fn add_rule(&self, rule: &Rule) -> Result<()> {
+		let rule_id = rule.id.to_string();
+		let user_id = rule.user_id.to_string();
+		let feed_id = rule.feed_id.map(|id| id.to_string());
+		let field = rule.field.as_str();
+		let match_type = rule.match_type.as_str();
+		let action = rule.action.as_str();
+		sqlx::query!("
+				INSERT INTO rules (
+					id, user_id, feed_id, field, match_type, pattern, action, label
+				) VALUES ( ?, ?, ?, ?, ?, ?, ?, ? )",
+				rule_id,
+				user_id,
+				feed_id,
+				field,
+				match_type,
+				rule.pattern,
+				action,
+				rule.label,
+			)
+			.execute(&self.pool)
+			.await?;
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn get_rules_for_user(&self, user_id: &UserId) -> impl IntoIterator<Item = Result<Rule>> {
+		let user_id = user_id.to_string();
+		let rows = sqlx::query_as!(RuleRow, "
+				SELECT
+					id, user_id, feed_id, field, match_type, pattern, action, label
+				FROM rules
+				WHERE user_id = ?
+				ORDER BY id;",
+				user_id,
+			)
+			.fetch_all(&self.pool)
+			.await;
+		let rv: Vec<Result<Rule>> = match rows {
+			Ok(rows) => rows.into_iter().map(Rule::try_from).collect(),
+			Err(e) => vec![Err(Box::new(e))],
+		};
+		rv
+	}
+
+	#[tracing::instrument]
+	async fn get_rules_for_feed(&self, feed_id: &FeedId) -> impl IntoIterator<Item = Result<Rule>> {
+		let feed_id = feed_id.to_string();
+		let rows = sqlx::query_as!(RuleRow, "
+				SELECT
+					r.id, r.user_id, r.feed_id, r.field, r.match_type, r.pattern, r.action, r.label
+				FROM rules AS r
+				INNER JOIN subscriptions AS s
+					ON r.user_id = s.user_id
+				WHERE s.feed_id = ?
+					AND (r.feed_id IS NULL OR r.feed_id = s.feed_id)
+				ORDER BY r.id;",
+				feed_id,
+			)
+			.fetch_all(&self.pool)
+			.await;
+		let rv: Vec<Result<Rule>> = match rows {
+			Ok(rows) => rows.into_iter().map(Rule::try_from).collect(),
+			Err(e) => vec![Err(Box::new(e))],
+		};
+		rv
+	}
+
+	#[tracing::instrument]
+	async fn delete_rule(&self, user_id: &UserId, rule_id: &RuleId) -> Result<()> {
+		let user_id = user_id.to_string();
+		let rule_id = rule_id.to_string();
+		sqlx::query!("
+				DELETE FROM rules
+				WHERE user_id = ? AND id = ?;",
+				user_id,
+				rule_id,
+			)
+			.execute(&self.pool)
+			.await?;
+		Ok(())
 	}
 
 	#[tracing::instrument]
@@ -133,7 +785,7 @@ impl RussetEntryPersistenceLayer for SqlDatabase {
 		// Query the entry first to make sure it actually exists
 		let row = sqlx::query!("
 				SELECT
-					id, feed_id, internal_id, check_id, article_date, title, url
+					id, feed_id, internal_id, check_id, article_date, title, url, author, categories, duplicate_of
 				FROM entries
 				WHERE id = ?;",
 				entry_id,
@@ -155,7 +807,31 @@ impl RussetEntryPersistenceLayer for SqlDatabase {
 			)
 			.execute(&mut *tx)
 			.await?;
+		// Duplicates in the user's other feeds are the same entry to them, so
+		// they're read or deleted along with it. Viewing an entry doesn't
+		// restore deleted duplicates, though.
+		sqlx::query!("
+				INSERT INTO user_entry_settings (user_id, entry_id, read, tombstone)
+				SELECT s.user_id, g.id, ?, ?
+				FROM entries AS e
+				INNER JOIN entries AS g
+					ON COALESCE(g.duplicate_of, g.id) = COALESCE(e.duplicate_of, e.id)
+				INNER JOIN subscriptions AS s
+					ON g.feed_id = s.feed_id
+				WHERE e.id = ? AND g.id != e.id AND s.user_id = ?
+				ON CONFLICT (user_id, entry_id)
+				DO UPDATE SET
+					read = excluded.read,
+					tombstone = COALESCE(excluded.tombstone, tombstone);",
+				read,
+				tombstone,
+				entry_id,
+				user_id,
+			)
+			.execute(&mut *tx)
+			.await?;
 		tx.commit().await?;
+		self.listing_counts.forget_user(&user_id);
 		let id = EntryId(Ulid::from_string(&row.id)?);
 		let feed_id = FeedId(Ulid::from_string(&row.feed_id)?);
 		let url = row.url.map(|url| Url::parse(&url)).transpose()?;
@@ -167,8 +843,165 @@ impl RussetEntryPersistenceLayer for SqlDatabase {
 			article_date: row.article_date.into(),
 			title: row.title,
 			url,
+			author: row.author,
+			categories: split_categories(row.categories),
+			duplicate_of: parse_duplicate_of(row.duplicate_of)?,
 		} )
 	}
+
+	#[tracing::instrument]
+	async fn set_userentry_starred(
+		&self,
+		entry_id: &EntryId,
+		user_id: &UserId,
+		starred: Option<&Timestamp>,
+	) -> Result<bool> {
+		let entry_id = entry_id.to_string();
+		let user_id = user_id.to_string();
+		let starred: Option<i64> = starred
+			.map(|timestamp| (*timestamp).try_into())
+			.transpose()?;
+		// As in `mark_entries_read`, only entries in the user's own
+		// subscriptions can be starred
+		let result = sqlx::query!("
+				INSERT INTO user_entry_settings (user_id, entry_id, starred)
+				SELECT s.user_id, e.id, ?
+				FROM entries AS e
+				INNER JOIN subscriptions AS s
+					ON e.feed_id = s.feed_id
+				WHERE s.user_id = ?
+					AND e.id = ?
+				ON CONFLICT (user_id, entry_id)
+				DO UPDATE SET starred = excluded.starred;",
+				starred,
+				user_id,
+				entry_id,
+			)
+			.execute(&self.pool)
+			.await?;
+		self.listing_counts.forget_user(&user_id);
+		Ok(result.rows_affected() > 0)
+	}
+}
+
+/// Restrictions on which entries [SqlDatabase::get_userentries] returns. The
+/// default is no restrictions.
+#[derive(Debug, Default)]
+struct UserEntryFilter<'a> {
+	feed_id: Option<&'a FeedId>,
+	entry_id: Option<&'a EntryId>,
+	starred_only: bool,
+	label: Option<&'a str>,
+	folder: Option<&'a str>,
+}
+impl UserEntryFilter<'_> {
+	/// Listings of everything or a folder show each group of duplicates once,
+	/// as its first entry the user can see. Narrower listings show the entries
+	/// they're about.
+	fn groups_duplicates(&self) -> bool {
+		self.feed_id.is_none() && self.entry_id.is_none() && !self.starred_only && self.label.is_none()
+	}
+}
+
+/// One row of [SqlDatabase::get_userentries]' queries
+struct UserEntryRow {
+	id: String,
+	feed_id: String,
+	internal_id: String,
+	check_id: i64,
+	article_date: i64,
+	title: String,
+	url: Option<String>,
+	author: Option<String>,
+	categories: Option<String>,
+	duplicate_of: Option<String>,
+	user_entry_user_id: Option<String>,
+	read: Option<i64>,
+	tombstone: Option<i64>,
+	starred: Option<i64>,
+}
+
+/// How long cached listing counts are trusted. Changes made through this
+/// database forget the counts they affect straight away; this bounds how stale
+/// counts get from changes made elsewhere, like the prune command.
+const LISTING_COUNTS_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// The listing a [ListingCounts] is for
+#[derive(Eq, Hash, PartialEq)]
+struct ListingKey {
+	user_id: String,
+	feed_id: Option<FeedId>,
+	entry_id: Option<EntryId>,
+	starred_only: bool,
+	label: Option<String>,
+	folder: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+struct ListingCounts {
+	total: u64,
+	unread: u64,
+}
+
+/// Total and unread counts of entry listings, so that paging through a listing
+/// doesn't count the whole of it again for every page
+#[derive(Default)]
+pub(super) struct ListingCountCache {
+	counts: Mutex<ListingCountState>,
+}
+#[derive(Default)]
+struct ListingCountState {
+	/// Bumped whenever counts are forgotten, so that counts read before a
+	/// change don't get cached after it
+	generation: u64,
+	counts: HashMap<ListingKey, (Instant, ListingCounts)>,
+}
+impl std::fmt::Debug for ListingCountCache {
+	// Leave out the counts, which would put every recent listing in any log
+	// line including the database
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("ListingCountCache").finish_non_exhaustive()
+	}
+}
+impl ListingCountCache {
+	/// The current generation, to pass to [ListingCountCache::insert] with
+	/// counts read after this
+	fn generation(&self) -> u64 {
+		self.counts.lock().expect("listing counts lock shouldn't be poisoned").generation
+	}
+
+	/// The cached counts for a listing, if they're fresh
+	fn get(&self, key: &ListingKey) -> Option<ListingCounts> {
+		let state = self.counts.lock().expect("listing counts lock shouldn't be poisoned");
+		state.counts.get(key)
+			.filter(|(cached, _)| cached.elapsed() < LISTING_COUNTS_TTL)
+			.map(|(_, counts)| *counts)
+	}
+
+	fn insert(&self, key: ListingKey, counts: ListingCounts, generation: u64) {
+		let mut state = self.counts.lock().expect("listing counts lock shouldn't be poisoned");
+		if state.generation == generation {
+			let now = Instant::now();
+			state.counts.retain(|_, (cached, _)| now.duration_since(*cached) < LISTING_COUNTS_TTL);
+			state.counts.insert(key, (now, counts));
+		}
+	}
+
+	/// Forget the counts of a user's listings, after changing which of their
+	/// entries are read, deleted, starred or labeled, or which feeds they
+	/// subscribe to
+	pub(super) fn forget_user(&self, user_id: &str) {
+		let mut state = self.counts.lock().expect("listing counts lock shouldn't be poisoned");
+		state.generation += 1;
+		state.counts.retain(|key, _| key.user_id != user_id);
+	}
+
+	/// Forget every listing's counts, after adding or removing entries
+	pub(super) fn forget_all(&self) {
+		let mut state = self.counts.lock().expect("listing counts lock shouldn't be poisoned");
+		state.generation += 1;
+		state.counts.clear();
+	}
 }
 
 impl SqlDatabase {
@@ -176,41 +1009,214 @@ impl SqlDatabase {
 	async fn get_userentries(
 		&self,
 		user_id: &UserId,
-		feed_id: Option<&FeedId>,
-		entry_id: Option<&EntryId>,
-		pagination: &Pagination,
-	) -> Vec<Result<(Entry, Option<UserEntry>)>> {
+		filter: &UserEntryFilter<'_>,
+		options: &ListingOptions,
+		pagination: &EntryPagination,
+	) -> Result<UserEntryPage> {
 		let user_id_str = user_id.to_string();
-		let no_feed = feed_id.is_none();
-		let feed_id_str = feed_id.map(|id| id.to_string());
-		let no_entry = entry_id.is_none();
-		let entry_id_str = entry_id.map(|id| id.to_string());
-		let page_size: i64 = match pagination.page_size.try_into() {
-			Ok(i) => i,
-			Err(e) => return vec![Err(e.into())]
+		let no_feed = filter.feed_id.is_none();
+		let feed_id_str = filter.feed_id.map(|id| id.to_string());
+		let no_entry = filter.entry_id.is_none();
+		let entry_id_str = filter.entry_id.map(|id| id.to_string());
+		let starred_only = filter.starred_only;
+		let no_label = filter.label.is_none();
+		let label = filter.label;
+		let no_folder = filter.folder.is_none();
+		let folder = filter.folder;
+		let unread_only = options.unread_only;
+		let group_duplicates = filter.groups_duplicates();
+		let by_date = options.sort == EntrySort::ArticleDate;
+		let ascending = options.oldest_first != pagination.position.is_backward();
+		// The first page starts from a cursor past every entry, so each query
+		// always has a cursor predicate to seek to in its index.
+		let (cursor_key, cursor_tiebreak, cursor_id) = match pagination.position.cursor() {
+			Some(cursor) => {
+				let check_id: i64 = cursor.check_id.try_into()?;
+				let article_date: i64 = cursor.article_date.try_into()?;
+				let (key, tiebreak) = if by_date { (article_date, check_id) } else { (check_id, article_date) };
+				(key, tiebreak, cursor.id.to_string())
+			},
+			None if ascending => (i64::MIN, i64::MIN, String::new()),
+			None => (i64::MAX, i64::MAX, String::new()),
 		};
-		let page_offset: i64 = match (pagination.page_num * pagination.page_size).try_into() {
-			Ok(i) => i,
-			Err(e) => return vec![Err(e.into())]
+		// Fetch one extra entry to find out whether there are more
+		let limit: i64 = (pagination.page_size + 1).try_into()?;
+
+		// This query is this way because in order to pass it to query_as!, it
+		// must be a &'static str, which means no dynamically-added query
+		// clauses. The (? OR id = ?) clauses allow us to skip these checks if we
+		// weren't provided an ID to check against. Each sort order and
+		// direction has its own copy, whose cursor predicate and ORDER BY match
+		// the index for that order; entry IDs break ties, so every entry has a
+		// distinct position. The CROSS JOIN keeps SQLite reading entries in
+		// index order, so it can stop once it has a page, rather than
+		// collecting all the user's entries and sorting them.
+		macro_rules! page_query {
+			($cursor:literal, $order:literal) => {
+				sqlx::query_as!(UserEntryRow, r#"
+						SELECT
+							e.id AS "id!",
+							e.feed_id AS "feed_id!",
+							e.internal_id AS "internal_id!",
+							e.check_id AS "check_id!",
+							e.article_date AS "article_date!",
+							e.title AS "title!",
+							e.url,
+							e.author,
+							e.categories,
+							e.duplicate_of,
+							u.user_id AS user_entry_user_id,
+							u.read,
+							u.tombstone,
+							u.starred
+						FROM entries AS e
+						CROSS JOIN subscriptions AS s
+							ON e.feed_id = s.feed_id
+						LEFT OUTER JOIN user_entry_settings AS u
+							ON s.user_id = u.user_id AND e.id = u.entry_id
+						WHERE s.user_id = ?
+							AND (? OR s.feed_id = ?)
+							AND (? OR e.id = ?)
+							AND (NOT ? OR u.starred IS NOT NULL)
+							AND (? OR e.id IN (
+								SELECT entry_id FROM entry_labels
+								WHERE user_id = s.user_id AND label = ?
+							))
+							AND (? OR s.folder = ?)
+							AND (NOT ? OR u.read IS NULL)
+							AND u.tombstone IS NULL
+							AND (NOT ? OR NOT EXISTS (
+								SELECT 1 FROM entries AS d
+								INNER JOIN subscriptions AS ds
+									ON d.feed_id = ds.feed_id
+								LEFT OUTER JOIN user_entry_settings AS du
+									ON ds.user_id = du.user_id AND d.id = du.entry_id
+								WHERE ds.user_id = s.user_id
+									AND COALESCE(d.duplicate_of, d.id) = COALESCE(e.duplicate_of, e.id)
+									AND d.id < e.id
+									AND (? OR ds.folder = ?)
+									AND du.tombstone IS NULL
+							))
+							AND "# + $cursor + "
+						ORDER BY " + $order + "
+						LIMIT ?;",
+						user_id_str,
+						no_feed,
+						feed_id_str,
+						no_entry,
+						entry_id_str,
+						starred_only,
+						no_label,
+						label,
+						no_folder,
+						folder,
+						unread_only,
+						group_duplicates,
+						no_folder,
+						folder,
+						cursor_key,
+						cursor_tiebreak,
+						cursor_id,
+						limit,
+					)
+					.fetch_all(&self.pool)
+					.await?
+			};
+		}
+		let rows = match (by_date, ascending) {
+			(false, true) => page_query!(
+				"(e.check_id, e.article_date, e.id) > (?, ?, ?)",
+				"e.check_id, e.article_date, e.id"
+			),
+			(false, false) => page_query!(
+				"(e.check_id, e.article_date, e.id) < (?, ?, ?)",
+				"e.check_id DESC, e.article_date DESC, e.id DESC"
+			),
+			(true, true) => page_query!(
+				"(e.article_date, e.check_id, e.id) > (?, ?, ?)",
+				"e.article_date, e.check_id, e.id"
+			),
+			(true, false) => page_query!(
+				"(e.article_date, e.check_id, e.id) < (?, ?, ?)",
+				"e.article_date DESC, e.check_id DESC, e.id DESC"
+			),
 		};
-		// TODO: Maybe do paging later. Or figure out how to stream from sqlx.
+		let counts = self.get_listing_counts(user_id, filter).await?;
+		let more = rows.len() > pagination.page_size;
+		let mut entries: Vec<Result<(Entry, Option<UserEntry>)>> = rows.into_iter()
+			.take(pagination.page_size)
+			.map(|row| {
+				let id = EntryId(Ulid::from_string(&row.id)?);
+				let feed_id = FeedId(Ulid::from_string(&row.feed_id)?);
+				let url = row.url.map(|url| Url::parse(&url)).transpose()?;
+				let entry = Entry {
+					id,
+					feed_id,
+					internal_id: row.internal_id,
+					check_id: row.check_id.try_into()?,
+					article_date: row.article_date.into(),
+					title: row.title,
+					url,
+					author: row.author,
+					categories: split_categories(row.categories),
+					duplicate_of: parse_duplicate_of(row.duplicate_of)?,
+				};
+				let user_entry = if row.user_entry_user_id.is_some() {
+					Some(UserEntry {
+						read: row.read.map(|read| read.into()),
+						tombstone: row.tombstone.map(|tombstone| tombstone.into()),
+						starred: row.starred.map(|starred| starred.into()),
+					} )
+				} else {
+					None
+				};
+				Ok( (
+					entry,
+					user_entry,
+				) )
+			} )
+			.collect();
+		if pagination.position.is_backward() {
+			entries.reverse();
+		}
+		Ok(UserEntryPage {
+			entries,
+			more,
+			total: counts.total,
+			unread: counts.unread,
+		} )
+	}
 
-		// This query is this way because in order to pass it to query!, it must
-		// be a &'static str, which means no dynamically-added query clauses.
-		// The (? OR id = ?) clauses allow us to skip these checks if we weren't
-		// provided an ID to check against.
-		let rows = sqlx::query!(r#"
+	/// How many undeleted entries a listing has, and how many of those the
+	/// user hasn't read. These are cached, since they're shown with every page.
+	async fn get_listing_counts(&self, user_id: &UserId, filter: &UserEntryFilter<'_>) -> Result<ListingCounts> {
+		let key = ListingKey {
+			user_id: user_id.to_string(),
+			feed_id: filter.feed_id.copied(),
+			entry_id: filter.entry_id.copied(),
+			starred_only: filter.starred_only,
+			label: filter.label.map(str::to_string),
+			folder: filter.folder.map(str::to_string),
+		};
+		let generation = self.listing_counts.generation();
+		if let Some(counts) = self.listing_counts.get(&key) {
+			return Ok(counts);
+		}
+		let user_id_str = user_id.to_string();
+		let no_feed = filter.feed_id.is_none();
+		let feed_id_str = filter.feed_id.map(|id| id.to_string());
+		let no_entry = filter.entry_id.is_none();
+		let entry_id_str = filter.entry_id.map(|id| id.to_string());
+		let starred_only = filter.starred_only;
+		let no_label = filter.label.is_none();
+		let label = filter.label;
+		let no_folder = filter.folder.is_none();
+		let folder = filter.folder;
+		let group_duplicates = filter.groups_duplicates();
+		let counts = sqlx::query!(r#"
 				SELECT
-					e.id AS "id!",
-					e.feed_id AS "feed_id!",
-					e.internal_id AS "internal_id!",
-					e.check_id AS "check_id!",
-					e.article_date AS "article_date!",
-					e.title AS "title!",
-					e.url,
-					u.user_id AS "user_entry_user_id",
-					u.read,
-					u.tombstone
+					COUNT(*) AS "total!: i64",
+					COUNT(u.read) AS "read!: i64"
 				FROM entries AS e
 				INNER JOIN subscriptions AS s
 					ON e.feed_id = s.feed_id
@@ -219,51 +1225,193 @@ impl SqlDatabase {
 				WHERE s.user_id = ?
 					AND (? OR s.feed_id = ?)
 					AND (? OR e.id = ?)
-				ORDER BY check_id DESC, article_date DESC
-				LIMIT ?
-				OFFSET ?;"#,
+					AND (NOT ? OR u.starred IS NOT NULL)
+					AND (? OR e.id IN (
+						SELECT entry_id FROM entry_labels
+						WHERE user_id = s.user_id AND label = ?
+					))
+					AND (? OR s.folder = ?)
+					AND u.tombstone IS NULL
+					AND (NOT ? OR NOT EXISTS (
+						SELECT 1 FROM entries AS d
+						INNER JOIN subscriptions AS ds
+							ON d.feed_id = ds.feed_id
+						LEFT OUTER JOIN user_entry_settings AS du
+							ON ds.user_id = du.user_id AND d.id = du.entry_id
+						WHERE ds.user_id = s.user_id
+							AND COALESCE(d.duplicate_of, d.id) = COALESCE(e.duplicate_of, e.id)
+							AND d.id < e.id
+							AND (? OR ds.folder = ?)
+							AND du.tombstone IS NULL
+					));"#,
 				user_id_str,
 				no_feed,
 				feed_id_str,
 				no_entry,
 				entry_id_str,
-				page_size,
-				page_offset,
+				starred_only,
+				no_label,
+				label,
+				no_folder,
+				folder,
+				group_duplicates,
+				no_folder,
+				folder,
 			)
-			.fetch_all(&self.pool)
-			.await;
-		let rv: Vec<Result<(Entry, Option<UserEntry>)>> = match rows {
-			Ok(rows) => {
-				rows.into_iter().map(|row| {
-					let id = EntryId(Ulid::from_string(&row.id)?);
-					let feed_id = FeedId(Ulid::from_string(&row.feed_id)?);
-					let url = row.url.map(|url| Url::parse(&url)).transpose()?;
-					let entry = Entry {
-						id,
-						feed_id,
-						internal_id: row.internal_id,
-						check_id: row.check_id.try_into()?,
-						article_date: row.article_date.into(),
-						title: row.title,
-						url,
-					};
-					let user_entry = if row.user_entry_user_id.is_some() {
-						Some(UserEntry {
-							read: row.read.map(|read| read.into()),
-							tombstone: row.tombstone.map(|tombstone| tombstone.into()),
-						} )
-					} else {
-						None
-					};
-					Ok( (
-						entry,
-						user_entry,
-					) )
-				} )
-					.collect()
-			},
-			Err(e) => vec![Err(Box::new(e))],
+			.fetch_one(&self.pool)
+			.await?;
+		let counts = ListingCounts {
+			total: counts.total.try_into()?,
+			unread: (counts.total - counts.read).try_into()?,
 		};
-		rv
+		self.listing_counts.insert(key, counts, generation);
+		Ok(counts)
+	}
+}
+
+/// Build an FTS5 MATCH expression requiring all the given terms. Each term is
+/// quoted, so FTS5 query syntax in it is matched literally.
+fn fts_match_expression(terms: &[String]) -> String {
+	terms.iter()
+		.map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+		.collect::<Vec<String>>()
+		.join(" ")
+}
+
+/// Build a LIKE pattern (with `!` as the escape character) matching strings
+/// containing `substring`
+fn like_pattern(substring: &str) -> String {
+	let escaped = substring
+		.replace('!', "!!")
+		.replace('%', "!%")
+		.replace('_', "!_");
+	format!("%{escaped}%")
+}
+
+/// Categories are stored newline-separated, or NULL if there are none
+fn join_categories(categories: &[String]) -> Option<String> {
+	let categories = categories
+		.iter()
+		.map(|category| category.replace('\n', " "))
+		.collect::<Vec<String>>();
+	Some(categories.join("\n")).filter(|categories| !categories.is_empty())
+}
+
+fn split_categories(categories: Option<String>) -> Vec<String> {
+	categories
+		.map(|categories| categories.split('\n').map(str::to_string).collect())
+		.unwrap_or_default()
+}
+
+/// Parse the ID of the first entry in a duplicate entry's group
+fn parse_duplicate_of(duplicate_of: Option<String>) -> Result<Option<EntryId>> {
+	Ok(duplicate_of.map(|id| Ulid::from_string(&id)).transpose()?.map(EntryId))
+}
+
+/// A list can't be bound as a parameter, so lists of IDs are bound as JSON
+/// arrays and expanded with `json_each`. ULIDs never need escaping.
+fn json_id_array(ids: &[EntryId]) -> String {
+	let ids = ids.iter()
+		.map(|id| format!("\"{}\"", id.to_string()))
+		.collect::<Vec<String>>();
+	format!("[{}]", ids.join(","))
+}
+
+/// A row of the `rules` table
+struct RuleRow {
+	id: String,
+	user_id: String,
+	feed_id: Option<String>,
+	field: String,
+	match_type: String,
+	pattern: String,
+	action: String,
+	label: Option<String>,
+}
+impl TryFrom<RuleRow> for Rule {
+	type Error = crate::Err;
+	fn try_from(row: RuleRow) -> Result<Rule> {
+		Ok(Rule {
+			id: RuleId(Ulid::from_string(&row.id)?),
+			user_id: UserId(Ulid::from_string(&row.user_id)?),
+			feed_id: row.feed_id.map(|id| Ulid::from_string(&id)).transpose()?.map(FeedId),
+			field: row.field.try_into()?,
+			match_type: row.match_type.try_into()?,
+			pattern: row.pattern,
+			action: row.action.try_into()?,
+			label: row.label,
+		} )
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::model::{ EntryId, FeedId, Timestamp, UserId, UserType };
+	use crate::persistence::{ RussetEntryPersistenceLayer, RussetFeedPersistenceLayer, RussetUserPersistenceLayer };
+	use crate::persistence::model::{ Entry, Feed, PasswordHash, User };
+	use crate::persistence::sql::SqlDatabase;
+	use reqwest::Url;
+	use std::path::Path;
+	use ulid::Ulid;
+
+	/// A fresh in-memory database. The pool's one connection keeps it alive.
+	async fn database() -> SqlDatabase {
+		SqlDatabase::new(Path::new("sqlite::memory:")).await.unwrap()
+	}
+
+	async fn add_feed_with_entry(db: &SqlDatabase, name: &str) -> (FeedId, EntryId) {
+		let feed_id = FeedId(Ulid::new());
+		db.add_feed(&Feed {
+				id: feed_id,
+				title: name.to_string(),
+				url: Url::parse(&format!("https://example.com/{name}.xml")).unwrap(),
+				credentials: None,
+			} )
+			.await
+			.unwrap();
+		let entry_id = EntryId(Ulid::new());
+		db.add_entry(&Entry {
+				id: entry_id,
+				feed_id,
+				internal_id: format!("{name}-1"),
+				check_id: 1,
+				article_date: Timestamp::now(),
+				title: format!("An entry from {name}"),
+				url: None,
+				author: None,
+				categories: Vec::new(),
+				duplicate_of: None,
+			}, &feed_id)
+			.await
+			.unwrap();
+		(feed_id, entry_id)
+	}
+
+	#[tokio::test]
+	async fn labeling_an_unsubscribed_entry_does_nothing() {
+		let db = database().await;
+		let user_id = UserId(Ulid::new());
+		db.add_user(&User {
+				id: user_id,
+				name: "reader".to_string(),
+				password_hash: PasswordHash("unused".to_string()),
+				user_type: UserType::Member,
+				disabled: false,
+			} )
+			.await
+			.unwrap();
+		let (subscribed_feed, subscribed_entry) = add_feed_with_entry(&db, "subscribed").await;
+		let (_, other_entry) = add_feed_with_entry(&db, "other").await;
+		db.add_subscription(&user_id, &subscribed_feed).await.unwrap();
+
+		db.add_entry_label(&user_id, &subscribed_entry, "kept").await.unwrap();
+		db.add_entry_label(&user_id, &other_entry, "stolen").await.unwrap();
+
+		let labels = db.get_label_counts(&user_id)
+			.await
+			.into_iter()
+			.collect::<crate::Result<Vec<(String, u64)>>>()
+			.unwrap();
+		assert_eq!(labels, vec![("kept".to_string(), 1)]);
 	}
 }
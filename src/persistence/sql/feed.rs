@@ -1,7 +1,7 @@
-use crate::model::{ FeedId, Pagination, UserId };
+use crate::model::{ FeedId, Pagination, Timestamp, UserId };
 use crate::persistence::RussetFeedPersistenceLayer;
 use crate::persistence::sql::SqlDatabase;
-use crate::persistence::model::{ Feed, FeedCheck, WriteFeedCheck };
+use crate::persistence::model::{ EncryptedCredentials, Feed, FeedCheck, HubSecret, ScrapeSelectors, SubscriptionStats, WebSubSubscription, WriteFeedCheck };
 use crate::Result;
 use reqwest::Url;
 use ulid::Ulid;
@@ -12,25 +12,80 @@ impl RussetFeedPersistenceLayer for SqlDatabase {
 	async fn add_feed(&self, feed: &Feed) -> Result<()> {
 		let feed_id = feed.id.to_string();
 		let feed_url = feed.url.to_string();
+		let credentials = feed.credentials.as_ref().map(|credentials| &credentials.0);
 		sqlx::query!("
 				INSERT INTO feeds (
-					id, url, title
-				) VALUES ( ?, ?, ? )",
+					id, url, title, credentials
+				) VALUES ( ?, ?, ?, ? )",
 				feed_id,
 				feed_url,
 				feed.title,
+				credentials,
 			)
 			.execute(&self.pool)
 			.await?;
 		Ok(())
 	}
 
+	#[tracing::instrument]
+	async fn add_scraped_feed(&self, feed: &Feed, selectors: &ScrapeSelectors) -> Result<()> {
+		let feed_id = feed.id.to_string();
+		let feed_url = feed.url.to_string();
+		let credentials = feed.credentials.as_ref().map(|credentials| &credentials.0);
+		let mut tx = self.pool.begin().await?;
+		sqlx::query!("
+				INSERT INTO feeds (
+					id, url, title, credentials
+				) VALUES ( ?, ?, ?, ? )",
+				feed_id,
+				feed_url,
+				feed.title,
+				credentials,
+			)
+			.execute(&mut *tx)
+			.await?;
+		sqlx::query!("
+				INSERT INTO scrape_selectors (
+					feed_id, item_selector, title_selector, link_selector, date_selector
+				) VALUES ( ?, ?, ?, ?, ? )",
+				feed_id,
+				selectors.item,
+				selectors.title,
+				selectors.link,
+				selectors.date,
+			)
+			.execute(&mut *tx)
+			.await?;
+		tx.commit().await?;
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn get_scrape_selectors(&self, feed_id: &FeedId) -> Result<Option<ScrapeSelectors>> {
+		let feed_id = feed_id.to_string();
+		let row = sqlx::query!("
+				SELECT
+					item_selector, title_selector, link_selector, date_selector
+				FROM scrape_selectors
+				WHERE feed_id = ?;",
+				feed_id,
+			)
+			.fetch_optional(&self.pool)
+			.await?;
+		Ok(row.map(|row| ScrapeSelectors {
+			item: row.item_selector,
+			title: row.title_selector,
+			link: row.link_selector,
+			date: row.date_selector,
+		} ) )
+	}
+
 	#[tracing::instrument]
 	async fn get_feeds(&self) -> Vec<Result<Feed>> {
 		// TODO: Maybe do paging later. Or figure out how to stream from sqlx.
 		let rows = sqlx::query!("
 				SELECT
-					id, url, title
+					id, url, title, credentials
 				FROM feeds;"
 			)
 			.fetch_all(&self.pool)
@@ -45,6 +100,7 @@ impl RussetFeedPersistenceLayer for SqlDatabase {
 							id,
 							title: row.title,
 							url,
+							credentials: row.credentials.map(EncryptedCredentials),
 						} )
 					} )
 					.collect()
@@ -59,7 +115,7 @@ impl RussetFeedPersistenceLayer for SqlDatabase {
 		let feed_id = id.to_string();
 		let row = sqlx::query!("
 				SELECT
-					url, title
+					url, title, credentials
 				FROM feeds
 				WHERE id = ?;",
 				feed_id,
@@ -69,7 +125,8 @@ impl RussetFeedPersistenceLayer for SqlDatabase {
 		let id = FeedId(id.0.clone());
 		let url = Url::parse(&row.url)?;
 		let title = row.title;
-		Ok(Feed { id, url, title })
+		let credentials = row.credentials.map(EncryptedCredentials);
+		Ok(Feed { id, url, title, credentials })
 	}
 
 	#[tracing::instrument]
@@ -77,9 +134,11 @@ impl RussetFeedPersistenceLayer for SqlDatabase {
 		let feed_url = url.to_string();
 		let row_result = sqlx::query!("
 				SELECT
-					id, url, title
+					id, url, title, credentials
 				FROM feeds
-				WHERE url = ?;",
+				WHERE url = ?
+					AND credentials IS NULL
+					AND id NOT IN (SELECT feed_id FROM scrape_selectors);",
 				feed_url)
 			.fetch_one(&self.pool)
 			.await;
@@ -88,19 +147,155 @@ impl RussetFeedPersistenceLayer for SqlDatabase {
 				let id = FeedId(Ulid::from_string(&row.id)?);
 				let url = Url::parse(&row.url)?;
 				let title = row.title;
-				Ok(Some(Feed { id, url, title }))
+				let credentials = row.credentials.map(EncryptedCredentials);
+				Ok(Some(Feed { id, url, title, credentials }))
 			},
 			Err(sqlx::Error::RowNotFound) => Ok(None),
 			Err(e) => Err(Box::new(e)),
 		}
 	}
 
+	#[tracing::instrument]
+	async fn get_subscriptions(
+		&self,
+		user_id: &UserId,
+	) -> impl IntoIterator<Item = Result<(Feed, Option<String>)>> {
+		let user_id = user_id.to_string();
+		let rows = sqlx::query!("
+				SELECT
+					f.id, f.url, f.title, f.credentials, s.folder
+				FROM feeds AS f
+				INNER JOIN subscriptions AS s
+					ON f.id = s.feed_id
+				WHERE s.user_id = ?
+				ORDER BY s.folder, f.title;",
+				user_id,
+			)
+			.fetch_all(&self.pool)
+			.await;
+		let rv: Vec<Result<(Feed, Option<String>)>> = match rows {
+			Ok(rows) => {
+				rows.into_iter()
+					.map(|row| {
+						let id = FeedId(Ulid::from_string(&row.id)?);
+						let url = Url::parse(&row.url)?;
+						let feed = Feed {
+							id,
+							title: row.title,
+							url,
+							credentials: row.credentials.map(EncryptedCredentials),
+						};
+						Ok((feed, row.folder))
+					} )
+					.collect()
+			},
+			Err(e) => vec![Err(Box::new(e))],
+		};
+		rv
+	}
+
+	#[tracing::instrument]
+	async fn get_subscription_stats(
+		&self,
+		user_id: &UserId,
+		recent_since: &Timestamp,
+	) -> impl IntoIterator<Item = Result<SubscriptionStats>> {
+		let user_id = user_id.to_string();
+		let recent_since: i64 = match (*recent_since).try_into() {
+			Ok(recent_since) => recent_since,
+			Err(e) => return vec![Err(e)],
+		};
+		// Each subquery is per-feed and served by an index on `feed_id`, so
+		// this stays cheap however many feeds other users have.
+		let rows = sqlx::query!(r#"
+				SELECT
+					f.id, f.url, f.title, f.credentials, s.folder,
+					(
+						SELECT COUNT(*)
+						FROM entries AS e
+						LEFT OUTER JOIN user_entry_settings AS u
+							ON e.id = u.entry_id AND u.user_id = s.user_id
+						WHERE e.feed_id = f.id
+							AND u.read IS NULL
+							AND u.tombstone IS NULL
+					) AS "unread_count!: i64",
+					(
+						SELECT COUNT(*)
+						FROM entries AS e
+						WHERE e.feed_id = f.id
+							AND e.article_date >= ?
+					) AS "recent_entries!: i64",
+					(
+						SELECT MAX(c.check_time)
+						FROM feed_checks AS c
+						WHERE c.feed_id = f.id
+							AND c.error IS NULL
+					) AS "last_success?: i64",
+					c.id AS "check_id?: i64",
+					c.check_time AS "check_time?: i64",
+					c.next_check_time AS "next_check_time?: i64",
+					c.etag,
+					c.error
+				FROM feeds AS f
+				INNER JOIN subscriptions AS s
+					ON f.id = s.feed_id
+				LEFT OUTER JOIN feed_checks AS c
+					ON c.id = (
+						SELECT MAX(id)
+						FROM feed_checks
+						WHERE feed_id = f.id
+					)
+				WHERE s.user_id = ?
+				ORDER BY f.title;"#,
+				recent_since,
+				user_id,
+			)
+			.fetch_all(&self.pool)
+			.await;
+		let rv: Vec<Result<SubscriptionStats>> = match rows {
+			Ok(rows) => {
+				rows.into_iter()
+					.map(|row| {
+						let id = FeedId(Ulid::from_string(&row.id)?);
+						let url = Url::parse(&row.url)?;
+						let last_check = match (row.check_id, row.check_time, row.next_check_time) {
+							(Some(check_id), Some(check_time), Some(next_check_time)) => Some(FeedCheck {
+								id: check_id.try_into()?,
+								feed_id: id,
+								check_time: check_time.into(),
+								next_check_time: next_check_time.into(),
+								etag: row.etag,
+								error: row.error,
+							} ),
+							_ => None,
+						};
+						Ok(SubscriptionStats {
+							feed: Feed {
+								id,
+								title: row.title,
+								url,
+								credentials: row.credentials.map(EncryptedCredentials),
+							},
+							folder: row.folder,
+							unread_count: row.unread_count.try_into()?,
+							recent_entries: row.recent_entries.try_into()?,
+							last_success: row.last_success.map(Timestamp::from),
+							last_check,
+						} )
+					} )
+					.collect()
+			},
+			Err(e) => vec![Err(Box::new(e))],
+		};
+		rv
+	}
+
 	#[tracing::instrument]
 	async fn get_subscribed_feeds(&self, user_id: &UserId) -> Vec<Result<Feed>> {
 		let user_id = user_id.to_string();
 		let rows = sqlx::query!("
 				SELECT
-					f.id, f.url, f.title
+					f.id, f.url, f.title, f.credentials
 				FROM feeds AS f
 				INNER JOIN subscriptions AS s
 					ON f.id = s.feed_id
@@ -119,6 +314,7 @@ impl RussetFeedPersistenceLayer for SqlDatabase {
 							id,
 							title: row.title,
 							url,
+							credentials: row.credentials.map(EncryptedCredentials),
 						} )
 					} )
 					.collect()
@@ -145,13 +341,14 @@ impl RussetFeedPersistenceLayer for SqlDatabase {
 		let next_check_time: i64 = feed_check.next_check_time.try_into()?;
 		sqlx::query!("
 				INSERT INTO feed_checks (
-					id, feed_id, check_time, next_check_time, etag
-				) VALUES ( ?, ?, ?, ?, ? )",
+					id, feed_id, check_time, next_check_time, etag, error
+				) VALUES ( ?, ?, ?, ?, ?, ? )",
 				next_fetch_index,
 				feed_id,
 				check_time,
 				next_check_time,
 				feed_check.etag,
+				feed_check.error,
 			)
 			.execute(&mut *tx)
 			.await?;
@@ -176,7 +373,7 @@ impl RussetFeedPersistenceLayer for SqlDatabase {
 		};
 		let rows = sqlx::query!("
 				SELECT
-					id, feed_id, check_time, next_check_time, etag
+					id, feed_id, check_time, next_check_time, etag, error
 				FROM feed_checks
 				WHERE feed_id = ?
 				ORDER BY id DESC
@@ -199,6 +396,7 @@ impl RussetFeedPersistenceLayer for SqlDatabase {
 						check_time: row.check_time.try_into()?,
 						next_check_time: row.next_check_time.try_into()?,
 						etag: row.etag,
+						error: row.error,
 					} )
 				} )
 					.collect()
@@ -207,4 +405,136 @@ impl RussetFeedPersistenceLayer for SqlDatabase {
 		};
 		rv
 	}
+
+	#[tracing::instrument]
+	async fn set_websub_subscription(&self, subscription: &WebSubSubscription) -> Result<()> {
+		let feed_id = subscription.feed_id.to_string();
+		let hub_url = subscription.hub.to_string();
+		let topic_url = subscription.topic.to_string();
+		let lease_expiration: Option<i64> = subscription.lease_expiration
+			.map(|expiration| expiration.try_into())
+			.transpose()?;
+		let pending_since: Option<i64> = subscription.pending_since
+			.map(|pending_since| pending_since.try_into())
+			.transpose()?;
+		sqlx::query!("
+				INSERT INTO websub_subscriptions (
+					feed_id, hub_url, topic_url, secret, lease_expiration, pending_since
+				) VALUES ( ?, ?, ?, ?, ?, ? )
+				ON CONFLICT (feed_id)
+				DO UPDATE SET
+					hub_url = excluded.hub_url,
+					topic_url = excluded.topic_url,
+					secret = excluded.secret,
+					lease_expiration = excluded.lease_expiration,
+					pending_since = excluded.pending_since;",
+				feed_id,
+				hub_url,
+				topic_url,
+				subscription.secret.0,
+				lease_expiration,
+				pending_since,
+			)
+			.execute(&self.pool)
+			.await?;
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn get_websub_subscription(&self, feed_id: &FeedId) -> Result<Option<WebSubSubscription>> {
+		let feed_id_str = feed_id.to_string();
+		let row = sqlx::query!("
+				SELECT
+					hub_url, topic_url, secret, lease_expiration, pending_since
+				FROM websub_subscriptions
+				WHERE feed_id = ?;",
+				feed_id_str,
+			)
+			.fetch_optional(&self.pool)
+			.await?;
+		row.map(|row| Ok(WebSubSubscription {
+				feed_id: *feed_id,
+				hub: Url::parse(&row.hub_url)?,
+				topic: Url::parse(&row.topic_url)?,
+				secret: HubSecret(row.secret),
+				lease_expiration: row.lease_expiration.map(|expiration| expiration.into()),
+				pending_since: row.pending_since.map(|pending_since| pending_since.into()),
+			} ) )
+			.transpose()
+	}
+
+	#[tracing::instrument]
+	async fn get_expiring_websub_subscriptions(&self, before: &Timestamp) -> impl IntoIterator<Item = Result<WebSubSubscription>> {
+		let before: i64 = match (*before).try_into() {
+			Ok(before) => before,
+			Err(e) => return vec![Err(e)],
+		};
+		let rows = sqlx::query!("
+				SELECT
+					feed_id, hub_url, topic_url, secret, lease_expiration, pending_since
+				FROM websub_subscriptions
+				WHERE lease_expiration < ?;",
+				before,
+			)
+			.fetch_all(&self.pool)
+			.await;
+		match rows {
+			Ok(rows) => {
+				rows.into_iter().map(|row| {
+					Ok(WebSubSubscription {
+						feed_id: FeedId(Ulid::from_string(&row.feed_id)?),
+						hub: Url::parse(&row.hub_url)?,
+						topic: Url::parse(&row.topic_url)?,
+						secret: HubSecret(row.secret),
+						lease_expiration: row.lease_expiration.map(|expiration| expiration.into()),
+						pending_since: row.pending_since.map(|pending_since| pending_since.into()),
+					} )
+				} )
+					.collect()
+			},
+			Err(e) => vec![Err(Box::new(e))],
+		}
+	}
+
+	#[tracing::instrument]
+	async fn delete_websub_subscription(&self, feed_id: &FeedId) -> Result<()> {
+		let feed_id = feed_id.to_string();
+		sqlx::query!("
+				DELETE FROM websub_subscriptions
+				WHERE feed_id = ?;",
+				feed_id,
+			)
+			.execute(&self.pool)
+			.await?;
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn get_feed_retention(&self, feed_id: &FeedId) -> Result<Option<u32>> {
+		let feed_id = feed_id.to_string();
+		let row = sqlx::query!("
+				SELECT retention_days
+				FROM feeds
+				WHERE id = ?;",
+				feed_id,
+			)
+			.fetch_one(&self.pool)
+			.await?;
+		Ok(row.retention_days.map(u32::try_from).transpose()?)
+	}
+
+	#[tracing::instrument]
+	async fn set_feed_retention(&self, feed_id: &FeedId, retention_days: Option<u32>) -> Result<()> {
+		let feed_id = feed_id.to_string();
+		sqlx::query!("
+				UPDATE feeds
+				SET retention_days = ?
+				WHERE id = ?;",
+				retention_days,
+				feed_id,
+			)
+			.execute(&self.pool)
+			.await?;
+		Ok(())
+	}
 }
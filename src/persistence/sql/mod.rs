@@ -14,6 +14,7 @@ use std::time::{ Duration, SystemTime };
 #[derive(Debug)]
 pub struct SqlDatabase {
 	pool: Pool<Sqlite>,
+	listing_counts: entry::ListingCountCache,
 }
 impl SqlDatabase {
 	pub async fn new(db_path: &Path) -> Result<SqlDatabase> {
@@ -24,7 +25,7 @@ impl SqlDatabase {
 		// connetion reduces throughput, but should be more durable.
 		let pool = PoolOptions::<Sqlite>::new().max_connections(1).connect(path).await?;
 		sqlx::migrate!("db/migrations/").run(&pool).await?;
-		Ok(SqlDatabase { pool })
+		Ok(SqlDatabase { pool, listing_counts: Default::default() })
 	}
 }
 
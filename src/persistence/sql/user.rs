@@ -1,8 +1,10 @@
-use crate::model::{ FeedId, Timestamp, UserId };
-use crate::persistence::model::{ PasswordHash, Session, SessionToken, User };
+use chrono_tz::Tz;
+use crate::model::{ DateDisplay, FeedId, InvitationId, ListingOptions, Theme, Timestamp, UserId };
+use crate::persistence::model::{ Invitation, PasswordHash, Session, SessionClient, SessionToken, Totp, User, UserPreferences };
 use crate::persistence::RussetUserPersistenceLayer;
 use crate::persistence::sql::SqlDatabase;
 use crate::Result;
+use sqlx::{ Sqlite, Transaction };
 use ulid::Ulid;
 
 impl RussetUserPersistenceLayer for SqlDatabase {
@@ -12,7 +14,7 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 		let user_id = user_id.to_string();
 		let row = sqlx::query!("
 				SELECT
-					id, name, password_hash, user_type
+					id, name, password_hash, user_type, disabled
 				FROM users
 				WHERE id = ?;",
 				user_id)
@@ -25,9 +27,38 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 			name: row.name,
 			password_hash,
 			user_type: row.user_type.try_into()?,
+			disabled: row.disabled,
 		} )
 	}
 
+	#[tracing::instrument]
+	async fn get_users(&self) -> Result<Vec<(User, u32)>> {
+		let rows = sqlx::query!("
+				SELECT
+					u.id, u.name, u.password_hash, u.user_type, u.disabled,
+					COUNT(s.token) AS \"session_count: u32\"
+				FROM users AS u
+				LEFT JOIN sessions AS s
+				ON u.id = s.user_id
+				GROUP BY u.id
+				ORDER BY u.name;",
+			)
+			.fetch_all(&self.pool)
+			.await?;
+		rows.into_iter()
+			.map(|row| Ok((
+				User {
+					id: UserId(Ulid::from_string(&row.id)?),
+					name: row.name,
+					password_hash: PasswordHash(row.password_hash),
+					user_type: row.user_type.try_into()?,
+					disabled: row.disabled,
+				},
+				row.session_count,
+			) ) )
+			.collect()
+	}
+
 	#[tracing::instrument]
 	async fn add_user(&self, user: &User) -> Result<()> {
 		let user_id = user.id.to_string();
@@ -35,12 +66,13 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 		let user_type: String = user.user_type.into();
 		sqlx::query!("
 				INSERT INTO users (
-					id, name, password_hash, user_type
-				) VALUES ( ?, ?, ?, ? );",
+					id, name, password_hash, user_type, disabled
+				) VALUES ( ?, ?, ?, ?, ? );",
 				user_id,
 				user.name,
 				password_hash,
 				user_type,
+				user.disabled,
 			)
 			.execute(&self.pool)
 			.await?;
@@ -51,13 +83,18 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 	async fn update_user(&self, user: &User) -> Result<()> {
 		let user_id = user.id.to_string();
 		let password_hash = &user.password_hash.0;
+		let user_type: String = user.user_type.into();
 		sqlx::query!("
 				UPDATE users SET
 					name = ?,
-					password_hash = ?
+					password_hash = ?,
+					user_type = ?,
+					disabled = ?
 				WHERE id = ?;",
 				user.name,
 				password_hash,
+				user_type,
+				user.disabled,
 				user_id,
 			)
 			.execute(&self.pool)
@@ -73,6 +110,18 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 				WHERE user_id = ?;
 				DELETE FROM user_entry_settings
 				WHERE user_id = ?;
+				DELETE FROM entry_labels
+				WHERE user_id = ?;
+				DELETE FROM rules
+				WHERE user_id = ?;
+				DELETE FROM user_preferences
+				WHERE user_id = ?;
+				DELETE FROM user_totp
+				WHERE user_id = ?;
+				DELETE FROM recovery_codes
+				WHERE user_id = ?;
+				DELETE FROM invitations
+				WHERE created_by = ?;
 				DELETE FROM subscriptions
 				WHERE user_id = ?;
 				DELETE FROM users
@@ -81,6 +130,88 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 				user_id,
 				user_id,
 				user_id,
+				user_id,
+				user_id,
+				user_id,
+				user_id,
+				user_id,
+				user_id,
+			)
+			.execute(&self.pool)
+			.await?;
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn get_user_preferences(&self, user_id: &UserId) -> Result<Option<UserPreferences>> {
+		let user_id = user_id.to_string();
+		let row = sqlx::query!("
+				SELECT
+					unread_only, oldest_first, entry_sort, page_size,
+					timezone, date_format, date_locale, recent_days, theme
+				FROM user_preferences
+				WHERE user_id = ?;",
+				user_id,
+			)
+			.fetch_optional(&self.pool)
+			.await?;
+		match row {
+			Some(row) => Ok(Some(UserPreferences {
+				listing: ListingOptions {
+					unread_only: row.unread_only != 0,
+					oldest_first: row.oldest_first != 0,
+					sort: row.entry_sort.try_into()?,
+				},
+				page_size: row.page_size.try_into()?,
+				dates: DateDisplay {
+					timezone: row.timezone
+						.parse::<Tz>()
+						.map_err(|_| format!("Unrecognized timezone {:?}", row.timezone))?,
+					format: row.date_format.try_into()?,
+					locale: row.date_locale.try_into()?,
+					recent_days: row.recent_days.try_into()?,
+				},
+				theme: Theme::try_from(row.theme)?,
+			} ) ),
+			None => Ok(None),
+		}
+	}
+
+	#[tracing::instrument]
+	async fn set_user_preferences(&self, user_id: &UserId, preferences: &UserPreferences) -> Result<()> {
+		let user_id = user_id.to_string();
+		let entry_sort = preferences.listing.sort.as_str();
+		let page_size: i64 = preferences.page_size.try_into()?;
+		let timezone = preferences.dates.timezone.name();
+		let date_format = preferences.dates.format.as_str();
+		let date_locale = preferences.dates.locale.as_str();
+		let theme = preferences.theme.as_str();
+		sqlx::query!("
+				INSERT INTO user_preferences (
+					user_id, unread_only, oldest_first, entry_sort, page_size,
+					timezone, date_format, date_locale, recent_days, theme
+				) VALUES ( ?, ?, ?, ?, ?, ?, ?, ?, ?, ? )
+				ON CONFLICT (user_id)
+				DO UPDATE SET
+					unread_only = excluded.unread_only,
+					oldest_first = excluded.oldest_first,
+					entry_sort = excluded.entry_sort,
+					page_size = excluded.page_size,
+					timezone = excluded.timezone,
+					date_format = excluded.date_format,
+					date_locale = excluded.date_locale,
+					recent_days = excluded.recent_days,
+					theme = excluded.theme;",
+				user_id,
+				preferences.listing.unread_only,
+				preferences.listing.oldest_first,
+				entry_sort,
+				page_size,
+				timezone,
+				date_format,
+				date_locale,
+				preferences.dates.recent_days,
+				theme,
 			)
 			.execute(&self.pool)
 			.await?;
@@ -91,7 +222,7 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 	async fn get_user_by_name(&self, user_name: &str) -> Result<Option<User>> {
 		let row_result = sqlx::query!("
 				SELECT
-					id, name, password_hash, user_type
+					id, name, password_hash, user_type, disabled
 				FROM users
 				WHERE name = ?;",
 				user_name)
@@ -106,6 +237,7 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 					name: row.name,
 					password_hash,
 					user_type: row.user_type.try_into()?,
+					disabled: row.disabled,
 				} ) )
 			},
 			Err(sqlx::Error::RowNotFound) => Ok(None),
@@ -117,13 +249,19 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 	async fn add_session(&self, session: &Session) -> Result<()> {
 		let user_id = session.user_id.to_string();
 		let expiration: i64 = session.expiration.clone().try_into()?;
+		let created: Option<i64> = session.created.map(|created| created.try_into()).transpose()?;
+		let last_used: Option<i64> = session.last_used.map(|last_used| last_used.try_into()).transpose()?;
 		sqlx::query!("
 				INSERT INTO sessions (
-					token, user_id, expiration
-				) VALUES ( ?, ?, ? )",
+					token, user_id, expiration, created, last_used, ip, user_agent
+				) VALUES ( ?, ?, ?, ?, ?, ?, ? )",
 				session.token.0,
 				user_id,
 				expiration,
+				created,
+				last_used,
+				session.client.ip,
+				session.client.user_agent,
 			)
 			.execute(&self.pool)
 			.await?;
@@ -134,8 +272,9 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 	async fn get_user_by_session(&self, session_token: &str) -> Result<Option<(User, Session)>> {
 		let row_result = sqlx::query!("
 				SELECT
-					users.id, users.name, users.password_hash, users.user_type,
-					sessions.expiration
+					users.id, users.name, users.password_hash, users.user_type, users.disabled,
+					sessions.expiration, sessions.created, sessions.last_used,
+					sessions.ip, sessions.user_agent
 				FROM users
 				JOIN sessions
 				ON users.id = sessions.user_id
@@ -153,11 +292,15 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 						name: row.name,
 						password_hash,
 						user_type: row.user_type.try_into()?,
+						disabled: row.disabled,
 					},
 					Session {
 						token: SessionToken(session_token.to_string()),
 						user_id,
 						expiration: row.expiration.into(),
+						created: row.created.map(|created| created.into()),
+						last_used: row.last_used.map(|last_used| last_used.into()),
+						client: SessionClient { ip: row.ip, user_agent: row.user_agent },
 					}
 				) ) )
 			},
@@ -166,6 +309,50 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 		}
 	}
 
+	#[tracing::instrument]
+	async fn get_sessions_for_user(&self, user_id: &UserId) -> Result<Vec<Session>> {
+		let user_id_string = user_id.to_string();
+		let rows = sqlx::query!("
+				SELECT
+					token, expiration, created, last_used, ip, user_agent
+				FROM sessions
+				WHERE user_id = ?
+				ORDER BY last_used DESC;",
+				user_id_string,
+			)
+			.fetch_all(&self.pool)
+			.await?;
+		Ok(rows.into_iter()
+			.map(|row| Session {
+				token: SessionToken(row.token),
+				user_id: *user_id,
+				expiration: row.expiration.into(),
+				created: row.created.map(|created| created.into()),
+				last_used: row.last_used.map(|last_used| last_used.into()),
+				client: SessionClient { ip: row.ip, user_agent: row.user_agent },
+			} )
+			.collect())
+	}
+
+	#[tracing::instrument]
+	async fn touch_session(&self, session_token: &str, last_used: &Timestamp, client: &SessionClient) -> Result<()> {
+		let last_used: i64 = (*last_used).try_into()?;
+		sqlx::query!("
+				UPDATE sessions SET
+					last_used = ?,
+					ip = ?,
+					user_agent = ?
+				WHERE token = ?;",
+				last_used,
+				client.ip,
+				client.user_agent,
+				session_token,
+			)
+			.execute(&self.pool)
+			.await?;
+		Ok(())
+	}
+
 	#[tracing::instrument]
 	async fn delete_session(&self, session_token: &str) -> Result<()> {
 		let rows = sqlx::query!("
@@ -212,19 +399,274 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 		Ok(rows)
 	}
 
+	#[tracing::instrument]
+	async fn delete_other_sessions_for_user(&self, user_id: &UserId, session_token: &str) -> Result<u32> {
+		let user_id = user_id.to_string();
+		let rows = sqlx::query!("
+				DELETE FROM sessions
+				WHERE user_id = ? AND token != ?;",
+				user_id,
+				session_token,
+			)
+			.execute(&self.pool)
+			.await?
+			.rows_affected()
+			.try_into()?;
+		Ok(rows)
+	}
+
+	#[tracing::instrument]
+	async fn get_totp(&self, user_id: &UserId) -> Result<Option<Totp>> {
+		let user_id = user_id.to_string();
+		let row = sqlx::query!("
+				SELECT secret, last_step
+				FROM user_totp
+				WHERE user_id = ?;",
+				user_id,
+			)
+			.fetch_optional(&self.pool)
+			.await?;
+		row.map(|row| Ok(Totp { secret: row.secret, last_step: row.last_step.try_into()? }))
+			.transpose()
+	}
+
+	#[tracing::instrument(skip(recovery_codes))]
+	async fn set_totp(&self, user_id: &UserId, totp: &Totp, recovery_codes: &[PasswordHash]) -> Result<()> {
+		let user_id = user_id.to_string();
+		let last_step: i64 = totp.last_step.try_into()?;
+		let mut tx = self.pool.begin().await?;
+		sqlx::query!("
+				INSERT INTO user_totp (user_id, secret, last_step)
+				VALUES ( ?, ?, ? )
+				ON CONFLICT (user_id)
+				DO UPDATE SET
+					secret = excluded.secret,
+					last_step = excluded.last_step;",
+				user_id,
+				totp.secret,
+				last_step,
+			)
+			.execute(&mut *tx)
+			.await?;
+		Self::replace_recovery_codes(&mut tx, &user_id, recovery_codes).await?;
+		tx.commit().await?;
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn use_totp_step(&self, user_id: &UserId, step: u64) -> Result<bool> {
+		let user_id = user_id.to_string();
+		let step: i64 = step.try_into()?;
+		let rows = sqlx::query!("
+				UPDATE user_totp
+				SET last_step = ?
+				WHERE user_id = ? AND last_step < ?;",
+				step,
+				user_id,
+				step,
+			)
+			.execute(&self.pool)
+			.await?
+			.rows_affected();
+		Ok(rows > 0)
+	}
+
+	#[tracing::instrument]
+	async fn delete_totp(&self, user_id: &UserId) -> Result<()> {
+		let user_id = user_id.to_string();
+		sqlx::query!("
+				DELETE FROM user_totp
+				WHERE user_id = ?;
+				DELETE FROM recovery_codes
+				WHERE user_id = ?;",
+				user_id,
+				user_id,
+			)
+			.execute(&self.pool)
+			.await?;
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn get_recovery_codes(&self, user_id: &UserId) -> Result<Vec<PasswordHash>> {
+		let user_id = user_id.to_string();
+		let rows = sqlx::query!("
+				SELECT code_hash
+				FROM recovery_codes
+				WHERE user_id = ?;",
+				user_id,
+			)
+			.fetch_all(&self.pool)
+			.await?;
+		Ok(rows.into_iter().map(|row| PasswordHash(row.code_hash)).collect())
+	}
+
+	#[tracing::instrument(skip(recovery_codes))]
+	async fn set_recovery_codes(&self, user_id: &UserId, recovery_codes: &[PasswordHash]) -> Result<()> {
+		let user_id = user_id.to_string();
+		let mut tx = self.pool.begin().await?;
+		Self::replace_recovery_codes(&mut tx, &user_id, recovery_codes).await?;
+		tx.commit().await?;
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn delete_recovery_code(&self, user_id: &UserId, recovery_code: &PasswordHash) -> Result<bool> {
+		let user_id = user_id.to_string();
+		let rows = sqlx::query!("
+				DELETE FROM recovery_codes
+				WHERE user_id = ? AND code_hash = ?;",
+				user_id,
+				recovery_code.0,
+			)
+			.execute(&self.pool)
+			.await?
+			.rows_affected();
+		Ok(rows > 0)
+	}
+
+	#[tracing::instrument]
+	async fn add_invitation(&self, invitation: &Invitation) -> Result<()> {
+		let id = invitation.id.to_string();
+		let user_type: String = invitation.user_type.into();
+		let created_by = invitation.created_by.to_string();
+		let created: i64 = invitation.created.try_into()?;
+		let expiration: Option<i64> = invitation.expiration
+			.map(|expiration| expiration.try_into())
+			.transpose()?;
+		sqlx::query!("
+				INSERT INTO invitations (
+					id, token_hash, user_type, created_by, created, expiration
+				) VALUES ( ?, ?, ?, ?, ?, ? );",
+				id,
+				invitation.token_hash,
+				user_type,
+				created_by,
+				created,
+				expiration,
+			)
+			.execute(&self.pool)
+			.await?;
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn get_invitations(&self) -> Result<Vec<(Invitation, String)>> {
+		let rows = sqlx::query!("
+				SELECT
+					i.id, i.token_hash, i.user_type, i.created_by, i.created, i.expiration,
+					u.name
+				FROM invitations AS i
+				INNER JOIN users AS u
+				ON i.created_by = u.id
+				ORDER BY i.created DESC;",
+			)
+			.fetch_all(&self.pool)
+			.await?;
+		rows.into_iter()
+			.map(|row| Ok((
+				Invitation {
+					id: InvitationId(Ulid::from_string(&row.id)?),
+					token_hash: row.token_hash,
+					user_type: row.user_type.try_into()?,
+					created_by: UserId(Ulid::from_string(&row.created_by)?),
+					created: row.created.into(),
+					expiration: row.expiration.map(|expiration| expiration.into()),
+				},
+				row.name,
+			) ) )
+			.collect()
+	}
+
+	#[tracing::instrument]
+	async fn get_invitation_by_token_hash(&self, token_hash: &str) -> Result<Option<Invitation>> {
+		let row = sqlx::query!("
+				SELECT
+					id, token_hash, user_type, created_by, created, expiration
+				FROM invitations
+				WHERE token_hash = ?;",
+				token_hash,
+			)
+			.fetch_optional(&self.pool)
+			.await?;
+		row.map(|row| Ok(Invitation {
+				id: InvitationId(Ulid::from_string(&row.id)?),
+				token_hash: row.token_hash,
+				user_type: row.user_type.try_into()?,
+				created_by: UserId(Ulid::from_string(&row.created_by)?),
+				created: row.created.into(),
+				expiration: row.expiration.map(|expiration| expiration.into()),
+			} ) )
+			.transpose()
+	}
+
+	#[tracing::instrument]
+	async fn delete_invitation(&self, invitation_id: &InvitationId) -> Result<bool> {
+		let invitation_id = invitation_id.to_string();
+		let rows = sqlx::query!("
+				DELETE FROM invitations
+				WHERE id = ?;",
+				invitation_id,
+			)
+			.execute(&self.pool)
+			.await?
+			.rows_affected();
+		Ok(rows > 0)
+	}
+
+	#[tracing::instrument]
+	async fn delete_expired_invitations(&self, expiry: &Timestamp) -> Result<u32> {
+		let expiry: i64 = (*expiry).try_into()?;
+		let rows = sqlx::query!("
+				DELETE FROM invitations
+				WHERE expiration < ?;",
+				expiry,
+			)
+			.execute(&self.pool)
+			.await?
+			.rows_affected()
+			.try_into()?;
+		Ok(rows)
+	}
+
 	#[tracing::instrument]
 	async fn add_subscription(&self, user_id: &UserId, feed_id: &FeedId) -> Result<()> {
 		let feed_id = feed_id.to_string();
 		let user_id = user_id.to_string();
+		let mut tx = self.pool.begin().await?;
 		sqlx::query!("
 				INSERT INTO subscriptions(
 					user_id, feed_id
-				) VALUES ( ?, ? )",
+				) VALUES ( ?, ? )
+				ON CONFLICT DO NOTHING",
 				user_id,
 				feed_id,
 			)
-			.execute(&self.pool)
+			.execute(&mut *tx)
 			.await?;
+		// Entries duplicating ones the user has already read elsewhere start
+		// out read
+		sqlx::query!("
+				INSERT INTO user_entry_settings (user_id, entry_id, read)
+				SELECT u.user_id, e.id, MIN(u.read)
+				FROM entries AS e
+				INNER JOIN entries AS g
+					ON COALESCE(g.duplicate_of, g.id) = COALESCE(e.duplicate_of, e.id)
+				INNER JOIN user_entry_settings AS u
+					ON g.id = u.entry_id
+				WHERE e.feed_id = ?
+					AND u.user_id = ?
+					AND g.id != e.id
+					AND u.read IS NOT NULL
+				GROUP BY u.user_id, e.id
+				ON CONFLICT DO NOTHING;",
+				feed_id,
+				user_id,
+			)
+			.execute(&mut *tx)
+			.await?;
+		tx.commit().await?;
+		self.listing_counts.forget_user(&user_id);
 		Ok(())
 	}
 
@@ -241,6 +683,56 @@ impl RussetUserPersistenceLayer for SqlDatabase {
 			)
 			.execute(&self.pool)
 			.await?;
+		self.listing_counts.forget_user(&user_id);
+		Ok(())
+	}
+
+	#[tracing::instrument]
+	async fn set_subscription_folder(
+		&self,
+		user_id: &UserId,
+		feed_id: &FeedId,
+		folder: Option<&str>,
+	) -> Result<()> {
+		let feed_id = feed_id.to_string();
+		let user_id = user_id.to_string();
+		sqlx::query!("
+				UPDATE subscriptions
+				SET folder = ?
+				WHERE user_id = ? AND feed_id = ?",
+				folder,
+				user_id,
+				feed_id,
+			)
+			.execute(&self.pool)
+			.await?;
+		self.listing_counts.forget_user(&user_id);
+		Ok(())
+	}
+}
+impl SqlDatabase {
+	async fn replace_recovery_codes(
+		tx: &mut Transaction<'_, Sqlite>,
+		user_id: &str,
+		recovery_codes: &[PasswordHash],
+	) -> Result<()> {
+		sqlx::query!("
+				DELETE FROM recovery_codes
+				WHERE user_id = ?;",
+				user_id,
+			)
+			.execute(&mut **tx)
+			.await?;
+		for recovery_code in recovery_codes {
+			sqlx::query!("
+					INSERT INTO recovery_codes (user_id, code_hash)
+					VALUES ( ?, ? );",
+					user_id,
+					recovery_code.0,
+				)
+				.execute(&mut **tx)
+				.await?;
+		}
 		Ok(())
 	}
 }
@@ -1,8 +1,9 @@
 use crate::Result;
 use crate::domain::RussetDomainService;
-use crate::http::{ AppState, russet_router };
+use crate::http::{ AppState, ProxyAuth, russet_router };
 use crate::model::{ FeedId, Timestamp };
 use crate::persistence::RussetPersistenceLayer;
+use std::net::{ IpAddr, SocketAddr };
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::{ error, info };
@@ -11,6 +12,8 @@ use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
 const SESSION_CLEANUP_INTERVAL: Duration = Duration::from_secs(3_600);
+const WEBSUB_RENEWAL_INTERVAL: Duration = Duration::from_secs(3_600);
+const ENTRY_PRUNING_INTERVAL: Duration = Duration::from_secs(86_400);
 
 /// Start the Russet server.
 ///
@@ -22,6 +25,9 @@ pub async fn start<Persistence>(
 	listen: String,
 	global_concurrent_limit: u32,
 	login_concurrent_limit: u32,
+	secure_cookies: bool,
+	trusted_proxies: Vec<IpAddr>,
+	proxy_auth: Option<ProxyAuth>,
 ) -> Result<()>
 where Persistence: RussetPersistenceLayer {
 	info!("Starting {}…", crate::APP_NAME);
@@ -43,9 +49,19 @@ where Persistence: RussetPersistenceLayer {
 	// Start the expired session cleanup coroutine
 	tasks.push(session_cleanup(domain_service.clone(), task_tracker.clone()).await);
 
+	// Start the old entry pruning coroutine
+	tasks.push(entry_pruning(domain_service.clone(), task_tracker.clone()).await);
+
+	// Start the WebSub lease renewal coroutine
+	tasks.push(websub_renewal(domain_service.clone(), task_tracker.clone()).await);
+
 	// Start the HTTP server
-	let app_state = AppState { domain_service: domain_service.clone() };
-	let routes = russet_router(global_concurrent_limit, login_concurrent_limit)
+	let app_state = AppState {
+		domain_service: domain_service.clone(),
+		secure_cookies,
+		proxy_auth: proxy_auth.map(Arc::new),
+	};
+	let routes = russet_router(global_concurrent_limit, login_concurrent_limit, trusted_proxies)
 		.with_state(app_state);
 	let listener = tokio::net::TcpListener::bind(&listen).await?;
 	let graceful_exit_signal = async {
@@ -54,7 +70,7 @@ where Persistence: RussetPersistenceLayer {
 	};
 	info!("Initialization complete, serving requests!");
 	info!("Listening on {listen}…");
-	axum::serve(listener, routes)
+	axum::serve(listener, routes.into_make_service_with_connect_info::<SocketAddr>())
 		.with_graceful_shutdown(graceful_exit_signal)
 		.await?;
 
@@ -156,6 +172,10 @@ where Persistence: RussetPersistenceLayer {
 			if let Err(e) = domain_service.cleanup_expired_sessions().await {
 				error!(error = e.as_ref(), "Error removing expired sessions");
 			}
+			if let Err(e) = domain_service.cleanup_expired_invitations().await {
+				error!(error = e.as_ref(), "Error removing expired invitations");
+			}
+			domain_service.prune_login_attempts();
 			if let WaitResult::Cancellation = wait_until(
 				Timestamp::now() + SESSION_CLEANUP_INTERVAL,
 				&captured_token,
@@ -167,6 +187,68 @@ where Persistence: RussetPersistenceLayer {
 	token
 }
 
+/// Schedule a coroutine to prune old, read entries from the persistence layer.
+///
+/// The returned [CancellationToken] can be used to cancel the coroutine, and
+/// the corouting will be registered with [task_tracker] so its exit can be
+/// joined on.
+async fn entry_pruning<Persistence>(
+	domain_service: Arc<RussetDomainService<Persistence>>,
+	task_tracker: TaskTracker,
+) -> CancellationToken
+where Persistence: RussetPersistenceLayer {
+	let token = CancellationToken::new();
+	let captured_token = token.clone();
+	task_tracker.spawn(async move {
+		loop {
+			info!("Pruning old entries");
+			match domain_service.prune_entries(false).await {
+				Ok(pruned) => {
+					let total: u64 = pruned.iter().map(|(_, count)| count).sum();
+					info!(feeds = pruned.len(), entries = total, "Pruned old entries");
+				}
+				Err(e) => error!(error = e.as_ref(), "Error pruning old entries"),
+			}
+			if let WaitResult::Cancellation = wait_until(
+				Timestamp::now() + ENTRY_PRUNING_INTERVAL,
+				&captured_token,
+			).await {
+				return
+			}
+		}
+	} );
+	token
+}
+
+/// Schedule a coroutine to renew WebSub subscriptions before their leases
+/// expire.
+///
+/// The returned [CancellationToken] can be used to cancel the coroutine, and
+/// the corouting will be registered with [task_tracker] so its exit can be
+/// joined on.
+async fn websub_renewal<Persistence>(
+	domain_service: Arc<RussetDomainService<Persistence>>,
+	task_tracker: TaskTracker,
+) -> CancellationToken
+where Persistence: RussetPersistenceLayer {
+	let token = CancellationToken::new();
+	let captured_token = token.clone();
+	task_tracker.spawn(async move {
+		loop {
+			if let Err(e) = domain_service.renew_websub_leases().await {
+				error!(error = e.as_ref(), "Error renewing WebSub leases");
+			}
+			if let WaitResult::Cancellation = wait_until(
+				Timestamp::now() + WEBSUB_RENEWAL_INTERVAL,
+				&captured_token,
+			).await {
+				return
+			}
+		}
+	} );
+	token
+}
+
 /// Wait [until] the given timestamp, or until [token] is cancelled.
 async fn wait_until(until: Timestamp, token: &CancellationToken) -> WaitResult {
 	let delay = Timestamp::until(until);